@@ -0,0 +1,77 @@
+use std::io::Cursor;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use minecraft::{
+    buffer::Buffer,
+    packets::{HandshakeC2SPacket, MinecraftPacket},
+    serialization::{read_varint, FieldWriter, MinecraftStream}
+};
+use tokio::runtime::Builder;
+
+fn handshake() -> HandshakeC2SPacket {
+    HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "play.example.com\0FML3\0".to_string(),
+        server_port: 25565,
+        next_state: 2
+    }
+}
+
+/// Values which take from 1 to 5 bytes as VarInt
+const VARINTS: [i32; 5] = [1, 300, 70_000, 10_000_000, -1];
+
+fn parse_handshake(c: &mut Criterion) {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let raw = MinecraftPacket::make_raw(0, &handshake()).unwrap();
+    let raw = raw.as_slice();
+    let mut group = c.benchmark_group("parse_handshake");
+    group.throughput(Throughput::Bytes(raw.len() as u64));
+    // the way mineginx reads it
+    group.bench_function("read_data_bounded", |b| b.to_async(&runtime).iter(|| async move {
+        let mut stream = MinecraftStream::new(Cursor::new(black_box(raw).to_vec()), 4096);
+        let signature = stream.read_signature().await.unwrap();
+        stream.read_data_bounded::<HandshakeC2SPacket>(signature).await.unwrap()
+    }));
+    group.bench_function("read_packet", |b| b.to_async(&runtime).iter(|| async move {
+        let mut stream = MinecraftStream::new(Cursor::new(black_box(raw).to_vec()), 4096);
+        stream.read_packet::<HandshakeC2SPacket>().await.unwrap()
+    }));
+    group.finish();
+}
+
+fn make_raw_handshake(c: &mut Criterion) {
+    let handshake = handshake();
+    c.bench_function("make_raw_handshake", |b| b.iter(|| MinecraftPacket::make_raw(0, black_box(&handshake)).unwrap()));
+}
+
+fn varint_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varint_encode");
+    for value in VARINTS {
+        group.throughput(Throughput::Elements(1));
+        group.bench_with_input(BenchmarkId::from_parameter(value), &value, |b, value| {
+            let mut buffer = Buffer::new(8);
+            b.iter(|| {
+                buffer.reset();
+                black_box(value).write(&mut buffer);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn varint_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varint_decode");
+    for value in VARINTS {
+        let mut buffer = Buffer::new(8);
+        value.write(&mut buffer);
+        let encoded = buffer.take().to_vec();
+        group.throughput(Throughput::Bytes(encoded.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(value), &encoded, |b, encoded| {
+            b.iter(|| read_varint(black_box(encoded)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse_handshake, make_raw_handshake, varint_encode, varint_decode);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+#![no_main]
+
+use std::{io::Cursor, sync::OnceLock};
+
+use libfuzzer_sys::fuzz_target;
+use minecraft::{
+    packets::HandshakeC2SPacket,
+    serialization::{read_varint, MinecraftStream}
+};
+use tokio::runtime::{Builder, Runtime};
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Builder::new_current_thread().build().unwrap())
+}
+
+// any input may only be parsed or rejected, a panic is a bug
+fuzz_target!(|data: &[u8]| {
+    _ = read_varint(data);
+    runtime().block_on(async {
+        let mut stream = MinecraftStream::new(Cursor::new(data.to_vec()), 16);
+        if let Ok(signature) = stream.read_signature().await {
+            _ = stream.read_data::<HandshakeC2SPacket>(signature).await;
+        }
+        // mineginx reads the handshake bounded by its length
+        let mut stream = MinecraftStream::new(Cursor::new(data.to_vec()), 16);
+        if let Ok(signature) = stream.read_signature().await {
+            _ = stream.read_data_bounded::<HandshakeC2SPacket>(signature).await;
+        }
+    });
+});
@@ -12,13 +12,17 @@ impl Buffer {
     }
 
     pub fn write_byte(&mut self, value: u8) {
-        if self.array.len() == self.position {
-            self.expand();
-        }
+        self.ensure_space(1);
         self.array[self.position] = value;
         self.position += 1;
     }
 
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.ensure_space(data.len());
+        self.array[self.position..self.position + data.len()].copy_from_slice(data);
+        self.position += data.len();
+    }
+
     pub fn take(&self) -> &[u8] {
         &self.array[0..self.position]
     }
@@ -27,9 +31,39 @@ impl Buffer {
         self.position = 0;
     }
 
-    fn expand(&mut self) {
-        let mut new_vec = vec![0_u8; self.array.len() * 2];
-        new_vec[0..self.array.len()].copy_from_slice(&self.array);
-        self.array = new_vec;
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Leaves `count` bytes for something known only after the next writes
+    pub(crate) fn skip(&mut self, count: usize) {
+        self.ensure_space(count);
+        self.position += count;
+    }
+
+    /// Moves the bytes written after `from` to end right before `to` and forgets them  
+    /// Returns where they start now
+    pub(crate) fn move_back(&mut self, from: usize, to: usize) -> usize {
+        let start = to - (self.position - from);
+        self.array.copy_within(from..self.position, start);
+        self.position = from;
+        start
+    }
+
+    /// Bytes it can hold before growing
+    pub fn capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    fn ensure_space(&mut self, count: usize) {
+        let required = self.position + count;
+        if self.array.len() < required {
+            self.expand(required);
+        }
+    }
+
+    /// Doubles the size, or grows right to `required` if doubling is not enough, so a big write resizes once
+    fn expand(&mut self, required: usize) {
+        self.array.resize(required.max(self.array.len() * 2), 0);
     }
 }
@@ -0,0 +1,135 @@
+use std::io::{Cursor, Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    buffer::Buffer,
+    packets::{MinecraftPacket, PacketDeserializer, PacketSerializer},
+    serialization::{read_varint, FieldWriter, MinecraftStream, ReadingError, MAX_PACKET_LENGTH}
+};
+
+/// The vanilla limit of the uncompressed `id` and `data` of a packet
+pub const MAX_UNCOMPRESSED_LENGTH: usize = 8388608;
+
+/// A `MinecraftStream` which compresses the packets once the server sends Set Compression  
+/// Until `set_threshold` the packets are written and read as usual  
+/// https://wiki.vg/Protocol#With_compression
+pub struct CompressedStream<RW> where RW: AsyncRead + AsyncWrite + Unpin {
+    stream: MinecraftStream<RW>,
+    threshold: Option<usize>
+}
+
+impl<RW: AsyncRead + AsyncWrite + Unpin> CompressedStream<RW> {
+    pub fn new(stream: MinecraftStream<RW>) -> CompressedStream<RW> {
+        CompressedStream {
+            stream,
+            threshold: None
+        }
+    }
+
+    /// The `threshold` of Set Compression, a negative one turns compression off
+    pub fn set_threshold(&mut self, threshold: i32) {
+        self.threshold = usize::try_from(threshold).ok();
+    }
+
+    pub fn threshold(&self) -> Option<usize> {
+        self.threshold
+    }
+
+    pub fn into_inner(self) -> MinecraftStream<RW> {
+        self.stream
+    }
+
+    /// Reads the next packet, returns its id and uncompressed `data`
+    pub async fn read_raw_packet(&mut self) -> Result<(i32, Vec<u8>), ReadingError> {
+        let signature = self.stream.read_signature().await?;
+        if self.threshold.is_none() {
+            let packet_id = signature.packet_id;
+            return Ok((packet_id, self.stream.read_raw_data(signature).await?));
+        }
+        // with compression the first varint after the length is the length of the uncompressed `id` and `data`
+        let data_length = usize::try_from(signature.packet_id).map_err(|_| ReadingError::Invalid)?;
+        let rest = self.stream.read_raw_data(signature).await?;
+        let body = match data_length {
+            0 => rest,
+            x if x > MAX_UNCOMPRESSED_LENGTH => return Err(ReadingError::Invalid),
+            x => decompress(&rest, x)?
+        };
+        let (packet_id, id_length) = read_varint(&body).map_err(|_| ReadingError::Invalid)?;
+        Ok((packet_id, body[id_length..].to_vec()))
+    }
+
+    /// Reads the next packet ignoring its id, like `MinecraftStream::read_packet`
+    pub async fn read_packet<T>(&mut self) -> Result<T, ReadingError> where T: PacketDeserializer {
+        let (packet_id, data) = self.read_raw_packet().await?;
+        parse_packet(packet_id, data).await
+    }
+
+    pub async fn write_packet_with_id<T>(&mut self, id: i32, packet: &T) -> Option<()> where T: PacketSerializer {
+        let packet = self.encode_packet(id, packet)?;
+        self.stream.write_raw(&packet).await
+    }
+
+    /// The packet as it is sent with the current threshold  
+    /// `id` and `data` shorter than the threshold are sent uncompressed, with `0` as their uncompressed length
+    pub fn encode_packet<T>(&self, id: i32, packet: &T) -> Option<Vec<u8>> where T: PacketSerializer {
+        let raw = MinecraftPacket::make_raw(id, packet)?;
+        let threshold = match self.threshold {
+            Some(x) => x,
+            None => return Some(raw)
+        };
+        let (_, length_len) = read_varint(&raw).ok()?;
+        let body = &raw[length_len..];
+        let mut frame = Buffer::new(body.len() + 10);
+        if body.len() < threshold {
+            ((body.len() + 1) as i32).write(&mut frame)?;
+            frame.write_byte(0);
+            frame.write_bytes(body);
+            return Some(frame.take().to_vec());
+        }
+        let compressed = compress(body)?;
+        let data_length = body.len() as i32;
+        let mut header = Buffer::new(5);
+        data_length.write(&mut header)?;
+        let length = header.take().len() + compressed.len();
+        if length > MAX_PACKET_LENGTH {
+            return None;
+        }
+        (length as i32).write(&mut frame)?;
+        frame.write_bytes(header.take());
+        frame.write_bytes(&compressed);
+        Some(frame.take().to_vec())
+    }
+}
+
+fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+/// The data must inflate to exactly `length` bytes, a longer stream is not inflated further
+fn decompress(data: &[u8], length: usize) -> Result<Vec<u8>, ReadingError> {
+    // the length is sent by the peer, so nothing is reserved for it up front
+    let mut body = vec![];
+    ZlibDecoder::new(data).take(length as u64 + 1).read_to_end(&mut body).map_err(|_| ReadingError::Invalid)?;
+    if body.len() != length {
+        return Err(ReadingError::Invalid);
+    }
+    Ok(body)
+}
+
+/// Parses `data` with the same readers as a packet of the socket, the fields may only use these bytes
+async fn parse_packet<T>(packet_id: i32, data: Vec<u8>) -> Result<T, ReadingError> where T: PacketDeserializer {
+    let mut frame = Buffer::new(data.len() + 10);
+    let mut id = Buffer::new(5);
+    packet_id.write(&mut id).ok_or(ReadingError::Invalid)?;
+    ((id.take().len() + data.len()) as i32).write(&mut frame).ok_or(ReadingError::Invalid)?;
+    frame.write_bytes(id.take());
+    frame.write_bytes(&data);
+    let frame = frame.take().to_vec();
+    let mut stream = MinecraftStream::new(Cursor::new(frame), data.len() + 10);
+    let signature = stream.read_signature().await?;
+    stream.read_data_bounded(signature).await
+}
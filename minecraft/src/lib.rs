@@ -1,6 +1,56 @@
+//! Reads and writes the Minecraft protocol packets from any tokio stream
+//!
+//! ```
+//! use std::io::Cursor;
+//!
+//! use minecraft::{packets::{HandshakeC2SPacket, MinecraftPacket}, serialization::MinecraftStream};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let data = MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+//!     protocol_version: 765,
+//!     domain: "mc.example.com".to_string(),
+//!     server_port: 25565,
+//!     next_state: 2
+//! }).unwrap();
+//!
+//! // a TcpStream works the same way
+//! let mut stream = MinecraftStream::new(Cursor::new(data), 1024);
+//! let handshake = stream.read_packet::<HandshakeC2SPacket>().await.unwrap();
+//! assert_eq!(handshake.domain, "mc.example.com");
+//! assert_eq!(handshake.next_state, 2);
+//! # }
+//! ```
+//!
+//! Other packets are declared with the derive macros, any `FieldReader` and `FieldWriter` type can be a field
+//!
+//! ```
+//! use minecraft::{
+//!     buffer::Buffer,
+//!     packets::{MinecraftPacket, PacketDeserializer, PacketSerializer},
+//!     serialization::{MinecraftStream, ReadingError}
+//! };
+//! use tokio::io::{AsyncRead, AsyncWrite};
+//!
+//! #[derive(PacketDeserializer, PacketSerializer)]
+//! struct ChatMessage {
+//!     message: String
+//! }
+//!
+//! let data = MinecraftPacket::make_raw(5, &ChatMessage { message: "hi".to_string() }).unwrap();
+//! assert_eq!(data, [4, 5, 2, b'h', b'i']);
+//! ```
+//!
+//! The `nbt` feature adds the `nbt::NbtTag` field type for the named binary tags of status and play packets  
+//! The `compression` feature adds `compression::CompressedStream` for the packets after Set Compression
 pub mod serialization;
 pub mod packets;
-mod buffer;
+pub mod login;
+pub mod buffer;
+#[cfg(feature = "nbt")]
+pub mod nbt;
+#[cfg(feature = "compression")]
+pub mod compression;
 
 #[cfg(test)]
 mod tests;
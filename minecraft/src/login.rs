@@ -0,0 +1,98 @@
+use crate::serialization::{read_varint, ReadingError};
+
+pub const ENCRYPTION_REQUEST_ID: i32 = 0x01;
+pub const LOGIN_SUCCESS_ID: i32 = 0x02;
+pub const SET_COMPRESSION_ID: i32 = 0x03;
+
+/// What the server side of the login state allows to parse  
+/// https://wiki.vg/Protocol#Login
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LoginPhase {
+    /// Packets are plain `length`, `id`, `data` frames
+    Plain,
+    /// Every next packet is zlib framed, packets shorter than `threshold` are sent uncompressed inside the frame
+    Compressed { threshold: i32 },
+    /// Everything after the encryption request is encrypted
+    Encrypted,
+    /// Login succeeded without compression, the next packets belong to another state
+    Finished
+}
+
+/// Follows the packets sent by the server during login and stops parsing them once they are no longer plain
+pub struct LoginWatcher {
+    phase: LoginPhase,
+    pending: Vec<u8>
+}
+
+impl Default for LoginWatcher {
+    fn default() -> Self {
+        LoginWatcher::new()
+    }
+}
+
+impl LoginWatcher {
+    pub fn new() -> LoginWatcher {
+        LoginWatcher {
+            phase: LoginPhase::Plain,
+            pending: vec![]
+        }
+    }
+
+    pub fn phase(&self) -> LoginPhase {
+        self.phase
+    }
+
+    /// Whether the packets seen so far can be parsed as plain packets
+    pub fn is_plain(&self) -> bool {
+        self.phase == LoginPhase::Plain
+    }
+
+    /// Takes the next bytes from the server, a packet may be split between calls  
+    /// Bytes after the phase changed from `Plain` are ignored
+    pub fn feed(&mut self, data: &[u8]) -> Result<LoginPhase, ReadingError> {
+        if !self.is_plain() {
+            return Ok(self.phase);
+        }
+        self.pending.extend_from_slice(data);
+        let mut position = 0;
+        while self.is_plain() {
+            let (length, size) = match read_varint(&self.pending[position..]) {
+                Ok(x) => x,
+                Err(ReadingError::Insufficient) => break,
+                Err(err) => return Err(err)
+            };
+            if length < 0 {
+                return Err(ReadingError::Invalid);
+            }
+            let start = position + size;
+            let end = start + length as usize;
+            if end > self.pending.len() {
+                break;
+            }
+            self.phase = next_phase(&self.pending[start..end])?;
+            position = end;
+        }
+        if self.is_plain() {
+            self.pending.drain(..position);
+        }
+        else {
+            self.pending = vec![];
+        }
+        Ok(self.phase)
+    }
+}
+
+/// `packet` is the id and data of one plain packet
+fn next_phase(packet: &[u8]) -> Result<LoginPhase, ReadingError> {
+    let (id, size) = read_varint(packet)?;
+    Ok(match id {
+        ENCRYPTION_REQUEST_ID => LoginPhase::Encrypted,
+        LOGIN_SUCCESS_ID => LoginPhase::Finished,
+        SET_COMPRESSION_ID => match read_varint(&packet[size..])? {
+            // a negative threshold disables compression
+            (threshold, _) if threshold >= 0 => LoginPhase::Compressed { threshold },
+            _ => LoginPhase::Plain
+        },
+        _ => LoginPhase::Plain
+    })
+}
@@ -0,0 +1,245 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{buffer::Buffer, serialization::{FieldReader, FieldWriter, MinecraftStream, ReadingError}};
+
+/// Compounds and lists deeper than this are `Invalid`, the same limit as the vanilla server
+pub const MAX_DEPTH: usize = 512;
+
+/// Uncompressed named binary tag
+/// As a field it is the network form since 1.20.2: the type of the root tag and its payload, without the root name
+/// https://wiki.vg/NBT
+#[derive(Debug, Clone, PartialEq)]
+pub enum NbtTag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// The elements must have the same type, the type of the first one is written
+    List(Vec<NbtTag>),
+    /// Entries keep their order
+    Compound(Vec<(String, NbtTag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>)
+}
+
+const TAG_END: u8 = 0;
+
+impl NbtTag {
+    pub fn type_id(&self) -> u8 {
+        match self {
+            NbtTag::Byte(_) => 1,
+            NbtTag::Short(_) => 2,
+            NbtTag::Int(_) => 3,
+            NbtTag::Long(_) => 4,
+            NbtTag::Float(_) => 5,
+            NbtTag::Double(_) => 6,
+            NbtTag::ByteArray(_) => 7,
+            NbtTag::String(_) => 8,
+            NbtTag::List(_) => 9,
+            NbtTag::Compound(_) => 10,
+            NbtTag::IntArray(_) => 11,
+            NbtTag::LongArray(_) => 12
+        }
+    }
+
+    /// Value of a compound entry, the first one if the name is repeated
+    pub fn get(&self, name: &str) -> Option<&NbtTag> {
+        match self {
+            NbtTag::Compound(entries) => entries.iter().find(|(key, _)| key == name).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    fn write_payload(&self, stream: &mut Buffer) -> Option<()> {
+        match self {
+            NbtTag::Byte(x) => stream.write_bytes(&x.to_be_bytes()),
+            NbtTag::Short(x) => stream.write_bytes(&x.to_be_bytes()),
+            NbtTag::Int(x) => stream.write_bytes(&x.to_be_bytes()),
+            NbtTag::Long(x) => stream.write_bytes(&x.to_be_bytes()),
+            NbtTag::Float(x) => stream.write_bytes(&x.to_be_bytes()),
+            NbtTag::Double(x) => stream.write_bytes(&x.to_be_bytes()),
+            NbtTag::ByteArray(x) => {
+                write_length(stream, x.len())?;
+                for item in x {
+                    stream.write_bytes(&item.to_be_bytes());
+                }
+            },
+            NbtTag::String(x) => write_string(stream, x)?,
+            NbtTag::List(x) => {
+                let type_id = x.first().map(|first| first.type_id()).unwrap_or(TAG_END);
+                if x.iter().any(|item| item.type_id() != type_id) {
+                    return None;
+                }
+                stream.write_byte(type_id);
+                write_length(stream, x.len())?;
+                for item in x {
+                    item.write_payload(stream)?;
+                }
+            },
+            NbtTag::Compound(x) => {
+                for (name, value) in x {
+                    stream.write_byte(value.type_id());
+                    write_string(stream, name)?;
+                    value.write_payload(stream)?;
+                }
+                stream.write_byte(TAG_END);
+            },
+            NbtTag::IntArray(x) => {
+                write_length(stream, x.len())?;
+                for item in x {
+                    stream.write_bytes(&item.to_be_bytes());
+                }
+            },
+            NbtTag::LongArray(x) => {
+                write_length(stream, x.len())?;
+                for item in x {
+                    stream.write_bytes(&item.to_be_bytes());
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+fn write_length(stream: &mut Buffer, length: usize) -> Option<()> {
+    let length = i32::try_from(length).ok()?;
+    stream.write_bytes(&length.to_be_bytes());
+    Some(())
+}
+
+/// Java's modified utf-8 matches utf-8 except for `\0` and characters outside of the BMP, which are rare in names and texts
+fn write_string(stream: &mut Buffer, value: &str) -> Option<()> {
+    let length = u16::try_from(value.len()).ok()?;
+    stream.write_bytes(&length.to_be_bytes());
+    stream.write_bytes(value.as_bytes());
+    Some(())
+}
+
+/// Parses tags of a byte slice, `Insufficient` if the slice ends inside of a tag
+struct Parser<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Parser<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ReadingError> {
+        if self.data.len() - self.position < count {
+            return Err(ReadingError::Insufficient);
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], ReadingError> {
+        let mut bytes = [0_u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    /// A negative length is `Invalid`, a length longer than the rest of the data is `Insufficient`
+    /// before anything is allocated for it
+    fn length(&mut self, item_size: usize) -> Result<usize, ReadingError> {
+        let length = usize::try_from(i32::from_be_bytes(self.take_array()?)).map_err(|_| ReadingError::Invalid)?;
+        if (self.data.len() - self.position) / item_size < length {
+            return Err(ReadingError::Insufficient);
+        }
+        Ok(length)
+    }
+
+    fn string(&mut self) -> Result<String, ReadingError> {
+        let length = u16::from_be_bytes(self.take_array()?) as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ReadingError::Invalid)
+    }
+
+    fn payload(&mut self, type_id: u8, depth: usize) -> Result<NbtTag, ReadingError> {
+        if depth > MAX_DEPTH {
+            return Err(ReadingError::Invalid);
+        }
+        Ok(match type_id {
+            1 => NbtTag::Byte(i8::from_be_bytes(self.take_array()?)),
+            2 => NbtTag::Short(i16::from_be_bytes(self.take_array()?)),
+            3 => NbtTag::Int(i32::from_be_bytes(self.take_array()?)),
+            4 => NbtTag::Long(i64::from_be_bytes(self.take_array()?)),
+            5 => NbtTag::Float(f32::from_be_bytes(self.take_array()?)),
+            6 => NbtTag::Double(f64::from_be_bytes(self.take_array()?)),
+            7 => {
+                let length = self.length(1)?;
+                NbtTag::ByteArray(self.take(length)?.iter().map(|x| *x as i8).collect())
+            },
+            8 => NbtTag::String(self.string()?),
+            9 => {
+                let item_type = self.take_array::<1>()?[0];
+                // every tag but an empty compound takes at least a byte
+                let length = self.length(1)?;
+                if item_type == TAG_END && length > 0 {
+                    return Err(ReadingError::Invalid);
+                }
+                let mut items = Vec::with_capacity(length);
+                for _ in 0..length {
+                    items.push(self.payload(item_type, depth + 1)?);
+                }
+                NbtTag::List(items)
+            },
+            10 => {
+                let mut entries = vec![];
+                loop {
+                    let entry_type = self.take_array::<1>()?[0];
+                    if entry_type == TAG_END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.push((name, self.payload(entry_type, depth + 1)?));
+                }
+                NbtTag::Compound(entries)
+            },
+            11 => {
+                let length = self.length(4)?;
+                let mut items = Vec::with_capacity(length);
+                for _ in 0..length {
+                    items.push(i32::from_be_bytes(self.take_array()?));
+                }
+                NbtTag::IntArray(items)
+            },
+            12 => {
+                let length = self.length(8)?;
+                let mut items = Vec::with_capacity(length);
+                for _ in 0..length {
+                    items.push(i64::from_be_bytes(self.take_array()?));
+                }
+                NbtTag::LongArray(items)
+            },
+            _ => return Err(ReadingError::Invalid)
+        })
+    }
+}
+
+/// Parses the network form from the start of `data`, returns the tag and the number of bytes it took
+pub fn parse_nbt(data: &[u8]) -> Result<(NbtTag, usize), ReadingError> {
+    let mut parser = Parser { data, position: 0 };
+    let type_id = parser.take_array::<1>()?[0];
+    let tag = parser.payload(type_id, 0)?;
+    Ok((tag, parser.position))
+}
+
+impl FieldReader for NbtTag {
+    /// Nothing is consumed unless the whole tag is buffered
+    fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
+        let (tag, length) = parse_nbt(stream.peek())?;
+        stream.consume(length);
+        Ok(tag)
+    }
+}
+
+impl FieldWriter for NbtTag {
+    /// `None` for a list with elements of different types, or a string or array too long for its length prefix
+    fn write(&self, stream: &mut Buffer) -> Option<()> {
+        stream.write_byte(self.type_id());
+        self.write_payload(stream)
+    }
+}
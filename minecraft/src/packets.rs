@@ -1,4 +1,7 @@
-use minecraft_macros::{PacketDeserializer, PacketSerializer};
+use std::cell::RefCell;
+
+/// The derived code expects `AsyncRead`, `AsyncWrite`, `MinecraftStream`, `ReadingError` and `Buffer` to be in scope
+pub use minecraft_macros::{PacketDeserializer, PacketSerializer};
 use tokio::io::{AsyncRead, AsyncWrite};
 use uuid::Uuid;
 
@@ -20,24 +23,46 @@ pub trait PacketSerializer {
 pub struct MinecraftPacket {
 }
 
+/// The longest varint, the packet length is written in this space once the rest of the packet is known
+const LENGTH_SPACE: usize = 5;
+
+/// A bigger reused buffer is dropped after the packet, so one large packet doesn't keep its memory on every thread
+const KEEP_BUFFER_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static PACKET_BUFFER: RefCell<Buffer> = RefCell::new(Buffer::new(1024));
+}
+
 impl MinecraftPacket {
     pub fn make_raw<T>(id: i32, packet: &T) -> Option<Vec<u8>> where T: PacketSerializer {
-        let mut data_buffer = Buffer::new(1024);
-        T::to_raw(packet, &mut data_buffer)?;
-        let mut packet_id_buffer = Buffer::new(5);
-        id.write(&mut packet_id_buffer);
-        let mut packet_length_buffer = Buffer::new(5);
-
-        let d2 = packet_id_buffer.take();
-        let d3 = data_buffer.take();
-        (d2.len() as i32 + d3.len() as i32).write(&mut packet_length_buffer);
-
-        let d1 = packet_length_buffer.take();
-        let array = [d1, d2, d3].concat();
-        Some(array)
+        PACKET_BUFFER.with(|buffer| {
+            let buffer = &mut *buffer.borrow_mut();
+            let raw = Self::write_raw(buffer, id, packet);
+            if buffer.capacity() > KEEP_BUFFER_CAPACITY {
+                *buffer = Buffer::new(1024);
+            }
+            raw
+        })
+    }
+
+    fn write_raw<T>(buffer: &mut Buffer, id: i32, packet: &T) -> Option<Vec<u8>> where T: PacketSerializer {
+        buffer.reset();
+        buffer.skip(LENGTH_SPACE);
+        id.write(buffer)?;
+        T::to_raw(packet, buffer)?;
+        let end = buffer.position();
+        ((end - LENGTH_SPACE) as i32).write(buffer)?;
+        let start = buffer.move_back(end, LENGTH_SPACE);
+        Some(buffer.take()[start..end].to_vec())
     }
 }
 
+/// Bytes the reused buffer of this thread holds
+#[cfg(test)]
+pub(crate) fn packet_buffer_capacity() -> usize {
+    PACKET_BUFFER.with(|buffer| buffer.borrow().capacity())
+}
+
 #[derive(PacketDeserializer, PacketSerializer)]
 pub struct HandshakeC2SPacket {
     pub protocol_version: i32,
@@ -46,6 +71,48 @@ pub struct HandshakeC2SPacket {
     pub next_state: i32
 }
 
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct StatusRequestC2SPacket {
+}
+
+/// Packet id is 0, `json_response` is described at https://wiki.vg/Server_List_Ping#Status_Response
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct StatusResponseS2CPacket {
+    pub json_response: String
+}
+
+/// Packet id is 1
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct PingRequestC2SPacket {
+    pub payload: i64
+}
+
+/// Packet id is 1, `payload` is the same as in the ping request
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct PongResponseS2CPacket {
+    pub payload: i64
+}
+
+/// Packet id is 0 in the login state, `reason` is a json text component
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct LoginDisconnectS2CPacket {
+    pub reason: String
+}
+
+/// Packet id is 3 in the login state, packets of at least `threshold` bytes are compressed after it
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct SetCompressionS2CPacket {
+    pub threshold: i32
+}
+
+/// Packet id is 4 in the login state since 1.13, the client answers it with Login Plugin Response of the same `message_id`  
+/// The payload which may follow `channel` is left out
+#[derive(PacketDeserializer, PacketSerializer)]
+pub struct LoginPluginRequestS2CPacket {
+    pub message_id: i32,
+    pub channel: String
+}
+
 #[derive(PacketDeserializer)]
 pub struct LoginC2SPacket {
     pub name: String,
@@ -1,27 +1,42 @@
 
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::{error::Error, fmt, time::Duration};
+
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, time::timeout};
 use uuid::Uuid;
 
 use crate::{buffer::Buffer, packets::{MinecraftPacket, PacketDeserializer, PacketSerializer}};
 
 const SEGMENT_BITS: i32 = 0x7F;
 const CONTINUE_BIT: i32 = 0x80;
+/// Minecraft doesn't send longer packets, a bigger length is rather an attempt to make the buffer grow
+pub const MAX_PACKET_LENGTH: usize = 2097151;
 
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum ReadingError {
     Insufficient,
     Invalid,
-    Closed
+    Closed,
+    /// The source closed before sending a single byte, like port scanners and tcp health checks do
+    ClosedEmpty,
+    /// The source didn't send anything within `read_timeout`
+    Timeout
 }
 
-impl From<ReadingError> for () {
-    fn from(value: ReadingError) -> Self {
-        let _ = value;
-        
+impl fmt::Display for ReadingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadingError::Insufficient => write!(f, "the data ends in the middle of a field"),
+            ReadingError::Invalid => write!(f, "malformed packet"),
+            ReadingError::Closed => write!(f, "connection closed in the middle of a packet"),
+            ReadingError::ClosedEmpty => write!(f, "connection closed without sending anything"),
+            ReadingError::Timeout => write!(f, "nothing was received in time")
+        }
     }
 }
 
+impl Error for ReadingError {}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub struct Signature {
@@ -37,19 +52,35 @@ impl FieldWriter for Signature {
     }
 }
 
-pub(crate) trait FieldReader {
+/// `data` of a packet which is read piece by piece, see `MinecraftStream::data_chunks`
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct DataChunks {
+    remaining: usize
+}
+
+impl DataChunks {
+    /// Bytes of `data` which are not read yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+/// A type which can be a field of a packet deriving `PacketDeserializer`
+pub trait FieldReader {
     fn read<RW>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError>
     where
         Self: Sized,
         RW: AsyncRead + AsyncWrite + Unpin;
 }
 
-pub(crate) trait FieldWriter {
+/// A type which can be a field of a packet deriving `PacketSerializer`
+pub trait FieldWriter {
     fn write(&self, stream: &mut Buffer) -> Option<()> where Self: Sized;
 }
 
 impl Buffer {
-    pub(crate) fn write_field<T>(&mut self, value: &T) -> Option<()> where T: FieldWriter {
+    pub fn write_field<T>(&mut self, value: &T) -> Option<()> where T: FieldWriter {
         T::write(value, self)
     }
 }
@@ -66,6 +97,9 @@ pub struct MinecraftStream<RW> where RW : AsyncRead + AsyncWrite + Unpin {
     client: RW,
     free: usize,
     position: usize,
+    read_timeout: Option<Duration>,
+    /// Whether the source sent anything yet
+    received: bool
 }
 
 impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
@@ -74,20 +108,50 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
             buffer: vec![0; init_buffer_size],
             client,
             position: 0,
-            free: 0
+            free: 0,
+            read_timeout: None,
+            received: false
         }
     }
 
+    /// Bounds every read from the source, `None` waits forever
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// Bytes it can buffer before growing
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
     pub fn get_position(&self) -> usize {
         self.position
     }
 
+    /// Bytes which are read from the source, but not parsed yet, see `peek`
     pub fn data_len(&self) -> usize {
-        self.free - self.position + 1
+        self.free - self.position
+    }
+
+    /// Bytes which are already read from the source, but not parsed yet  
+    /// Such as the start of the next packet, which arrived in the same read as the previous one
+    pub fn peek(&self) -> &[u8] {
+        &self.buffer[self.position..self.free]
     }
 
+    /// Returns the same bytes as `peek` and forgets them  
+    /// Nothing is read from the source after the last packet, so once the stream is unwrapped
+    /// these bytes followed by the rest of the source are exactly what the peer sent after that packet
     pub fn take_buffer(&mut self) -> Vec<u8> {
-        self.buffer[self.position..self.free].to_vec()
+        let unread = self.peek().to_vec();
+        self.position = self.free;
+        unread
+    }
+
+    /// Forgets `count` bytes of `peek`, for readers which parse the buffered bytes themselves
+    #[cfg(feature = "nbt")]
+    pub(crate) fn consume(&mut self, count: usize) {
+        self.position += count.min(self.data_len());
     }
 
     /// Reads signature of packet to the end  
@@ -105,10 +169,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
                         return Err(e);
                     }
                     else if e == ReadingError::Insufficient {
-                        match self.fill_buffer_from_source(0).await {
-                            Ok(_) => { },
-                            Err(_) => return Err(ReadingError::Closed),
-                        }
+                        self.fill_buffer_from_source(0).await?;
                         continue;
                     }
                     return Err(ReadingError::Closed);
@@ -124,10 +185,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
                         return Err(e);
                     }
                     else if e == ReadingError::Insufficient {
-                        match self.fill_buffer_from_source(0).await {
-                            Ok(_) => { },
-                            Err(_) => return Err(ReadingError::Closed),
-                        }
+                        self.fill_buffer_from_source(0).await?;
                         continue;
                     }
                     return Err(ReadingError::Closed);
@@ -149,14 +207,92 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
     /// Reads `data` field of packet to the end  
     /// https://wiki.vg/Protocol#Packet_format
     pub async fn read_data<T>(&mut self, signature: Signature) -> Result<T, ReadingError> where T : PacketDeserializer {
-        if signature.length > self.data_len() {
-            match &self.fill_buffer_from_source(signature.length).await {
-                Ok(_) => {},
-                Err(_) => return Err(ReadingError::Closed)
-            };
+        self.buffer_data(&signature).await?;
+        T::from_raw(self)
+    }
+
+    /// Reads `data` like `read_data`, but the fields may only use the `length` bytes of this packet  
+    /// A field which needs more bytes makes the packet `Invalid` instead of taking them from the next packet  
+    /// Bytes left after the last field are skipped, so the stream stays at the start of the next packet
+    pub async fn read_data_bounded<T>(&mut self, signature: Signature) -> Result<T, ReadingError> where T : PacketDeserializer {
+        let data_length = self.buffer_data(&signature).await?;
+        let end = self.position + data_length;
+        let free = self.free;
+        // the readers stop at `free`, so the next packet looks like it isn't received yet
+        self.free = end;
+        let result = T::from_raw(self);
+        self.free = free;
+        self.position = end;
+        match result {
+            Err(ReadingError::Insufficient) => Err(ReadingError::Invalid),
+            x => x
         }
+    }
 
-        T::from_raw(self)
+    /// Waits until the whole `data` of the packet is buffered, returns its length
+    async fn buffer_data(&mut self, signature: &Signature) -> Result<usize, ReadingError> {
+        let data_length = data_length(signature)?;
+        if data_length > self.data_len() {
+            self.fill_buffer_from_source(data_length).await?;
+        }
+        Ok(data_length)
+    }
+
+    /// Reads `data` of the packet as bytes, without parsing it
+    pub async fn read_raw_data(&mut self, signature: Signature) -> Result<Vec<u8>, ReadingError> {
+        let data_length = self.buffer_data(&signature).await?;
+        let data = self.buffer[self.position..self.position + data_length].to_vec();
+        self.position += data_length;
+        Ok(data)
+    }
+
+    /// Reads the whole next packet as bytes, its length and id included, exactly as the peer sent them
+    /// Even a length which isn't encoded in the shortest form is kept, so the packet can be passed on unchanged
+    pub async fn read_raw_packet(&mut self) -> Result<Vec<u8>, ReadingError> {
+        let (length, length_size) = loop {
+            match read_varint(self.peek()) {
+                Ok(x) => break x,
+                Err(ReadingError::Insufficient) => self.fill_buffer_from_source(0).await?,
+                Err(e) => return Err(e)
+            }
+        };
+        if length < 0 || length as usize > MAX_PACKET_LENGTH {
+            return Err(ReadingError::Invalid);
+        }
+        let packet_length = length_size + length as usize;
+        if packet_length > self.data_len() {
+            self.fill_buffer_from_source(packet_length).await?;
+        }
+        let packet = self.buffer[self.position..self.position + packet_length].to_vec();
+        self.position += packet_length;
+        Ok(packet)
+    }
+
+    /// Reads `data` of the packet piece by piece with `read_chunk` instead of buffering it whole like `read_data` does  
+    /// The buffer doesn't grow for it, so a large packet takes no more memory than the buffer already has
+    pub fn data_chunks(&self, signature: Signature) -> Result<DataChunks, ReadingError> {
+        Ok(DataChunks {
+            remaining: data_length(&signature)?
+        })
+    }
+
+    /// The next bytes of `data`, as many as are buffered or arrive in one read, `None` once all of them are read  
+    /// The stream is at the start of the next packet after the last chunk
+    pub async fn read_chunk(&mut self, chunks: &mut DataChunks) -> Result<Option<&[u8]>, ReadingError> {
+        if chunks.remaining == 0 {
+            return Ok(None);
+        }
+        if self.data_len() == 0 {
+            // everything buffered is parsed, so the read may take the whole buffer
+            self.position = 0;
+            self.free = 0;
+            self.fill_buffer_from_source(1).await?;
+        }
+        let size = chunks.remaining.min(self.data_len());
+        let chunk = &self.buffer[self.position..self.position + size];
+        self.position += size;
+        chunks.remaining -= size;
+        Ok(Some(chunk))
     }
 
     /// Reads **exactly this packet** to the end ignoring packet id from signature.  
@@ -167,7 +303,11 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
     }
 
     pub async fn write_packet<T>(&mut self, packet: &T) -> Option<()> where T: PacketSerializer {
-        let packet = MinecraftPacket::make_raw(0, packet)?;
+        self.write_packet_with_id(0, packet).await
+    }
+
+    pub async fn write_packet_with_id<T>(&mut self, id: i32, packet: &T) -> Option<()> where T: PacketSerializer {
+        let packet = MinecraftPacket::make_raw(id, packet)?;
         match self.client.write_all(&packet[0..packet.len()]).await {
             Ok(_) => { },
             Err(_) => return None,
@@ -175,12 +315,14 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
         Some(())
     }
 
-    pub(crate) fn read_field<T>(&mut self) -> Result<T, ReadingError> where T: FieldReader {
-        T::read(self)
+    /// Writes already encoded packets, such as the ones of `MinecraftPacket::make_raw`
+    pub async fn write_raw(&mut self, data: &[u8]) -> Option<()> {
+        self.client.write_all(data).await.ok()
     }
 
-    fn remain_len(&self) -> usize {
-        self.buffer.len() - self.position
+    /// Reads one field from the already buffered data, `Insufficient` if it isn't fully there
+    pub fn read_field<T>(&mut self) -> Result<T, ReadingError> where T: FieldReader {
+        T::read(self)
     }
 
     fn copy_buffer_to_start(&mut self) {
@@ -190,32 +332,38 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
         self.position = 0;
     }
 
+    /// Doubles the buffer for a packet which doesn't fit
     fn expand_buffer(&mut self) {
-        todo!()
+        let size = self.buffer.len().max(1) * 2;
+        self.buffer.resize(size, 0);
     }
 
-    async fn fill_buffer_from_source(&mut self, required: usize) -> Result<(), ()> {
-        if self.free >= self.buffer.len() {
-            if self.position != 0 {
-                self.copy_buffer_to_start();
-            }
-            else {
-                self.expand_buffer();
-            }
-        }
+    async fn fill_buffer_from_source(&mut self, required: usize) -> Result<(), ReadingError> {
         loop {
+            if self.free >= self.buffer.len() {
+                if self.position != 0 {
+                    self.copy_buffer_to_start();
+                }
+                else {
+                    self.expand_buffer();
+                }
+            }
             let pos = &self.free;
-            let read = self.client.read(&mut self.buffer[*pos..]).await;
+            let read = self.client.read(&mut self.buffer[*pos..]);
+            let read = match self.read_timeout {
+                Some(duration) => match timeout(duration, read).await {
+                    Ok(x) => x,
+                    Err(_) => return Err(ReadingError::Timeout)
+                },
+                None => read.await
+            };
             match read {
-                Ok(size) => {
-                    if size == 0 {
-                        return Err(());
-                    }
+                Ok(size) if size > 0 => {
                     self.free += size;
+                    self.received = true;
                 },
-                Err(_) => {
-                    return Err(());
-                }
+                _ if !self.received => return Err(ReadingError::ClosedEmpty),
+                _ => return Err(ReadingError::Closed)
             }
 
             if self.data_len() < required {
@@ -227,44 +375,67 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> MinecraftStream<RW> {
     }
 }
 
+/// Length of `data` of the packet, `length` counts the packet id too, which `read_signature` has already taken
+fn data_length(signature: &Signature) -> Result<usize, ReadingError> {
+    match signature.length.checked_sub(varint_len(signature.packet_id)) {
+        Some(x) if x <= MAX_PACKET_LENGTH => Ok(x),
+        _ => Err(ReadingError::Invalid)
+    }
+}
+
 impl FieldReader for i32 {
     fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
-        let mut value = 0;
-        let mut current_position = 0;
-        let mut current_byte: i32;
-        let mut index = stream.position;
+        let (value, size) = read_varint(&stream.buffer[stream.position..stream.free])?;
+        stream.position += size;
+        Ok(value)
+    }
+}
 
-        loop {
-            if index >= stream.free {
-                return Err(ReadingError::Insufficient);
-            }
-            current_byte = stream.buffer[index] as i32;
-            index += 1;
-            value |= (current_byte & SEGMENT_BITS) << current_position;
+/// Reads a varint from the start of `data`, returns it with the count of its bytes
+pub fn read_varint(data: &[u8]) -> Result<(i32, usize), ReadingError> {
+    let mut value = 0;
+    let mut current_position = 0;
+    let mut index = 0;
+    loop {
+        let current_byte = match data.get(index) {
+            Some(&x) => x as i32,
+            None => return Err(ReadingError::Insufficient)
+        };
+        index += 1;
+        value |= (current_byte & SEGMENT_BITS) << current_position;
 
-            if (current_byte & CONTINUE_BIT) == 0 {
-                break;
-            }
-            current_position += 7;
-            if current_position >= 32 {
-                return Err(ReadingError::Invalid);
-            }
+        if (current_byte & CONTINUE_BIT) == 0 {
+            break;
         }
+        current_position += 7;
+        if current_position >= 32 {
+            return Err(ReadingError::Invalid);
+        }
+    }
+    Ok((value, index))
+}
 
-        stream.position = index;
-        Ok(value)
+/// How many bytes `value` takes as a VarInt
+pub fn varint_len(value: i32) -> usize {
+    let mut value = value as u32;
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
     }
+    len
 }
 
 impl FieldWriter for i32 {
     fn write(&self, stream: &mut Buffer) -> Option<()> {
-        let mut value = *self;
+        // negative values take 5 bytes, the shift must not keep the sign
+        let mut value = *self as u32;
         loop {
-            if (value & !SEGMENT_BITS) == 0 {
+            if (value & !(SEGMENT_BITS as u32)) == 0 {
                 stream.write_byte(value as u8);
                 return Some(())
             }
-            stream.write_byte(((value & SEGMENT_BITS) | CONTINUE_BIT) as u8);
+            stream.write_byte(((value & SEGMENT_BITS as u32) | CONTINUE_BIT as u32) as u8);
             value >>= 7;
         }
     }
@@ -272,16 +443,16 @@ impl FieldWriter for i32 {
 
 impl FieldReader for String {
     fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
-        // todo: there is a bug - read_field changes position of the stream, but below can happen reading error if packet doesn't fully read
-        let length = stream.read_field::<i32>()? as usize;
-
-        if length > stream.remain_len() {
+        // the stream stays at the length until the whole string is buffered, so it can be read again after a refill
+        let (length, size) = read_varint(stream.peek())?;
+        let length = usize::try_from(length).map_err(|_| ReadingError::Invalid)?;
+        if size + length > stream.data_len() {
             return Err(ReadingError::Insufficient);
         }
-        let mut vec: Vec<u8> = vec![0; length];
-        vec.copy_from_slice(&stream.buffer[stream.position..stream.position + length]);
-        stream.position += length;
-        Ok(String::from_utf8(vec).unwrap())
+        let start = stream.position + size;
+        let vec = stream.buffer[start..start + length].to_vec();
+        stream.position = start + length;
+        String::from_utf8(vec).map_err(|_| ReadingError::Invalid)
     }
 }
 
@@ -289,9 +460,7 @@ impl FieldWriter for String {
     fn write(&self, stream: &mut Buffer) -> Option<()> {
         let length = self.len() as i32;
         length.write(stream);
-        for byte in self.as_bytes() {
-            stream.write_byte(*byte)
-        }
+        stream.write_bytes(self.as_bytes());
         Some(())
     }
 }
@@ -301,14 +470,8 @@ impl FieldReader for u16 {
         if stream.data_len() < 2 {
             return Err(ReadingError::Insufficient);
         }
-        let b1 = match stream.read_field::<u8>() {
-            Ok(x) => x,
-            Err(e) => return Err(e),
-        };
-        let b2 = match stream.read_field::<u8>() {
-            Ok(x) => x,
-            Err(e) => return Err(e),
-        };
+        let b1 = stream.read_field::<u8>()?;
+        let b2 = stream.read_field::<u8>()?;
         Ok(b2 as u16 | (b1 as u16) << 8)
     }
 }
@@ -347,6 +510,63 @@ impl FieldReader for u8 {
     }
 }
 
+impl FieldReader for i64 {
+    fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
+        if stream.free - stream.position < 8 {
+            return Err(ReadingError::Insufficient);
+        }
+        let mut bytes = [0_u8; 8];
+        bytes.copy_from_slice(&stream.buffer[stream.position..stream.position + 8]);
+        stream.position += 8;
+        Ok(i64::from_be_bytes(bytes))
+    }
+}
+
+impl FieldWriter for i64 {
+    fn write(&self, stream: &mut Buffer) -> Option<()> {
+        stream.write_bytes(&self.to_be_bytes());
+        Some(())
+    }
+}
+
+impl FieldReader for f32 {
+    fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
+        if stream.data_len() < 4 {
+            return Err(ReadingError::Insufficient);
+        }
+        let mut bytes = [0_u8; 4];
+        bytes.copy_from_slice(&stream.buffer[stream.position..stream.position + 4]);
+        stream.position += 4;
+        Ok(f32::from_be_bytes(bytes))
+    }
+}
+
+impl FieldWriter for f32 {
+    fn write(&self, stream: &mut Buffer) -> Option<()> {
+        stream.write_bytes(&self.to_be_bytes());
+        Some(())
+    }
+}
+
+impl FieldReader for f64 {
+    fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
+        if stream.data_len() < 8 {
+            return Err(ReadingError::Insufficient);
+        }
+        let mut bytes = [0_u8; 8];
+        bytes.copy_from_slice(&stream.buffer[stream.position..stream.position + 8]);
+        stream.position += 8;
+        Ok(f64::from_be_bytes(bytes))
+    }
+}
+
+impl FieldWriter for f64 {
+    fn write(&self, stream: &mut Buffer) -> Option<()> {
+        stream.write_bytes(&self.to_be_bytes());
+        Some(())
+    }
+}
+
 impl FieldReader for Uuid {
     fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
         if stream.data_len() < 16 {
@@ -365,13 +585,48 @@ impl FieldReader for Uuid {
 
 impl FieldWriter for Uuid {
     fn write(&self, stream: &mut Buffer) -> Option<()> {
-        for &byte in self.as_bytes() {
-            stream.write_byte(byte);
-        }
+        stream.write_bytes(self.as_bytes());
         Some(())
     }
 }
 
+/// Block coordinates packed into one `i64`: 26 bits of `x`, 26 bits of `z` and 12 bits of `y`  
+/// https://wiki.vg/Protocol#Position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32
+}
+
+impl Position {
+    /// Bits of the coordinates out of their ranges are dropped
+    pub fn pack(&self) -> i64 {
+        ((self.x as i64 & 0x3FFFFFF) << 38) | ((self.z as i64 & 0x3FFFFFF) << 12) | (self.y as i64 & 0xFFF)
+    }
+
+    pub fn unpack(value: i64) -> Position {
+        // the arithmetic shifts extend the sign of each field
+        Position {
+            x: (value >> 38) as i32,
+            y: (value << 52 >> 52) as i32,
+            z: (value << 26 >> 38) as i32
+        }
+    }
+}
+
+impl FieldReader for Position {
+    fn read<RW: AsyncRead + AsyncWrite + Unpin>(stream: &mut MinecraftStream<RW>) -> Result<Self, ReadingError> {
+        i64::read(stream).map(Position::unpack)
+    }
+}
+
+impl FieldWriter for Position {
+    fn write(&self, stream: &mut Buffer) -> Option<()> {
+        self.pack().write(stream)
+    }
+}
+
 pub fn truncate_to_zero(value: &str) -> &str {
     let index = &value.find('\0');
     match index {
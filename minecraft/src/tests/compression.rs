@@ -0,0 +1,117 @@
+use std::io::Cursor;
+
+use tokio::io::{duplex, AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+
+use crate::{
+    buffer::Buffer,
+    compression::CompressedStream,
+    packets::{HandshakeC2SPacket, MinecraftPacket, PacketDeserializer, PacketSerializer},
+    serialization::{MinecraftStream, ReadingError}
+};
+
+#[derive(PacketDeserializer, PacketSerializer, PartialEq, Debug)]
+struct ChatPacket {
+    message: String
+}
+
+/// `id` and `data` of the packet take `length` bytes
+fn chat(length: usize) -> ChatPacket {
+    // one byte of the id and one of the string length
+    ChatPacket { message: "a".repeat(length - 2) }
+}
+
+fn compressed(threshold: i32) -> CompressedStream<Cursor<Vec<u8>>> {
+    let mut stream = CompressedStream::new(MinecraftStream::new(Cursor::new(vec![]), 16));
+    stream.set_threshold(threshold);
+    stream
+}
+
+/// Writes `data` to a compressed stream with `threshold`, which reads it back
+async fn reader(data: Vec<u8>, threshold: i32) -> CompressedStream<DuplexStream> {
+    let (mut client, source) = duplex(data.len() + 1);
+    client.write_all(&data).await.unwrap();
+    let mut stream = CompressedStream::new(MinecraftStream::new(source, 16));
+    stream.set_threshold(threshold);
+    stream
+}
+
+#[test]
+fn below_threshold_is_not_compressed() {
+    let packet = compressed(64).encode_packet(3, &chat(63)).unwrap();
+    let raw = MinecraftPacket::make_raw(3, &chat(63)).unwrap();
+    // the length grows by the zero uncompressed length
+    assert_eq!(packet[0], 64);
+    assert_eq!(packet[1], 0);
+    assert_eq!(packet[2..], raw[1..]);
+}
+
+#[test]
+fn at_threshold_is_compressed() {
+    let packet = compressed(64).encode_packet(3, &chat(64)).unwrap();
+    // the packet length, 64 bytes when uncompressed, then zlib
+    assert_eq!(packet[1], 64);
+    assert_eq!(packet[2], 0x78);
+    assert!(packet.len() < 64);
+}
+
+#[test]
+fn without_threshold_is_plain() {
+    let mut stream = compressed(64);
+    stream.set_threshold(-1);
+    assert_eq!(stream.threshold(), None);
+    assert_eq!(stream.encode_packet(3, &chat(64)), MinecraftPacket::make_raw(3, &chat(64)));
+}
+
+#[tokio::test]
+async fn round_trip_at_and_below_threshold() {
+    for length in [10, 63, 64, 65, 5000] {
+        let packet = compressed(64).encode_packet(3, &chat(length)).unwrap();
+        let mut stream = reader(packet, 64).await;
+        assert_eq!(stream.read_packet::<ChatPacket>().await.unwrap(), chat(length), "{length}");
+    }
+}
+
+#[tokio::test]
+async fn raw_packet_has_id_and_data() {
+    let handshake = HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "mc.example.com".to_string(),
+        server_port: 25565,
+        next_state: 2
+    };
+    let data = [compressed(0).encode_packet(0, &handshake).unwrap(), compressed(0).encode_packet(7, &chat(3)).unwrap()].concat();
+    let mut stream = reader(data, 0).await;
+    let read = stream.read_packet::<HandshakeC2SPacket>().await.unwrap();
+    assert_eq!(read.domain, "mc.example.com");
+    assert_eq!(stream.read_raw_packet().await.unwrap(), (7, vec![1, b'a']));
+}
+
+#[tokio::test]
+async fn wrong_uncompressed_length_is_invalid() {
+    let mut packet = compressed(64).encode_packet(3, &chat(100)).unwrap();
+    // claims 99 bytes instead of 100
+    packet[1] = 99;
+    let mut stream = reader(packet, 64).await;
+    assert_eq!(stream.read_raw_packet().await.err(), Some(ReadingError::Invalid));
+
+    let mut frame = Buffer::new(16);
+    frame.write_bytes(&[6, 4, 1, 2, 3, 4, 5]);
+    let mut stream = reader(frame.take().to_vec(), 64).await;
+    assert_eq!(stream.read_raw_packet().await.err(), Some(ReadingError::Invalid));
+}
+
+#[tokio::test]
+async fn written_packets_are_read_by_peer() {
+    let (client, server) = duplex(1024);
+    let mut client = CompressedStream::new(MinecraftStream::new(client, 16));
+    let mut server = CompressedStream::new(MinecraftStream::new(server, 16));
+    client.write_packet_with_id(3, &chat(100)).await.unwrap();
+    assert_eq!(server.read_packet::<ChatPacket>().await.unwrap(), chat(100));
+
+    client.set_threshold(64);
+    server.set_threshold(64);
+    client.write_packet_with_id(3, &chat(10)).await.unwrap();
+    client.write_packet_with_id(3, &chat(100)).await.unwrap();
+    assert_eq!(server.read_packet::<ChatPacket>().await.unwrap(), chat(10));
+    assert_eq!(server.read_packet::<ChatPacket>().await.unwrap(), chat(100));
+}
@@ -1,4 +1,11 @@
-use crate::{buffer::Buffer, serialization::FieldWriter};
+use tokio::io::{duplex, AsyncRead, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    buffer::Buffer,
+    packets::{MinecraftPacket, PacketDeserializer, PacketSerializer},
+    serialization::{FieldWriter, MinecraftStream, Position, ReadingError}
+};
 
 #[test]
 fn bool_write_true() {
@@ -14,4 +21,144 @@ fn bool_write_false() {
     assert_eq!(buffer.take()[0], 0);
 }
 
+#[test]
+fn i64_write_big_endian() {
+    let mut buffer = Buffer::new(1024);
+    0x0102030405060708_i64.write(&mut buffer);
+    assert_eq!(buffer.take(), [1, 2, 3, 4, 5, 6, 7, 8]);
+}
+
+#[test]
+fn varint_write_negative() {
+    let mut buffer = Buffer::new(1024);
+    (-1).write(&mut buffer);
+    assert_eq!(buffer.take(), [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+}
+
 // todo: make more tests for FieldWriter and FieldReader
+
+#[test]
+fn write_bytes_same_as_write_byte() {
+    let data: Vec<u8> = (0..=255).cycle().take(3000).collect();
+    // the small buffer has to grow several times in one call
+    let mut bulk = Buffer::new(4);
+    bulk.write_byte(7);
+    bulk.write_bytes(&data);
+    bulk.write_bytes(&[]);
+    let mut single = Buffer::new(4);
+    single.write_byte(7);
+    for byte in &data {
+        single.write_byte(*byte);
+    }
+    assert_eq!(bulk.take(), single.take());
+}
+
+#[test]
+fn large_write_resizes_once() {
+    let data: Vec<u8> = (0..=255).cycle().take(100 * 1024).collect();
+    let mut buffer = Buffer::new(1024);
+    buffer.write_byte(1);
+    buffer.write_bytes(&data);
+    assert_eq!(buffer.capacity(), 1 + 100 * 1024);
+    assert_eq!(buffer.take()[0], 1);
+    assert_eq!(&buffer.take()[1..], data);
+    // small writes still double
+    buffer.write_byte(2);
+    assert_eq!(buffer.capacity(), 2 * (1 + 100 * 1024));
+}
+
+#[test]
+fn string_write() {
+    let mut buffer = Buffer::new(2);
+    "mc.example.com".to_string().write(&mut buffer);
+    assert_eq!(buffer.take(), b"\x0emc.example.com");
+}
+
+#[test]
+fn uuid_write() {
+    let uuid = Uuid::from_u128(0x0102030405060708090a0b0c0d0e0f10);
+    let mut buffer = Buffer::new(1024);
+    uuid.write(&mut buffer);
+    assert_eq!(buffer.take(), (1..=16).collect::<Vec<u8>>());
+}
+
+/// Packed values of the example of https://wiki.vg/Protocol#Position and the edges of the ranges
+fn positions() -> [(Position, u64); 5] {
+    [
+        (Position { x: 18357644, y: 831, z: -20882616 }, 0x4607632C15B4833F),
+        (Position { x: 0, y: 0, z: 0 }, 0),
+        (Position { x: -1, y: -1, z: -1 }, 0xFFFFFFFFFFFFFFFF),
+        (Position { x: -33554432, y: -2048, z: -33554432 }, 0x8000002000000800),
+        (Position { x: 33554431, y: 2047, z: 33554431 }, 0x7FFFFFDFFFFFF7FF)
+    ]
+}
+
+#[test]
+fn position_write() {
+    for (position, packed) in positions() {
+        let mut buffer = Buffer::new(1024);
+        position.write(&mut buffer);
+        assert_eq!(buffer.take(), packed.to_be_bytes(), "{position:?}");
+    }
+}
+
+#[derive(PacketDeserializer, PacketSerializer)]
+struct PositionPacket {
+    position: Position
+}
+
+#[tokio::test]
+async fn position_read() {
+    for (position, packed) in positions() {
+        assert_eq!(Position::unpack(packed as i64), position);
+        let (mut client, source) = duplex(64);
+        client.write_all(&MinecraftPacket::make_raw(0, &PositionPacket { position }).unwrap()).await.unwrap();
+        let mut minecraft = MinecraftStream::new(source, 1024);
+        assert_eq!(minecraft.read_packet::<PositionPacket>().await.unwrap().position, position);
+    }
+}
+
+#[test]
+fn float_write_big_endian() {
+    let mut buffer = Buffer::new(1024);
+    1.5_f32.write(&mut buffer);
+    (-2.0_f64).write(&mut buffer);
+    assert_eq!(buffer.take(), [0x3F, 0xC0, 0, 0, 0xC0, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[derive(PacketDeserializer, PacketSerializer)]
+struct FloatsPacket {
+    single: f32,
+    double: f64
+}
+
+/// NaN is not equal to itself, so the bits are compared
+#[tokio::test]
+async fn float_read_special_values() {
+    let values = [
+        (0.0, 0.0),
+        (-0.0, -0.0),
+        (f32::NAN, f64::NAN),
+        (f32::INFINITY, f64::INFINITY),
+        (f32::NEG_INFINITY, f64::NEG_INFINITY),
+        (f32::MIN_POSITIVE, f64::MAX)
+    ];
+    for (single, double) in values {
+        let (mut client, source) = duplex(64);
+        client.write_all(&MinecraftPacket::make_raw(0, &FloatsPacket { single, double }).unwrap()).await.unwrap();
+        let mut minecraft = MinecraftStream::new(source, 1024);
+        let packet = minecraft.read_packet::<FloatsPacket>().await.unwrap();
+        assert_eq!(packet.single.to_bits(), single.to_bits());
+        assert_eq!(packet.double.to_bits(), double.to_bits());
+    }
+}
+
+#[tokio::test]
+async fn float_read_insufficient() {
+    // the packet claims 1 + 4 bytes, but the f64 needs 8 more
+    let (mut client, source) = duplex(64);
+    client.write_all(&[0x05, 0x00, 0x3F, 0xC0, 0, 0]).await.unwrap();
+    let mut minecraft = MinecraftStream::new(source, 1024);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_data_bounded::<FloatsPacket>(signature).await.err(), Some(ReadingError::Invalid));
+}
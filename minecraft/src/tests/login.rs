@@ -0,0 +1,54 @@
+use crate::{
+    login::{LoginPhase, LoginWatcher},
+    packets::{LoginDisconnectS2CPacket, MinecraftPacket, SetCompressionS2CPacket}
+};
+
+fn set_compression(threshold: i32) -> Vec<u8> {
+    MinecraftPacket::make_raw(3, &SetCompressionS2CPacket { threshold }).unwrap()
+}
+
+#[test]
+fn plain_packets_stay_plain() {
+    let mut watcher = LoginWatcher::new();
+    // login plugin request
+    let packet = MinecraftPacket::make_raw(4, &LoginDisconnectS2CPacket { reason: "velocity:player_info".to_string() }).unwrap();
+    assert_eq!(watcher.feed(&packet), Ok(LoginPhase::Plain));
+    assert!(watcher.is_plain());
+}
+
+#[test]
+fn parsing_stops_after_set_compression() {
+    let mut watcher = LoginWatcher::new();
+    // whatever follows is zlib framed and would be invalid as a plain packet
+    let data = [set_compression(256), vec![0xFF; 8]].concat();
+    assert_eq!(watcher.feed(&data), Ok(LoginPhase::Compressed { threshold: 256 }));
+    assert_eq!(watcher.feed(&[0xFF; 8]), Ok(LoginPhase::Compressed { threshold: 256 }));
+    assert!(!watcher.is_plain());
+}
+
+#[test]
+fn set_compression_split_between_reads() {
+    let mut watcher = LoginWatcher::new();
+    let data = set_compression(256);
+    for byte in &data[..data.len() - 1] {
+        assert_eq!(watcher.feed(&[*byte]), Ok(LoginPhase::Plain));
+    }
+    assert_eq!(watcher.feed(&data[data.len() - 1..]), Ok(LoginPhase::Compressed { threshold: 256 }));
+}
+
+#[test]
+fn negative_threshold_stays_plain() {
+    let mut watcher = LoginWatcher::new();
+    assert_eq!(watcher.feed(&set_compression(-1)), Ok(LoginPhase::Plain));
+}
+
+#[test]
+fn encryption_and_success_end_parsing() {
+    let mut watcher = LoginWatcher::new();
+    assert_eq!(watcher.feed(&[1, 1]), Ok(LoginPhase::Encrypted));
+    assert_eq!(watcher.feed(&set_compression(256)), Ok(LoginPhase::Encrypted));
+
+    let mut watcher = LoginWatcher::new();
+    assert_eq!(watcher.feed(&[1, 2]), Ok(LoginPhase::Finished));
+    assert_eq!(watcher.phase(), LoginPhase::Finished);
+}
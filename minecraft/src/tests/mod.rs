@@ -2,3 +2,8 @@
 mod serialization;
 mod truncate_to_zero;
 mod field_types;
+mod login;
+#[cfg(feature = "nbt")]
+mod nbt;
+#[cfg(feature = "compression")]
+mod compression;
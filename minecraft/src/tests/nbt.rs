@@ -0,0 +1,116 @@
+use tokio::io::{duplex, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    buffer::Buffer,
+    nbt::{parse_nbt, NbtTag, MAX_DEPTH},
+    packets::{MinecraftPacket, PacketDeserializer, PacketSerializer},
+    serialization::{FieldWriter, MinecraftStream, ReadingError}
+};
+
+/// `{name: "Bob", list: [1, 2], nested: {id: 5b}}`
+fn compound() -> NbtTag {
+    NbtTag::Compound(vec![
+        ("name".to_string(), NbtTag::String("Bob".to_string())),
+        ("list".to_string(), NbtTag::List(vec![NbtTag::Int(1), NbtTag::Int(2)])),
+        ("nested".to_string(), NbtTag::Compound(vec![("id".to_string(), NbtTag::Byte(5))]))
+    ])
+}
+
+fn compound_bytes() -> Vec<u8> {
+    [
+        &[0x0A][..],
+        &[0x08, 0x00, 0x04], b"name", &[0x00, 0x03], b"Bob",
+        &[0x09, 0x00, 0x04], b"list", &[0x03, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2],
+        &[0x0A, 0x00, 0x06], b"nested", &[0x01, 0x00, 0x02], b"id", &[0x05, 0x00],
+        &[0x00]
+    ].concat()
+}
+
+#[test]
+fn compound_write() {
+    let mut buffer = Buffer::new(16);
+    compound().write(&mut buffer).unwrap();
+    assert_eq!(buffer.take(), compound_bytes());
+}
+
+#[test]
+fn compound_parse() {
+    let data = [compound_bytes(), vec![0xFF]].concat();
+    assert_eq!(parse_nbt(&data), Ok((compound(), data.len() - 1)));
+    assert_eq!(compound().get("nested").and_then(|x| x.get("id")), Some(&NbtTag::Byte(5)));
+}
+
+#[test]
+fn every_type_round_trips() {
+    let tag = NbtTag::Compound(vec![
+        ("short".to_string(), NbtTag::Short(-2)),
+        ("long".to_string(), NbtTag::Long(i64::MIN)),
+        ("float".to_string(), NbtTag::Float(1.5)),
+        ("double".to_string(), NbtTag::Double(-0.25)),
+        ("bytes".to_string(), NbtTag::ByteArray(vec![-1, 0, 1])),
+        ("ints".to_string(), NbtTag::IntArray(vec![i32::MAX, -1])),
+        ("longs".to_string(), NbtTag::LongArray(vec![7])),
+        ("empty".to_string(), NbtTag::List(vec![])),
+        ("lists".to_string(), NbtTag::List(vec![NbtTag::List(vec![NbtTag::String("a".to_string())]), NbtTag::List(vec![])]))
+    ]);
+    let mut buffer = Buffer::new(16);
+    tag.write(&mut buffer).unwrap();
+    let data = buffer.take();
+    assert_eq!(parse_nbt(data), Ok((tag, data.len())));
+}
+
+#[test]
+fn truncated_is_insufficient() {
+    let data = compound_bytes();
+    for length in 0..data.len() {
+        assert_eq!(parse_nbt(&data[..length]), Err(ReadingError::Insufficient), "{length}");
+    }
+}
+
+#[test]
+fn broken_tags_are_invalid() {
+    // unknown type
+    assert_eq!(parse_nbt(&[0x0D]), Err(ReadingError::Invalid));
+    // negative length of an int array
+    assert_eq!(parse_nbt(&[0x0B, 0xFF, 0xFF, 0xFF, 0xFF]), Err(ReadingError::Invalid));
+    // a list of end tags which isn't empty
+    assert_eq!(parse_nbt(&[0x09, 0x00, 0, 0, 0, 1, 0x00]), Err(ReadingError::Invalid));
+    // a name which isn't utf-8
+    assert_eq!(parse_nbt(&[0x0A, 0x01, 0x00, 0x01, 0xFF, 0x00, 0x00]), Err(ReadingError::Invalid));
+}
+
+#[test]
+fn huge_length_is_not_allocated() {
+    assert_eq!(parse_nbt(&[0x0C, 0x7F, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]), Err(ReadingError::Insufficient));
+}
+
+#[test]
+fn deep_nesting_is_invalid() {
+    // lists of one list, the innermost list is empty and `depth` levels below the root
+    let nested = |depth: usize| [vec![0x09], [0x09, 0, 0, 0, 1].repeat(depth), vec![0x00, 0, 0, 0, 0]].concat();
+    assert!(parse_nbt(&nested(MAX_DEPTH)).is_ok());
+    assert_eq!(parse_nbt(&nested(MAX_DEPTH + 1)), Err(ReadingError::Invalid));
+}
+
+#[test]
+fn mixed_list_is_not_written() {
+    let mut buffer = Buffer::new(16);
+    assert_eq!(NbtTag::List(vec![NbtTag::Int(1), NbtTag::Byte(1)]).write(&mut buffer), None);
+}
+
+#[derive(PacketDeserializer, PacketSerializer)]
+struct NbtPacket {
+    tag: NbtTag,
+    after: String
+}
+
+#[tokio::test]
+async fn read_field_from_packet() {
+    let packet = NbtPacket { tag: compound(), after: "end".to_string() };
+    let (mut client, source) = duplex(256);
+    client.write_all(&MinecraftPacket::make_raw(0, &packet).unwrap()).await.unwrap();
+    let mut minecraft = MinecraftStream::new(source, 16);
+    let read = minecraft.read_packet::<NbtPacket>().await.unwrap();
+    assert_eq!(read.tag, compound());
+    assert_eq!(read.after, "end");
+}
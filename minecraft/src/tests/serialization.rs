@@ -1,8 +1,12 @@
-use std::{borrow::BorrowMut, io::Cursor};
+use std::{borrow::BorrowMut, io::{self, Cursor}, pin::Pin, task::{Context, Poll}, time::{Duration, Instant}};
 
-use tokio::io::{AsyncReadExt, AsyncSeekExt, BufStream};
+use tokio::{io::{duplex, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufStream, ReadBuf}, time::timeout};
 
-use crate::{packets::HandshakeC2SPacket, serialization::MinecraftStream};
+use crate::{
+    buffer::Buffer,
+    packets::{packet_buffer_capacity, HandshakeC2SPacket, MinecraftPacket, PacketSerializer, PingRequestC2SPacket, StatusRequestC2SPacket, StatusResponseS2CPacket},
+    serialization::{varint_len, FieldWriter, MinecraftStream, ReadingError, Signature, MAX_PACKET_LENGTH}
+};
 
 #[tokio::test]
 async fn read_handshake() {
@@ -22,6 +26,32 @@ async fn read_handshake() {
     assert_eq!(handshake.next_state, 2);
 }
 
+#[test]
+fn reading_error_messages() {
+    assert_eq!(ReadingError::Insufficient.to_string(), "the data ends in the middle of a field");
+    assert_eq!(ReadingError::Invalid.to_string(), "malformed packet");
+    assert_eq!(ReadingError::Closed.to_string(), "connection closed in the middle of a packet");
+    assert_eq!(ReadingError::ClosedEmpty.to_string(), "connection closed without sending anything");
+    assert_eq!(ReadingError::Timeout.to_string(), "nothing was received in time");
+    let boxed: Box<dyn std::error::Error> = Box::new(ReadingError::Invalid);
+    assert_eq!(boxed.to_string(), "malformed packet");
+}
+
+/// Found by the fuzz target, the reader used to panic
+#[tokio::test]
+async fn read_handshake_with_invalid_utf8() {
+    let array: Vec<u8> = vec![
+        0x09, // signature: packet length
+        0x00, // signature: packet id
+        0x10, // protocol version
+        0x3, 0x6E, 0xFF, 0x74, // domain string
+        0xFF, 0xFF, // server port
+        0x02, // next state
+    ];
+    let mut minecraft = make_minecraft_stream(array);
+    assert_eq!(minecraft.read_packet::<HandshakeC2SPacket>().await.err(), Some(ReadingError::Invalid));
+}
+
 #[tokio::test]
 async fn read_signature() {
     let array: Vec<u8> = vec![
@@ -34,6 +64,20 @@ async fn read_signature() {
     assert_eq!(signature.packet_id, 11);
 }
 
+#[tokio::test]
+async fn read_ping() {
+    let array: Vec<u8> = vec![
+        0x09, // signature: packet length
+        0x01, // signature: packet id
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE, // payload
+    ];
+    let mut minecraft = make_minecraft_stream(array);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 1);
+    let ping = minecraft.read_data::<PingRequestC2SPacket>(signature).await.unwrap();
+    assert_eq!(ping.payload, -2);
+}
+
 #[tokio::test]
 async fn write_packet() {
     let mut stream = BufStream::new(Cursor::new(vec![0; 1024]));
@@ -58,8 +102,440 @@ async fn write_packet() {
     assert_eq!(packet.next_state, 2);
 }
 
+#[tokio::test]
+async fn stalled_source_times_out() {
+    let (mut client, source) = duplex(64);
+    // the length of the packet arrives, the rest never does
+    client.write_all(&[0x09, 0x00, 0x10]).await.unwrap();
+    let mut minecraft = MinecraftStream::new(source, 1024);
+    minecraft.set_read_timeout(Some(Duration::from_millis(50)));
+    let started = Instant::now();
+    let result = minecraft.read_packet::<HandshakeC2SPacket>().await;
+    assert_eq!(result.err(), Some(ReadingError::Timeout));
+    assert!(started.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn closed_source_is_not_timeout() {
+    let (mut client, source) = duplex(64);
+    client.write_all(&[0x09, 0x00]).await.unwrap();
+    drop(client);
+    let mut minecraft = MinecraftStream::new(source, 1024);
+    minecraft.set_read_timeout(Some(Duration::from_secs(5)));
+    assert_eq!(minecraft.read_packet::<HandshakeC2SPacket>().await.err(), Some(ReadingError::Closed));
+}
+
+#[tokio::test]
+async fn close_before_any_byte_is_distinguished() {
+    let (client, source) = duplex(64);
+    drop(client);
+    let mut minecraft = MinecraftStream::new(source, 1024);
+    assert_eq!(minecraft.read_signature().await.err(), Some(ReadingError::ClosedEmpty));
+
+    // a closed length is still a broken packet
+    let (mut client, source) = duplex(64);
+    client.write_all(&[0x80]).await.unwrap();
+    drop(client);
+    let mut minecraft = MinecraftStream::new(source, 1024);
+    assert_eq!(minecraft.read_signature().await.err(), Some(ReadingError::Closed));
+}
+
+#[tokio::test]
+async fn reading_errors_by_operation() {
+    // a length longer than 5 bytes of VarInt
+    let mut minecraft = make_minecraft_stream(vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    assert_eq!(minecraft.read_signature().await.err(), Some(ReadingError::Invalid));
+    // a length of -1
+    let mut minecraft = make_minecraft_stream(vec![0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00]);
+    assert_eq!(minecraft.read_signature().await.err(), Some(ReadingError::Invalid));
+
+    let mut minecraft = make_minecraft_stream(vec![0x09, 0x00, 0x10]);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_data::<HandshakeC2SPacket>(signature).await.err(), Some(ReadingError::Closed));
+
+    let mut minecraft = make_minecraft_stream(vec![]);
+    assert_eq!(minecraft.read_packet::<HandshakeC2SPacket>().await.err(), Some(ReadingError::ClosedEmpty));
+}
+
+#[test]
+fn make_raw_is_same_as_concat() {
+    let handshake = |domain: String| HandshakeC2SPacket {
+        protocol_version: 765,
+        domain,
+        server_port: 25565,
+        next_state: 2
+    };
+    // a long packet grows the reused buffer and needs a two byte length, the next short one must not see its leftovers
+    let status = StatusResponseS2CPacket { json_response: "x".repeat(5000) };
+    assert_eq!(MinecraftPacket::make_raw(0, &status), concat_make_raw(0, &status));
+    for domain in ["mc.example.com".to_string(), "a".repeat(300), String::new()] {
+        assert_eq!(MinecraftPacket::make_raw(0, &handshake(domain.clone())), concat_make_raw(0, &handshake(domain)));
+    }
+    assert_eq!(MinecraftPacket::make_raw(0, &StatusRequestC2SPacket {}), concat_make_raw(0, &StatusRequestC2SPacket {}));
+    let ping = PingRequestC2SPacket { payload: -2 };
+    assert_eq!(MinecraftPacket::make_raw(1, &ping), concat_make_raw(1, &ping));
+    assert_eq!(MinecraftPacket::make_raw(300, &ping), concat_make_raw(300, &ping));
+}
+
+#[test]
+fn large_packet_buffer_is_dropped() {
+    let status = |size: usize| StatusResponseS2CPacket { json_response: "x".repeat(size) };
+    assert_eq!(MinecraftPacket::make_raw(0, &status(20_000)), concat_make_raw(0, &status(20_000)));
+    assert!(packet_buffer_capacity() >= 20_000);
+    assert_eq!(MinecraftPacket::make_raw(0, &status(100_000)), concat_make_raw(0, &status(100_000)));
+    assert_eq!(packet_buffer_capacity(), 1024);
+}
+
+/// `make_raw` as it was before the buffer was reused
+fn concat_make_raw<T>(id: i32, packet: &T) -> Option<Vec<u8>> where T: PacketSerializer {
+    let mut data_buffer = Buffer::new(1024);
+    T::to_raw(packet, &mut data_buffer)?;
+    let mut packet_id_buffer = Buffer::new(5);
+    id.write(&mut packet_id_buffer);
+    let mut packet_length_buffer = Buffer::new(5);
+    let d2 = packet_id_buffer.take();
+    let d3 = data_buffer.take();
+    (d2.len() as i32 + d3.len() as i32).write(&mut packet_length_buffer);
+    let d1 = packet_length_buffer.take();
+    Some([d1, d2, d3].concat())
+}
+
+#[tokio::test]
+async fn bytes_after_handshake_are_kept() {
+    let handshake = MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "localhost".to_string(),
+        server_port: 25565,
+        next_state: 2
+    }).unwrap();
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+    let (mut client, mut server) = duplex(1024);
+    client.write_all(&[handshake.as_slice(), &login].concat()).await.unwrap();
+    let mut minecraft = MinecraftStream::new(server.borrow_mut(), 1024);
+    minecraft.read_packet::<HandshakeC2SPacket>().await.unwrap();
+
+    assert_eq!(minecraft.peek(), login);
+    assert_eq!(minecraft.peek(), login);
+    assert_eq!(minecraft.take_buffer(), login);
+    assert!(minecraft.peek().is_empty());
+    assert!(minecraft.take_buffer().is_empty());
+
+    // the rest is still in the source
+    client.write_all(b"after").await.unwrap();
+    let mut after = [0; 5];
+    server.read_exact(&mut after).await.unwrap();
+    assert_eq!(&after, b"after");
+}
+
+#[tokio::test]
+async fn packet_with_long_id_is_read_without_waiting() {
+    let ping = MinecraftPacket::make_raw(300, &PingRequestC2SPacket { payload: 7 }).unwrap();
+    let (mut client, mut server) = duplex(1024);
+    client.write_all(&ping).await.unwrap();
+    let mut minecraft = MinecraftStream::new(server.borrow_mut(), 1024);
+
+    // the client keeps the connection open, so waiting for one more byte would hang
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 300);
+    let read = timeout(Duration::from_secs(1), minecraft.read_data::<PingRequestC2SPacket>(signature)).await;
+    assert_eq!(read.unwrap().unwrap().payload, 7);
+    assert_eq!(minecraft.data_len(), 0);
+}
+
+#[tokio::test]
+async fn data_len_counts_unread_bytes() {
+    let handshake = MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "localhost".to_string(),
+        server_port: 25565,
+        next_state: 2
+    }).unwrap();
+    let (mut client, mut server) = duplex(1024);
+    client.write_all(&handshake[..handshake.len() - 1]).await.unwrap();
+    let mut minecraft = MinecraftStream::new(server.borrow_mut(), 1024);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.data_len(), handshake.len() - 3);
+
+    // the last byte is missing, so the packet waits for it
+    let read = timeout(Duration::from_millis(100), minecraft.read_data::<HandshakeC2SPacket>(signature)).await;
+    assert!(read.is_err());
+}
+
+#[test]
+fn varint_lengths() {
+    for (value, len) in [(0, 1), (127, 1), (128, 2), (300, 2), (2097151, 3), (2097152, 4), (i32::MAX, 5), (-1, 5)] {
+        let mut buffer = Buffer::new(5);
+        value.write(&mut buffer);
+        assert_eq!(buffer.take().len(), len);
+        assert_eq!(varint_len(value), len);
+    }
+}
+
+fn long_handshake() -> Vec<u8> {
+    MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: format!("mc.example.com\0FML3\0{}", "mod;".repeat(200)),
+        server_port: 25565,
+        next_state: 2
+    }).unwrap()
+}
+
+#[tokio::test]
+async fn buffer_grows_for_long_packet() {
+    let handshake = long_handshake();
+    let mut minecraft = make_minecraft_stream_sized(handshake.clone(), 64);
+    let read = minecraft.read_packet::<HandshakeC2SPacket>().await.unwrap();
+    assert!(read.domain.ends_with("mod;"));
+    assert!(minecraft.capacity() >= handshake.len());
+}
+
+#[tokio::test]
+async fn large_enough_buffer_is_not_expanded() {
+    let mut minecraft = make_minecraft_stream_sized(long_handshake(), 1024);
+    minecraft.read_packet::<HandshakeC2SPacket>().await.unwrap();
+    assert_eq!(minecraft.capacity(), 1024);
+}
+
+#[tokio::test]
+async fn too_long_packet_is_invalid() {
+    let mut length = Buffer::new(5);
+    (MAX_PACKET_LENGTH as i32 + 2).write(&mut length);
+    let mut minecraft = make_minecraft_stream([length.take(), &[0]].concat());
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_data::<HandshakeC2SPacket>(signature).await.err(), Some(ReadingError::Invalid));
+}
+
+fn make_minecraft_stream_sized(array: Vec<u8>, buffer_size: usize) -> MinecraftStream<Cursor<Vec<u8>>> {
+    MinecraftStream::new(Cursor::new(array), buffer_size)
+}
+
+#[tokio::test]
+async fn bounded_read_stops_at_packet_end() {
+    let mut array: Vec<u8> = vec![
+        0x07, // signature: packet length, the port and next state are cut off
+        0x00, // signature: packet id
+        0x10, // protocol version
+        0x3, 0x6E, 0x65, 0x74, // domain string
+    ];
+    // the next packet would have been taken for the port and next state
+    array.extend([0x01, 0x00, 0x02]);
+    let mut minecraft = make_minecraft_stream(array.clone());
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_data_bounded::<HandshakeC2SPacket>(signature).await.err(), Some(ReadingError::Invalid));
+
+    let mut minecraft = make_minecraft_stream(array);
+    let signature = minecraft.read_signature().await.unwrap();
+    // unbounded, the port comes from the next packet
+    assert_eq!(minecraft.read_data::<HandshakeC2SPacket>(signature).await.unwrap().server_port, 256);
+}
+
+#[tokio::test]
+async fn bounded_string_does_not_cross_packet_end() {
+    let array: Vec<u8> = vec![
+        0x05, // signature: packet length
+        0x00, // signature: packet id
+        0x10, // protocol version
+        0x5, 0x6E, 0x65, // domain string, longer than the packet
+        0x74, 0x01, 0x00, 0x02 // next packet
+    ];
+    let mut minecraft = make_minecraft_stream(array);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_data_bounded::<HandshakeC2SPacket>(signature).await.err(), Some(ReadingError::Invalid));
+}
+
+#[tokio::test]
+async fn bounded_read_skips_unknown_tail() {
+    let handshake = MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "localhost".to_string(),
+        server_port: 25565,
+        next_state: 2
+    }).unwrap();
+    let mut array = handshake.clone();
+    array[0] += 2;
+    array.extend([0xAA, 0xBB]);
+    let ping = MinecraftPacket::make_raw(1, &PingRequestC2SPacket { payload: 7 }).unwrap();
+    array.extend(&ping);
+    let mut minecraft = make_minecraft_stream(array);
+    let signature = minecraft.read_signature().await.unwrap();
+    let read = minecraft.read_data_bounded::<HandshakeC2SPacket>(signature).await.unwrap();
+    assert_eq!(read.domain, "localhost");
+    assert_eq!(minecraft.peek(), ping);
+    assert_eq!(minecraft.read_packet::<PingRequestC2SPacket>().await.unwrap().payload, 7);
+}
+
+/// A packet with id 1 and `data` of `length` bytes counting up
+fn large_packet(length: usize) -> (Vec<u8>, Vec<u8>) {
+    let data: Vec<u8> = (0..length).map(|x| x as u8).collect();
+    let mut signature = Buffer::new(10);
+    ((length + 1) as i32).write(&mut signature).unwrap();
+    1.write(&mut signature).unwrap();
+    ([signature.take(), &data].concat(), data)
+}
+
+#[tokio::test]
+async fn large_packet_is_read_in_chunks() {
+    let (packet, data) = large_packet(MAX_PACKET_LENGTH - 1);
+    let (mut client, source) = duplex(4096);
+    let writing = tokio::spawn(async move {
+        client.write_all(&packet).await.unwrap();
+        client.write_all(&[1, 0]).await.unwrap();
+    });
+    let mut minecraft = MinecraftStream::new(source, 256);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 1);
+    let mut chunks = minecraft.data_chunks(signature).unwrap();
+    let mut read = Vec::with_capacity(data.len());
+    while let Some(chunk) = minecraft.read_chunk(&mut chunks).await.unwrap() {
+        assert!(!chunk.is_empty() && chunk.len() <= 256);
+        read.extend_from_slice(chunk);
+    }
+    assert_eq!(chunks.remaining(), 0);
+    assert!(read == data);
+    assert_eq!(minecraft.capacity(), 256);
+    // the next packet follows right after the last chunk
+    assert_eq!(minecraft.read_signature().await.unwrap().packet_id, 0);
+    writing.await.unwrap();
+}
+
+#[tokio::test]
+async fn chunks_start_with_buffered_bytes() {
+    let (packet, data) = large_packet(100);
+    let mut minecraft = make_minecraft_stream_sized(packet, 1024);
+    let signature = minecraft.read_signature().await.unwrap();
+    let mut chunks = minecraft.data_chunks(signature).unwrap();
+    assert_eq!(minecraft.read_chunk(&mut chunks).await.unwrap(), Some(data.as_slice()));
+    assert_eq!(minecraft.read_chunk(&mut chunks).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn chunks_of_truncated_packet() {
+    let (packet, _) = large_packet(100);
+    let mut minecraft = make_minecraft_stream_sized(packet[..50].to_vec(), 16);
+    let signature = minecraft.read_signature().await.unwrap();
+    let mut chunks = minecraft.data_chunks(signature).unwrap();
+    let mut read = 0;
+    let result = loop {
+        match minecraft.read_chunk(&mut chunks).await {
+            Ok(Some(chunk)) => read += chunk.len(),
+            x => break x.map(|x| x.map(|x| x.to_vec()))
+        }
+    };
+    assert_eq!(result, Err(ReadingError::Closed));
+    assert_eq!(read + chunks.remaining(), 100);
+    let too_long = Signature { length: MAX_PACKET_LENGTH + 2, packet_id: 1 };
+    assert_eq!(minecraft.data_chunks(too_long), Err(ReadingError::Invalid));
+}
+
 fn make_minecraft_stream(array: Vec<u8>) -> MinecraftStream<BufStream<Cursor<Vec<u8>>>> {
     let stream = BufStream::new(Cursor::new(array.clone()));
     
     MinecraftStream::new(stream, 1024)
 }
+
+/// Gives `data` one byte per read, every other read waits first, like a peer sending each byte in its own segment
+struct Trickle {
+    data: Vec<u8>,
+    position: usize,
+    ready: bool
+}
+
+impl AsyncRead for Trickle {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if !self.ready {
+            self.ready = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.ready = false;
+        if let Some(&byte) = self.data.get(self.position) {
+            buf.put_slice(&[byte]);
+            self.position += 1;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Trickle {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn handshake_arrives_byte_by_byte() {
+    let handshake = HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "play.example.com\0FML3\0".to_string(),
+        server_port: 25565,
+        next_state: 2
+    };
+    let raw = MinecraftPacket::make_raw(0, &handshake).unwrap();
+    // the buffer boundary falls on every byte of the packet for one of the sizes
+    for buffer_size in 1..=raw.len() + 1 {
+        let source = Trickle { data: [raw.as_slice(), &raw].concat(), position: 0, ready: false };
+        let mut minecraft = MinecraftStream::new(source, buffer_size);
+        for _ in 0..2 {
+            let signature = minecraft.read_signature().await.unwrap();
+            let received = minecraft.read_data_bounded::<HandshakeC2SPacket>(signature).await.unwrap();
+            assert_eq!(received.domain, handshake.domain, "{buffer_size}");
+            assert_eq!(received.server_port, 25565);
+            assert_eq!(received.next_state, 2);
+        }
+        assert_eq!(minecraft.read_signature().await.err(), Some(ReadingError::Closed));
+    }
+}
+
+#[tokio::test]
+async fn partial_string_is_not_consumed() {
+    let (mut client, source) = duplex(64);
+    // the length of the string says 3 bytes, only 2 of them arrived
+    client.write_all(&[0x05, 0x00, 0x03, b'n', b'e']).await.unwrap();
+    let mut minecraft = MinecraftStream::new(source, 64);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_field::<String>().err(), Some(ReadingError::Insufficient));
+    assert_eq!(minecraft.peek(), [0x03, b'n', b'e']);
+
+    client.write_all(b"t").await.unwrap();
+    assert_eq!(minecraft.read_raw_data(signature).await.unwrap(), [0x03, b'n', b'e', b't']);
+}
+
+#[tokio::test]
+async fn negative_string_length_is_invalid() {
+    let mut minecraft = make_minecraft_stream(vec![0x06, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    minecraft.read_signature().await.unwrap();
+    assert_eq!(minecraft.read_field::<String>().err(), Some(ReadingError::Invalid));
+}
+
+#[tokio::test]
+async fn raw_packet_keeps_every_byte() {
+    // the length 11 takes two bytes instead of one, the domain ends with null bytes
+    let packet = [0x8B, 0x00, 0x00, 0x10, 0x05, b'n', b'e', b't', 0x00, 0x00, 0xFF, 0xFF, 0x02];
+    let next = [0x01, 0x00];
+    for buffer_size in 1..=packet.len() + 1 {
+        let source = Trickle { data: [packet.as_slice(), &next].concat(), position: 0, ready: false };
+        let mut minecraft = MinecraftStream::new(source, buffer_size);
+        assert_eq!(minecraft.read_raw_packet().await.unwrap(), packet, "{buffer_size}");
+        assert_eq!(minecraft.read_raw_packet().await.unwrap(), next);
+        assert_eq!(minecraft.read_raw_packet().await.err(), Some(ReadingError::Closed));
+    }
+}
+
+#[tokio::test]
+async fn raw_packet_errors() {
+    assert_eq!(make_minecraft_stream(vec![]).read_raw_packet().await.err(), Some(ReadingError::ClosedEmpty));
+    assert_eq!(make_minecraft_stream(vec![0x05, 0x00, 0x01]).read_raw_packet().await.err(), Some(ReadingError::Closed));
+    assert_eq!(make_minecraft_stream(vec![0xFF, 0xFF, 0xFF, 0xFF, 0x0F]).read_raw_packet().await.err(), Some(ReadingError::Invalid));
+    assert_eq!(make_minecraft_stream(vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).read_raw_packet().await.err(), Some(ReadingError::Invalid));
+    let mut length = Buffer::new(5);
+    (MAX_PACKET_LENGTH as i32 + 1).write(&mut length);
+    assert_eq!(make_minecraft_stream(length.take().to_vec()).read_raw_packet().await.err(), Some(ReadingError::Invalid));
+}
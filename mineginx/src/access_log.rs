@@ -0,0 +1,91 @@
+use std::{io, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use log::error;
+use tokio::{fs::{File, OpenOptions}, io::AsyncWriteExt, sync::mpsc::{self, UnboundedReceiver, UnboundedSender}};
+
+enum Message {
+    Line(String),
+    Reopen
+}
+
+/// One line per finished session, appended to a file by a background task
+#[derive(Clone)]
+pub struct AccessLog {
+    sender: UnboundedSender<Message>
+}
+
+pub struct Session<'a> {
+    pub client: &'a str,
+    pub domain: &'a str,
+    pub upstream: &'a str,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duration: Duration
+}
+
+impl AccessLog {
+    pub async fn open(path: &Path) -> io::Result<AccessLog> {
+        let file = open_file(path).await?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_lines(path.to_path_buf(), file, receiver));
+        Ok(AccessLog { sender })
+    }
+
+    pub fn write(&self, session: &Session) {
+        _ = self.sender.send(Message::Line(format_session(session, SystemTime::now())));
+    }
+
+    /// Opens the file by its path again, so a rotated file is not written anymore
+    pub fn reopen(&self) {
+        _ = self.sender.send(Message::Reopen);
+    }
+}
+
+/// `{unix time with millis} client={ip} domain={domain} upstream={proxy_pass} bytes_in={n} bytes_out={n} duration_ms={n}`  
+/// The fields are escaped by `escape_field`, the domain comes from the client and may have spaces or line breaks
+pub fn format_session(session: &Session, time: SystemTime) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "{}.{:03} client={} domain={} upstream={} bytes_in={} bytes_out={} duration_ms={}",
+        time.as_secs(),
+        time.subsec_millis(),
+        escape_field(session.client),
+        escape_field(session.domain),
+        escape_field(session.upstream),
+        session.bytes_in,
+        session.bytes_out,
+        session.duration.as_millis())
+}
+
+/// Percent-encodes `%`, spaces, control characters and everything outside ASCII, so a field can't split or add lines
+pub fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for byte in field.bytes() {
+        match byte {
+            b'%' => escaped.push_str("%25"),
+            0x21..=0x7E => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("%{byte:02X}"))
+        }
+    }
+    escaped
+}
+
+async fn open_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path).await
+}
+
+async fn write_lines(path: PathBuf, mut file: File, mut receiver: UnboundedReceiver<Message>) {
+    while let Some(message) = receiver.recv().await {
+        match message {
+            Message::Line(line) => {
+                if let Err(err) = file.write_all(format!("{line}\n").as_bytes()).await {
+                    error!("failed to write access log '{}': {err}", path.display());
+                }
+            },
+            Message::Reopen => match open_file(&path).await {
+                Ok(x) => file = x,
+                Err(err) => error!("failed to reopen access log '{}': {err}", path.display())
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{env, fs, future::Future, path::Path, process::ExitCode, sync::Arc};
+use log::{error, info, warn};
+
+use crate::{
+    cli::parse_args,
+    config::{
+        read_config_file, serialize_config, server_name_warnings, validate,
+        ConfigFormat, MinecraftServerDescription, MineginxConfig
+    },
+    probe::probe_upstreams,
+    Proxy, Shutdown, StartError
+};
+
+/// Exit codes of the binary, so process supervisors can tell the failures apart
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Exit {
+    Ok = 0,
+    /// Invalid command line, the config can't be read or has errors, or the access log, bans file or a geoip database can't be opened
+    InvalidConfig = 1,
+    /// There is no config file and the default one can't be written
+    ConfigGeneration = 2,
+    /// None of the `listen` addresses could be bound
+    NoListeners = 3,
+    /// Sessions were still open after `shutdown_grace_ms` and were aborted
+    ForcedShutdown = 4,
+    /// `--probe` didn't get the status of some `proxy_pass`
+    Unreachable = 5
+}
+
+impl From<Exit> for ExitCode {
+    fn from(value: Exit) -> Self {
+        ExitCode::from(value as u8)
+    }
+}
+
+impl From<&StartError> for Exit {
+    fn from(value: &StartError) -> Self {
+        match value {
+            StartError::NoListeners => Exit::NoListeners,
+            StartError::AccessLog(..) | StartError::Bans(..) | StartError::GeoIp(..) => Exit::InvalidConfig
+        }
+    }
+}
+
+impl From<Shutdown> for Exit {
+    fn from(value: Shutdown) -> Self {
+        match value {
+            Shutdown::Graceful => Exit::Ok,
+            Shutdown::Forced => Exit::ForcedShutdown
+        }
+    }
+}
+
+pub async fn get_config(path: &Path) -> Option<MineginxConfig> {
+    match read_config_file(path) {
+        Ok(x) => Some(x),
+        Err(err) => {
+            error!("{err}");
+            None
+        }
+    }
+}
+
+pub async fn generate_config(path: &Path) -> Option<MineginxConfig> {
+    info!("generate new configuration file: '{}'", path.display());
+    let default_server = MinecraftServerDescription {
+        listen: "0.0.0.0:25565".into(),
+        server_names: vec!["mineginx.localhost".to_string()],
+        proxy_pass: "127.0.0.1:7878".to_string(),
+        ..Default::default()
+    };
+    let servers: Vec<MinecraftServerDescription> = vec![default_server];
+    let config = MineginxConfig {
+        handshake_timeout_ms: Some(30_000),
+        servers,
+        ..Default::default()
+    };
+    let data = match serialize_config(&config, ConfigFormat::from_path(path)) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("failed to serialize default configuration: {}", err);
+            return None;
+        }
+    };
+
+    if let Some(directory) = path.parent().filter(|x| !x.as_os_str().is_empty() && !x.exists()) {
+        if let Err(err) = fs::create_dir_all(directory) {
+            error!("failed to create config directory: {}", err);
+            return None;
+        };
+    }
+    if let Err(err) = fs::write(path, data) {
+        error!("failed to save default configuration: {}", err);
+        return None;
+    }
+
+    Some(config)
+}
+
+async fn check_config(path: &Path) -> Option<MineginxConfig> {
+    info!("trying to parse config and exit");
+    let config = match get_config(path).await {
+        Some(x) => x,
+        None => {
+            error!("there are some errors");
+            return None;
+        }
+    };
+    for warning in server_name_warnings(&config) {
+        warn!("{warning}");
+    }
+    let errors = validate(&config).await;
+    if !errors.is_empty() {
+        for err in &errors {
+            error!("{err}");
+        }
+        error!("there are some errors");
+        return None;
+    }
+    info!("it's fine! let's try to run");
+    Some(config)
+}
+
+/// Prints the config with all defaults filled in as yaml to stdout
+async fn dump_config(path: &Path) -> Option<()> {
+    let config = get_config(path).await?.with_defaults();
+    match serialize_config(&config, ConfigFormat::Yaml) {
+        Ok(yaml) => {
+            print!("{yaml}");
+            Some(())
+        },
+        Err(err) => {
+            error!("failed to serialize config: {err}");
+            None
+        }
+    }
+}
+
+/// Logs whether each `proxy_pass` answers a status request and how long it took  
+/// `None` if the config can't be used, `Some(false)` if an upstream didn't answer
+async fn probe(path: &Path) -> Option<bool> {
+    let config = get_config(path).await?;
+    let errors = validate(&config).await;
+    if !errors.is_empty() {
+        for err in &errors {
+            error!("{err}");
+        }
+        return None;
+    }
+    let mut reachable = true;
+    for probe in probe_upstreams(Arc::new(config)).await {
+        match probe.result {
+            Ok(latency) => info!("{} is reachable, status in {}ms", probe.proxy_pass, latency.as_millis()),
+            Err(err) => {
+                error!("{} is unreachable: {err}", probe.proxy_pass);
+                reachable = false;
+            }
+        }
+    }
+    Some(reachable)
+}
+
+/// Everything the binary does after the logger is set up  
+/// `args` are without the program name, the proxy runs until `shutdown` completes
+pub async fn run<I, F>(args: I, env_config: Option<String>, shutdown: F) -> Exit where I: IntoIterator<Item = String>, F: Future<Output = ()> {
+    let options = match parse_args(args, env_config) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("{err}");
+            error!("usage: mineginx [-t] [--dump-config] [--probe] [-c|--config <path>]");
+            return Exit::InvalidConfig;
+        }
+    };
+    if options.dump_config {
+        return match dump_config(&options.config_path).await {
+            Some(_) => Exit::Ok,
+            None => Exit::InvalidConfig
+        };
+    }
+    if options.probe {
+        return match probe(&options.config_path).await {
+            Some(true) => Exit::Ok,
+            Some(false) => Exit::Unreachable,
+            None => Exit::InvalidConfig
+        };
+    }
+    if options.check_config {
+        return match check_config(&options.config_path).await {
+            Some(_) => Exit::Ok,
+            None => Exit::InvalidConfig
+        };
+    }
+
+    info!("mineginx version: {} ({})", env!("MINEGINX_VERSION"), env!("MINEGINX_HASH"));
+    // a broken config is reported rather than replaced with the default one
+    let config = if options.config_path.exists() {
+        match get_config(&options.config_path).await {
+            Some(x) => x,
+            None => return Exit::InvalidConfig
+        }
+    }
+    else {
+        match generate_config(&options.config_path).await {
+            Some(x) => x,
+            None => return Exit::ConfigGeneration
+        }
+    };
+    for warning in server_name_warnings(&config) {
+        warn!("{warning}");
+    }
+    let errors = validate(&config).await;
+    if !errors.is_empty() {
+        for err in &errors {
+            error!("{err}");
+        }
+        return Exit::InvalidConfig;
+    }
+    match Proxy::new(config).with_config_path(options.config_path).run(shutdown).await {
+        Ok(Shutdown::Graceful) => {
+            info!("shutdown");
+            Exit::Ok
+        },
+        Ok(Shutdown::Forced) => {
+            warn!("shutdown, sessions were aborted");
+            Exit::ForcedShutdown
+        },
+        Err(err) => {
+            error!("{err}");
+            Exit::from(&err)
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashSet, fmt, fs, io, net::{IpAddr, Ipv4Addr, Ipv6Addr}, path::{Path, PathBuf}, str::FromStr, sync::{Arc, Mutex, RwLock}};
+
+/// Addresses whose clients are disconnected right after accepting, see `ban` of the control socket  
+/// The set in memory is checked for each client, `bans_file` only keeps it between restarts
+#[derive(Default)]
+pub struct Bans {
+    entries: Arc<RwLock<HashSet<BanEntry>>>,
+    file: Option<PathBuf>,
+    /// Taken while the file is rewritten, so the last write has the latest set
+    saving: Arc<Mutex<()>>
+}
+
+/// One address like `1.2.3.4` or a network like `10.0.0.0/8` and `2001:db8::/32`  
+/// IPv4-mapped IPv6 addresses are kept as IPv4, so `::ffff:1.2.3.4` and `1.2.3.4` are the same ban
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BanEntry {
+    network: IpAddr,
+    prefix: u8
+}
+
+impl BanEntry {
+    pub fn new(ip: IpAddr, prefix: u8) -> Option<BanEntry> {
+        let (ip, prefix) = match ip {
+            IpAddr::V6(x) if x.to_ipv4_mapped().is_some() => match prefix.checked_sub(96) {
+                Some(prefix) => (ip.to_canonical(), prefix),
+                // wider than the mapped range, so it also covers other IPv6 addresses
+                None => (ip, prefix)
+            },
+            _ => (ip, prefix)
+        };
+        let network = match ip {
+            IpAddr::V4(x) if prefix <= 32 => IpAddr::V4(Ipv4Addr::from_bits(x.to_bits() & mask_v4(prefix))),
+            IpAddr::V6(x) if prefix <= 128 => IpAddr::V6(Ipv6Addr::from_bits(x.to_bits() & mask_v6(prefix))),
+            _ => return None
+        };
+        Some(BanEntry { network, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => ip.to_bits() & mask_v4(self.prefix) == network.to_bits(),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => ip.to_bits() & mask_v6(self.prefix) == network.to_bits(),
+            // an IPv6 network wide enough to cover the mapped range, such as ::/0
+            (IpAddr::V6(network), IpAddr::V4(ip)) => ip.to_ipv6_mapped().to_bits() & mask_v6(self.prefix) == network.to_bits(),
+            (IpAddr::V4(_), IpAddr::V6(_)) => false
+        }
+    }
+
+    fn is_single(&self) -> bool {
+        self.prefix == if self.network.is_ipv4() { 32 } else { 128 }
+    }
+}
+
+impl From<IpAddr> for BanEntry {
+    fn from(ip: IpAddr) -> BanEntry {
+        let ip = ip.to_canonical();
+        BanEntry { network: ip, prefix: if ip.is_ipv4() { 32 } else { 128 } }
+    }
+}
+
+impl FromStr for BanEntry {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<BanEntry, ()> {
+        match s.split_once('/') {
+            Some((ip, prefix)) => {
+                let ip = ip.parse().map_err(|_| ())?;
+                // only digits, so `+8` and ` 8` are not accepted
+                if prefix.is_empty() || !prefix.bytes().all(|x| x.is_ascii_digit()) {
+                    return Err(());
+                }
+                BanEntry::new(ip, prefix.parse().map_err(|_| ())?).ok_or(())
+            },
+            None => s.parse::<IpAddr>().map(BanEntry::from).map_err(|_| ())
+        }
+    }
+}
+
+impl fmt::Display for BanEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.is_single() {
+            true => write!(f, "{}", self.network),
+            false => write!(f, "{}/{}", self.network, self.prefix)
+        }
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+impl Bans {
+    /// A missing file is created on the first ban
+    pub fn load(path: &Path) -> io::Result<Bans> {
+        let entries = match fs::read(path) {
+            Ok(data) => parse(&data)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err)
+        };
+        Ok(Bans {
+            entries: Arc::new(RwLock::new(entries)),
+            file: Some(path.to_path_buf()),
+            saving: Arc::default()
+        })
+    }
+
+    /// `ip` may be IPv4-mapped, as clients of a dual-stack `[::]` listener are
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.entries.read().unwrap().iter().any(|x| x.contains(ip))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The ban applies even if the file can't be written, the error is about the file only
+    pub async fn add(&self, entry: BanEntry) -> io::Result<()> {
+        self.entries.write().unwrap().insert(entry);
+        self.save().await
+    }
+
+    /// `false` if exactly this `entry` is not banned, an address inside a banned network is not removed
+    pub async fn remove(&self, entry: &BanEntry) -> io::Result<bool> {
+        if !self.entries.write().unwrap().remove(entry) {
+            return Ok(false);
+        }
+        self.save().await.map(|_| true)
+    }
+
+    /// Writes a temporary file next to `bans_file` and renames it, so the file is never half written  
+    /// The disk is slow at times, so it is written on a blocking thread, which finishes the write even if the caller is gone
+    async fn save(&self) -> io::Result<()> {
+        let path = match &self.file {
+            Some(x) => x.clone(),
+            None => return Ok(())
+        };
+        let (entries, saving) = (self.entries.clone(), self.saving.clone());
+        tokio::task::spawn_blocking(move || {
+            let _saving = saving.lock().unwrap();
+            let mut entries: Vec<String> = entries.read().unwrap().iter().map(|x| x.to_string()).collect();
+            entries.sort();
+            let data = serde_json::to_vec_pretty(&entries).map_err(io::Error::other)?;
+            let mut temporary = path.clone().into_os_string();
+            temporary.push(".tmp");
+            fs::write(&temporary, data)?;
+            fs::rename(&temporary, path)
+        }).await.map_err(io::Error::other)?
+    }
+}
+
+/// A json array of addresses and networks, like `["1.2.3.4", "10.0.0.0/8", "2001:db8::/32"]`
+fn parse(data: &[u8]) -> io::Result<HashSet<BanEntry>> {
+    let entries: Vec<String> = serde_json::from_slice(data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    entries.iter()
+        .map(|x| x.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid ip '{x}'"))))
+        .collect()
+}
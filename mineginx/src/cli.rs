@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+pub const DEFAULT_CONFIG_FILE: &str = "./config/mineginx.yaml";
+pub const CONFIG_ENV: &str = "MINEGINX_CONFIG";
+
+#[derive(PartialEq, Debug)]
+pub struct Options {
+    /// `-t`: check the config and exit
+    pub check_config: bool,
+    /// `--dump-config`: print the config with all defaults filled in and exit
+    pub dump_config: bool,
+    /// `--probe`: fetch the status of each `proxy_pass` and exit
+    pub probe: bool,
+    pub config_path: PathBuf
+}
+
+/// `args` without the program name  
+/// The config path is taken from `-c`/`--config`, then from `env_config`, then the default one
+pub fn parse_args<I>(args: I, env_config: Option<String>) -> Result<Options, String> where I: IntoIterator<Item = String> {
+    let mut check_config = false;
+    let mut dump_config = false;
+    let mut probe = false;
+    let mut config_path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-t" => check_config = true,
+            "--dump-config" => dump_config = true,
+            "--probe" => probe = true,
+            "-c" | "--config" => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => return Err(format!("{arg} requires a path"))
+            },
+            _ => return Err(format!("unknown argument '{arg}'"))
+        }
+    }
+    let config_path = config_path
+        .or(env_config.filter(|x| !x.is_empty()).map(PathBuf::from))
+        .unwrap_or(PathBuf::from(DEFAULT_CONFIG_FILE));
+    Ok(Options {
+        check_config,
+        dump_config,
+        probe,
+        config_path
+    })
+}
@@ -1,16 +1,968 @@
-use serde::{Serialize, Deserialize};
+use std::{collections::HashMap, env, hash::{BuildHasher, Hasher, RandomState}, fmt::Display, fs, net::Ipv6Addr, path::Path, sync::{Arc, RwLock}, time::Duration};
 
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::net::lookup_host;
+
+use crate::{listener::UNIX_PREFIX, srv::is_domain_name, upstream::ConnectOptions};
+
+pub const MAX_BUFFER_SIZE: u32 = 1024 * 1024;
+pub const DEFAULT_PORT: u16 = 25565;
+pub const DEFAULT_HANDSHAKE_TIMEOUT_MS: u64 = 10_000;
+pub const DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS: u64 = 10_000;
+pub const DEFAULT_UPSTREAM_RETRY_BACKOFF_MS: u64 = 100;
+pub const DEFAULT_UPSTREAM_WRITE_TIMEOUT_MS: u64 = 10_000;
+pub const DEFAULT_BUFFER_SIZE: u32 = 2048;
+pub const DEFAULT_HANDSHAKE_BUFFER_SIZE: u32 = 4096;
+pub const DEFAULT_UNSUPPORTED_VERSION_MESSAGE: &str = "Please use a supported version of Minecraft";
+pub const TRANSFER_DENIED_MESSAGE: &str = "Transfers to this server are not allowed";
+pub const DEFAULT_ON_DEMAND_START_TIMEOUT_MS: u64 = 25_000;
+pub const DEFAULT_ON_DEMAND_POLL_INTERVAL_MS: u64 = 500;
+pub const DEFAULT_ON_DEMAND_STARTING_MESSAGE: &str = "The server is starting, please join again in a minute";
+
+/// One address or a list of them, all of them route to the same server
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum Listen {
+    Single(String),
+    Multiple(Vec<String>)
+}
+
+impl Listen {
+    pub fn addresses(&self) -> &[String] {
+        match self {
+            Listen::Single(address) => std::slice::from_ref(address),
+            Listen::Multiple(addresses) => addresses
+        }
+    }
+
+    fn addresses_mut(&mut self) -> &mut [String] {
+        match self {
+            Listen::Single(address) => std::slice::from_mut(address),
+            Listen::Multiple(addresses) => addresses
+        }
+    }
+}
+
+impl MinecraftServerDescription {
+    pub fn allows_protocol_version(&self, protocol_version: i32) -> bool {
+        if self.blocks_protocol_version(protocol_version) {
+            return false;
+        }
+        match &self.allowed_protocol_versions {
+            Some(versions) => versions.contains(&protocol_version),
+            None => true
+        }
+    }
+
+    pub fn blocks_protocol_version(&self, protocol_version: i32) -> bool {
+        self.blocked_protocol_versions.as_ref().is_some_and(|x| x.contains(&protocol_version))
+    }
+
+    pub fn unsupported_version_message(&self) -> &str {
+        self.unsupported_version_message.as_deref().unwrap_or(DEFAULT_UNSUPPORTED_VERSION_MESSAGE)
+    }
+
+    pub fn status_cache_ttl(&self) -> Option<Duration> {
+        self.status_cache_ttl_ms.map(Duration::from_millis)
+    }
+
+    /// Addresses of the pool, or `proxy_pass` if there is no pool, none for `proxy_pass_srv`
+    pub fn upstream_addresses(&self) -> Vec<&String> {
+        match &self.proxy_pass_pool {
+            Some(pool) => pool.iter().map(|x| &x.addr).collect(),
+            // the target of the record is known only when a client comes
+            None if self.proxy_pass_srv.is_some() && self.proxy_pass.is_empty() => vec![],
+            None => vec![&self.proxy_pass]
+        }
+    }
+
+    /// `name (proxy_pass)` if the server is named, otherwise just `proxy_pass`
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{name} ({})", self.proxy_pass),
+            None => self.proxy_pass.clone()
+        }
+    }
+}
+
+/// A regex of `server_name_patterns`, compiled when the config is parsed  
+/// It has to match the whole domain, `mc[0-9]+\.example\.com` doesn't match `eu.mc1.example.com`
+#[derive(Debug, Clone)]
+pub struct ServerNamePattern {
+    source: String,
+    case_sensitive: Regex,
+    case_insensitive: Regex
+}
+
+impl ServerNamePattern {
+    pub fn new(source: &str) -> Result<ServerNamePattern, regex::Error> {
+        let anchored = format!("^(?:{source})$");
+        Ok(ServerNamePattern {
+            source: source.to_string(),
+            case_sensitive: RegexBuilder::new(&anchored).build()?,
+            case_insensitive: RegexBuilder::new(&anchored).case_insensitive(true).build()?
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, domain: &str, case_insensitive: bool) -> bool {
+        match case_insensitive {
+            true => self.case_insensitive.is_match(domain),
+            false => self.case_sensitive.is_match(domain)
+        }
+    }
+}
+
+impl PartialEq for ServerNamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for ServerNamePattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerNamePattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let source = String::deserialize(deserializer)?;
+        ServerNamePattern::new(&source)
+            .map_err(|err| serde::de::Error::custom(format!("invalid server_name_pattern '{source}': {err}")))
+    }
+}
+
+impl Default for Listen {
+    fn default() -> Self {
+        Listen::Multiple(vec![])
+    }
+}
+
+impl From<&str> for Listen {
+    fn from(value: &str) -> Self {
+        Listen::Single(value.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct MinecraftServerDescription {
-    pub listen: String,
+    /// Human-readable identification for logs, like "eu-lobby"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub listen: Listen,
+    #[serde(default)]
     pub server_names: Vec<String>,
+    /// Regexes for domains which match neither `server_names` nor their wildcards
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name_patterns: Option<Vec<ServerNamePattern>>,
+    /// May be omitted if there is `proxy_pass_pool` or `proxy_pass_srv`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub proxy_pass: String,
+    /// Upstreams which share the clients by their weights instead of the single `proxy_pass`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_pass_pool: Option<Vec<PoolBackend>>,
+    /// SRV record like `_minecraft._tcp.example.com` whose target is the upstream instead of `proxy_pass`, it is looked up again once its TTL passes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_pass_srv: Option<String>,
+    /// Players joining again go to the backend of `proxy_pass_pool` they got before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sticky_sessions: Option<bool>,
+    /// Initial size of the buffer of each direction of a session, it grows while the reads fill it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<u32>,
+    /// Overrides the global `handshake_timeout_ms` for this server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_timeout_ms: Option<u64>,
+    /// Limits each direction of every connection to this server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth_bytes_per_sec: Option<u64>,
+    /// Overrides the global `upstream_connect_retries` for this server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_retries: Option<u32>,
+    /// Overrides the global `upstream_retry_backoff_ms` for this server
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub buffer_size: Option<u32>
+    pub connect_retry_delay_ms: Option<u64>,
+    /// Logs the first bytes of both directions as hex at trace level
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_dump: Option<bool>,
+    /// Which addresses of `proxy_pass` are tried first, the others are a fallback
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_family: Option<AddressFamily>,
+    /// Resolves `proxy_pass` in background with this interval instead of on each connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolve_interval_ms: Option<u64>,
+    /// Answers clients of this server without connecting to `proxy_pass`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Maintenance>,
+    /// Players with other protocol versions are kicked, status requests are not checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_protocol_versions: Option<Vec<i32>>,
+    /// Players with these protocol versions are kicked, and their status requests are closed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_protocol_versions: Option<Vec<i32>>,
+    /// Kick message for players with a protocol version not in `allowed_protocol_versions` or in `blocked_protocol_versions`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsupported_version_message: Option<String>,
+    /// Starts `proxy_pass` when a player joins while it is down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_demand: Option<OnDemand>,
+    /// Clients of the `listen` addresses come through a load balancer which sends a PROXY protocol header first
+    #[serde(skip_serializing_if = "Option::is_none", alias = "expect_proxy_protocol")]
+    pub accept_proxy_protocol: Option<bool>,
+    /// Connections to `proxy_pass` start with a PROXY protocol header carrying the client address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+    /// Replaces the host of the handshake domain sent to `proxy_pass`, for backends which only accept their own virtual host
+    #[serde(skip_serializing_if = "Option::is_none", alias = "rewrite_host")]
+    pub upstream_hostname: Option<String>,
+    /// Replaces `server_port` of the handshake sent to `proxy_pass`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrite_port: Option<u16>,
+    /// Forwards the handshake exactly as the client sent it instead of encoding it again  
+    /// For modded clients, such as Forge, whose backends check the markers after the domain byte by byte
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_handshake: Option<bool>,
+    /// Overrides the global `tcp_nodelay` for the connection to `proxy_pass`, the client side keeps the global one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tcp_nodelay: Option<bool>,
+    /// `SO_SNDBUF` of the connection to `proxy_pass`, the system default if omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_send_buffer_size: Option<u32>,
+    /// `SO_RCVBUF` of the connection to `proxy_pass`, the system default if omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_recv_buffer_size: Option<u32>,
+    /// Overrides the global `allow_transfer` for this server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_transfer: Option<bool>,
+    /// Status requests are answered from the last response of `proxy_pass` for this long
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_cache_ttl_ms: Option<u64>,
+    /// Clients from these countries are disconnected, ISO codes like `US`, see `geoip_country_database`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_countries: Option<Vec<String>>,
+    /// Clients from these autonomous systems are disconnected, see `geoip_asn_database`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_asns: Option<Vec<u32>>
+}
+
+/// One upstream of `proxy_pass_pool`
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Default)]
+pub struct PoolBackend {
+    pub addr: String,
+    /// Share of the clients relative to the other backends of the pool, 1 by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+impl PoolBackend {
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+}
+
+/// The PROXY protocol header mineginx sends, the text one or the binary one
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    /// In the order returned by the resolver
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Maintenance {
+    /// Shown to players trying to join
+    pub kick_message: String,
+    /// Shown in the server list
+    pub motd: String,
+    /// Adds the version of mineginx to the status as `mineginx`, so the operator can check which one answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_version: Option<bool>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct OnDemand {
+    /// Shell command which starts the server, it runs once per `start_timeout_ms` however many players wait  
+    /// Without it the player just waits, for servers started by something else, such as a scaler watching the connections
+    #[serde(default, alias = "start_command", skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// How long a player waits for the server to come up, 25 seconds by default  
+    /// Clients before 1.13 can't be kept past their 30 second timeout, so they wait 25 seconds at most
+    #[serde(alias = "max_wait_ms", skip_serializing_if = "Option::is_none")]
+    pub start_timeout_ms: Option<u64>,
+    /// Shown to players if the server doesn't come up in time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starting_message: Option<String>,
+    /// The pause between attempts to connect the starting server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_interval_ms: Option<u64>
+}
+
+impl OnDemand {
+    pub fn start_timeout(&self) -> Duration {
+        Duration::from_millis(self.start_timeout_ms.unwrap_or(DEFAULT_ON_DEMAND_START_TIMEOUT_MS))
+    }
+
+    pub fn starting_message(&self) -> &str {
+        self.starting_message.as_deref().unwrap_or(DEFAULT_ON_DEMAND_STARTING_MESSAGE)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.unwrap_or(DEFAULT_ON_DEMAND_POLL_INTERVAL_MS))
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct MineginxConfig {
     pub handshake_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_connect_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_connect_retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_retry_backoff_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_write_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_nodelay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_buffer_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_transfer: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_matching: Option<DomainMatching>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub honeypot_domains: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_listen: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_listen: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bans_file: Option<String>,
+    /// MaxMind GeoLite2 Country database for `block_countries`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geoip_country_database: Option<String>,
+    /// MaxMind GeoLite2 ASN database for `block_asns`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geoip_asn_database: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_grace_ms: Option<u64>,
+    /// Share of the successful connections whose start and end are logged at info level, failures are always logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_log_sample_rate: Option<f64>,
     pub servers: Vec<MinecraftServerDescription>
 }
+
+/// How the handshake domain is normalized before looking for `server_names`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct DomainMatching {
+    /// Cuts the Forge marker and anything else after `\0`, true by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_fml: Option<bool>,
+    /// Ignores letter case, true by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub case_insensitive: Option<bool>,
+    /// Treats `mc.example.com.` as `mc.example.com`, false by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_trailing_dot: Option<bool>
+}
+
+impl DomainMatching {
+    pub fn strip_fml(&self) -> bool {
+        self.strip_fml.unwrap_or(true)
+    }
+
+    pub fn case_insensitive(&self) -> bool {
+        self.case_insensitive.unwrap_or(true)
+    }
+
+    pub fn strip_trailing_dot(&self) -> bool {
+        self.strip_trailing_dot.unwrap_or(false)
+    }
+}
+
+impl MineginxConfig {
+    pub fn domain_matching(&self) -> DomainMatching {
+        self.domain_matching.unwrap_or_default()
+    }
+
+    /// The server's own timeout, then the global one, then the default
+    pub fn handshake_timeout(&self, server: &MinecraftServerDescription) -> Duration {
+        Duration::from_millis(server.handshake_timeout_ms
+            .or(self.handshake_timeout_ms)
+            .unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT_MS))
+    }
+
+    pub fn upstream_write_timeout(&self) -> Duration {
+        Duration::from_millis(self.upstream_write_timeout_ms.unwrap_or(DEFAULT_UPSTREAM_WRITE_TIMEOUT_MS))
+    }
+
+    /// Whether `domain` is one of `honeypot_domains`, wildcards match like in `server_names`
+    pub fn is_honeypot(&self, domain: &str) -> bool {
+        let case_insensitive = self.domain_matching().case_insensitive();
+        self.honeypot_domains.iter()
+            .flatten()
+            .any(|x| names_equal(x, domain, case_insensitive) || wildcard_matches(x, domain, case_insensitive))
+    }
+
+    /// `TCP_NODELAY` of client and upstream sockets, true by default
+    pub fn tcp_nodelay(&self) -> bool {
+        self.tcp_nodelay.unwrap_or(true)
+    }
+
+    /// Initial size of the buffer the handshake is read into, it grows if a handshake doesn't fit
+    pub fn handshake_buffer_size(&self) -> usize {
+        self.handshake_buffer_size.unwrap_or(DEFAULT_HANDSHAKE_BUFFER_SIZE) as usize
+    }
+
+    /// How long open sessions may finish after the shutdown signal, `None` leaves them to the process exit
+    pub fn shutdown_grace(&self) -> Option<Duration> {
+        self.shutdown_grace_ms.map(Duration::from_millis)
+    }
+
+    /// Whether a connection is picked for `connection_log_sample_rate`, every connection by default
+    pub fn samples_connection_log(&self) -> bool {
+        match self.connection_log_sample_rate {
+            None => true,
+            Some(rate) if rate >= 1.0 => true,
+            Some(rate) if rate <= 0.0 => false,
+            Some(rate) => random_fraction() < rate
+        }
+    }
+
+    /// Whether players sent by another server with a Transfer packet may join `server`, true by default
+    pub fn allows_transfer(&self, server: &MinecraftServerDescription) -> bool {
+        server.allow_transfer.or(self.allow_transfer).unwrap_or(true)
+    }
+
+    /// The server's own retry settings win over the global ones
+    pub fn connect_options(&self, server: &MinecraftServerDescription) -> ConnectOptions {
+        ConnectOptions {
+            timeout: Duration::from_millis(self.upstream_connect_timeout_ms.unwrap_or(DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS)),
+            retries: server.connect_retries.or(self.upstream_connect_retries).unwrap_or(0),
+            retry_backoff: Duration::from_millis(server.connect_retry_delay_ms
+                .or(self.upstream_retry_backoff_ms)
+                .unwrap_or(DEFAULT_UPSTREAM_RETRY_BACKOFF_MS)),
+            address_family: server.address_family.unwrap_or_default(),
+            addresses: None,
+            nodelay: server.upstream_tcp_nodelay.unwrap_or(self.tcp_nodelay()),
+            send_buffer_size: server.upstream_send_buffer_size,
+            recv_buffer_size: server.upstream_recv_buffer_size
+        }
+    }
+
+    /// The domain is unknown until the handshake is read, so a listener waits as long as its most patient server  
+    /// After the handshake, the matched server's own timeout is checked again
+    pub fn listener_handshake_timeout(&self, address: &str) -> Duration {
+        self.servers.iter()
+            .filter(|x| x.listen.addresses().iter().any(|x| x == address))
+            .map(|x| self.handshake_timeout(x))
+            .max()
+            .unwrap_or(Duration::from_millis(self.handshake_timeout_ms.unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT_MS)))
+    }
+
+    /// Whether connections to `address` start with a PROXY protocol header  
+    /// All servers of an address have to agree, see `validate`
+    pub fn listener_accepts_proxy_protocol(&self, address: &str) -> bool {
+        self.servers.iter()
+            .filter(|x| x.listen.addresses().iter().any(|x| x == address))
+            .any(|x| x.accept_proxy_protocol == Some(true))
+    }
+
+    /// The same config, but every omitted setting is replaced with the value mineginx actually uses
+    pub fn with_defaults(&self) -> MineginxConfig {
+        MineginxConfig {
+            handshake_timeout_ms: Some(self.handshake_timeout_ms.unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT_MS)),
+            upstream_connect_timeout_ms: Some(self.upstream_connect_timeout_ms.unwrap_or(DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS)),
+            upstream_connect_retries: Some(self.upstream_connect_retries.unwrap_or(0)),
+            upstream_retry_backoff_ms: Some(self.upstream_retry_backoff_ms.unwrap_or(DEFAULT_UPSTREAM_RETRY_BACKOFF_MS)),
+            upstream_write_timeout_ms: Some(self.upstream_write_timeout().as_millis() as u64),
+            tcp_nodelay: Some(self.tcp_nodelay()),
+            handshake_buffer_size: Some(self.handshake_buffer_size() as u32),
+            allow_transfer: Some(self.allow_transfer.unwrap_or(true)),
+            domain_matching: Some(DomainMatching {
+                strip_fml: Some(self.domain_matching().strip_fml()),
+                case_insensitive: Some(self.domain_matching().case_insensitive()),
+                strip_trailing_dot: Some(self.domain_matching().strip_trailing_dot())
+            }),
+            honeypot_domains: self.honeypot_domains.clone(),
+            access_log: self.access_log.clone(),
+            health_listen: self.health_listen.clone(),
+            control_listen: self.control_listen.clone(),
+            bans_file: self.bans_file.clone(),
+            geoip_country_database: self.geoip_country_database.clone(),
+            geoip_asn_database: self.geoip_asn_database.clone(),
+            shutdown_grace_ms: self.shutdown_grace_ms,
+            connection_log_sample_rate: Some(self.connection_log_sample_rate.unwrap_or(1.0)),
+            servers: self.servers.iter().map(|server| MinecraftServerDescription {
+                buffer_size: Some(server.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE)),
+                proxy_pass_pool: server.proxy_pass_pool.as_ref().map(|pool| pool.iter()
+                    .map(|x| PoolBackend { weight: Some(x.weight()), ..x.clone() })
+                    .collect()),
+                handshake_timeout_ms: Some(self.handshake_timeout(server).as_millis() as u64),
+                connect_retries: Some(self.connect_options(server).retries),
+                connect_retry_delay_ms: Some(self.connect_options(server).retry_backoff.as_millis() as u64),
+                address_family: Some(server.address_family.unwrap_or_default()),
+                upstream_tcp_nodelay: Some(self.connect_options(server).nodelay),
+                allow_transfer: Some(self.allows_transfer(server)),
+                ..server.clone()
+            }).collect()
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json
+}
+
+impl ConfigFormat {
+    /// Detects the format by the file extension, anything unknown is read as yaml
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml
+        }
+    }
+}
+
+/// `listen`, `proxy_pass` and the backends of `proxy_pass_pool` without a port get the default minecraft port
+pub fn parse_config(data: &[u8], format: ConfigFormat) -> Result<MineginxConfig, String> {
+    let mut config: MineginxConfig = match format {
+        ConfigFormat::Yaml => serde_yaml::from_slice(data).map_err(|e| e.to_string())?,
+        ConfigFormat::Json => serde_json::from_slice(data).map_err(|e| e.to_string())?,
+        ConfigFormat::Toml => match std::str::from_utf8(data) {
+            Ok(text) => toml::from_str(text).map_err(|e| e.to_string())?,
+            Err(err) => return Err(err.to_string())
+        }
+    };
+    for server in &mut config.servers {
+        for listen in server.listen.addresses_mut() {
+            *listen = with_default_port(listen);
+        }
+        server.proxy_pass = with_default_port(&server.proxy_pass);
+        for backend in server.proxy_pass_pool.iter_mut().flatten() {
+            backend.addr = with_default_port(&backend.addr);
+        }
+    }
+    Ok(config)
+}
+
+/// Reads, expands environment variables in and parses the config file, the format is detected by its extension
+pub fn read_config_file(path: &Path) -> Result<MineginxConfig, String> {
+    let data = match fs::read(path) {
+        Ok(x) => x,
+        Err(err) => return Err(format!("failed to open config file: '{}': {err}", path.display()))
+    };
+    let text = match String::from_utf8(data) {
+        Ok(x) => x,
+        Err(err) => return Err(format!("failed to read config file: '{}': {err}", path.display()))
+    };
+    let text = match substitute_env(&text, |name| env::var(name).ok()) {
+        Ok(x) => x,
+        Err(err) => return Err(format!("failed to substitute variables in config file: '{}': {err}", path.display()))
+    };
+    parse_config(text.as_bytes(), ConfigFormat::from_path(path))
+        .map_err(|err| format!("failed to parse config file: '{}': {err}", path.display()))
+}
+
+/// The config which can be replaced while clients are connected, see `reload` of the control socket
+pub struct SharedConfig {
+    current: RwLock<Arc<MineginxConfig>>
+}
+
+impl SharedConfig {
+    pub fn new(config: Arc<MineginxConfig>) -> SharedConfig {
+        SharedConfig { current: RwLock::new(config) }
+    }
+
+    /// A client keeps the config it got at the start even if it is replaced later
+    pub fn get(&self) -> Arc<MineginxConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn set(&self, config: Arc<MineginxConfig>) {
+        *self.current.write().unwrap() = config;
+    }
+}
+
+/// Appends `DEFAULT_PORT` to an address without a port, such as `mc.example.com`, `10.0.0.1`, `::1` or `[::1]`  
+/// Unix socket paths and empty addresses are left as is
+pub fn with_default_port(address: &str) -> String {
+    if address.is_empty() || address.starts_with(UNIX_PREFIX) {
+        return address.to_string();
+    }
+    // `[::1]:25565` has a port, `[::1]` doesn't
+    if address.starts_with('[') {
+        if address.ends_with(']') {
+            return format!("{address}:{DEFAULT_PORT}");
+        }
+        return address.to_string();
+    }
+    if address.parse::<Ipv6Addr>().is_ok() {
+        return format!("[{address}]:{DEFAULT_PORT}");
+    }
+    if address.contains(':') {
+        return address.to_string();
+    }
+    format!("{address}:{DEFAULT_PORT}")
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` placeholders using `lookup`  
+/// A variable without a value and without a default is an error
+pub fn substitute_env<F>(text: &str, lookup: F) -> Result<String, String> where F: Fn(&str) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let end = match placeholder.find('}') {
+            Some(x) => x,
+            None => return Err(format!("unclosed placeholder '{}'", &rest[start..]))
+        };
+        let (name, default) = match placeholder[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&placeholder[..end], None)
+        };
+        match lookup(name).or(default.map(|x| x.to_string())) {
+            Some(value) => result.push_str(&value),
+            None => return Err(format!("environment variable '{name}' is not set"))
+        }
+        rest = &placeholder[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+pub fn serialize_config(config: &MineginxConfig, format: ConfigFormat) -> Result<String, String> {
+    match format {
+        ConfigFormat::Yaml => serde_yaml::to_string(config).map_err(|e| e.to_string()),
+        ConfigFormat::Json => serde_json::to_string_pretty(config).map_err(|e| e.to_string()),
+        ConfigFormat::Toml => toml::to_string(config).map_err(|e| e.to_string())
+    }
+}
+
+/// Resolves `listen` and `proxy_pass` of every server  
+/// Returns one message per address that can't be used, empty if everything is fine
+pub async fn validate_addresses(config: &MineginxConfig) -> Vec<String> {
+    let mut errors = vec![];
+    for (index, server) in config.servers.iter().enumerate() {
+        if server.listen.addresses().is_empty() {
+            errors.push(format!("server #{index}: listen must not be empty"));
+        }
+        for listen in server.listen.addresses() {
+            if let Some(path) = listen.strip_prefix(UNIX_PREFIX) {
+                if path.is_empty() {
+                    errors.push(format!("server #{index}: invalid listen '{listen}': empty socket path"));
+                }
+                continue;
+            }
+            if let Err(err) = lookup_host(listen).await {
+                errors.push(format!("server #{index}: invalid listen '{listen}': {err}"));
+            }
+        }
+        for proxy_pass in server.upstream_addresses() {
+            if let Err(err) = lookup_host(proxy_pass).await {
+                errors.push(format!("server #{index}: invalid proxy_pass '{proxy_pass}': {err}"));
+            }
+        }
+    }
+    if let Some(health_listen) = &config.health_listen {
+        if let Err(err) = lookup_host(health_listen).await {
+            errors.push(format!("invalid health_listen '{health_listen}': {err}"));
+        }
+    }
+    // the control socket has no authentication, so it must not be reachable from other hosts
+    if let Some(control_listen) = config.control_listen.as_ref().filter(|x| !x.starts_with(UNIX_PREFIX)) {
+        match lookup_host(control_listen).await {
+            Ok(mut addresses) => if !addresses.all(|x| x.ip().is_loopback()) {
+                errors.push(format!("control_listen '{control_listen}' must be a loopback address or a unix socket"));
+            },
+            Err(err) => errors.push(format!("invalid control_listen '{control_listen}': {err}"))
+        }
+    }
+    errors
+}
+
+/// A number in `[0, 1)`, `RandomState` gets new keys each time, so hashing nothing with it is a cheap random source
+pub(crate) fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// Every `listen` address once, in the order of appearance
+pub fn unique_listen_addresses(config: &MineginxConfig) -> Vec<&String> {
+    let mut addresses: Vec<&String> = vec![];
+    for server in &config.servers {
+        for address in server.listen.addresses() {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    addresses
+}
+
+/// Checks everything that can be checked without starting the proxy  
+/// Returns all found problems, empty if the config is fine
+pub async fn validate(config: &MineginxConfig) -> Vec<String> {
+    let mut errors = vec![];
+    if config.servers.is_empty() {
+        errors.push("there are no servers".to_string());
+    }
+    if config.handshake_timeout_ms == Some(0) {
+        errors.push("handshake_timeout_ms must be greater than 0".to_string());
+    }
+    if config.upstream_connect_timeout_ms == Some(0) {
+        errors.push("upstream_connect_timeout_ms must be greater than 0".to_string());
+    }
+    if config.upstream_write_timeout_ms == Some(0) {
+        errors.push("upstream_write_timeout_ms must be greater than 0".to_string());
+    }
+    if config.connection_log_sample_rate.is_some_and(|x| !(0.0..=1.0).contains(&x)) {
+        errors.push("connection_log_sample_rate must be between 0 and 1".to_string());
+    }
+    if let Some(size) = config.handshake_buffer_size {
+        if size == 0 || size > MAX_BUFFER_SIZE {
+            errors.push(format!("handshake_buffer_size must be between 1 and {MAX_BUFFER_SIZE}"));
+        }
+    }
+    for (index, server) in config.servers.iter().enumerate() {
+        if server.server_names.is_empty() && server.server_name_patterns.as_deref().unwrap_or_default().is_empty() {
+            errors.push(format!("server #{index}: server_names must not be empty"));
+        }
+        for server_name in &server.server_names {
+            if server_name.is_empty() {
+                errors.push(format!("server #{index}: server_names contains an empty name"));
+                continue;
+            }
+            if server_name.rfind('*').is_some_and(|x| x != 0 || !server_name.starts_with("*.")) {
+                errors.push(format!("server #{index}: server_name '{server_name}' may only contain a leading '*.' wildcard"));
+            }
+        }
+        if server.handshake_timeout_ms == Some(0) {
+            errors.push(format!("server #{index}: handshake_timeout_ms must be greater than 0"));
+        }
+        if server.allowed_protocol_versions.as_ref().is_some_and(|x| x.is_empty()) {
+            errors.push(format!("server #{index}: allowed_protocol_versions must not be empty"));
+        }
+        if let Some(on_demand) = &server.on_demand {
+            if on_demand.command.as_ref().is_some_and(|x| x.trim().is_empty()) {
+                errors.push(format!("server #{index}: on_demand.command must not be empty"));
+            }
+            if on_demand.start_timeout_ms == Some(0) {
+                errors.push(format!("server #{index}: on_demand.start_timeout_ms must be greater than 0"));
+            }
+            if on_demand.poll_interval_ms == Some(0) {
+                errors.push(format!("server #{index}: on_demand.poll_interval_ms must be greater than 0"));
+            }
+        }
+        if server.resolve_interval_ms == Some(0) {
+            errors.push(format!("server #{index}: resolve_interval_ms must be greater than 0"));
+        }
+        match &server.proxy_pass_pool {
+            Some(_) if !server.proxy_pass.is_empty() => errors.push(format!("server #{index}: proxy_pass and proxy_pass_pool can't be used together")),
+            Some(pool) if pool.is_empty() => errors.push(format!("server #{index}: proxy_pass_pool must not be empty")),
+            Some(pool) if pool.iter().any(|x| x.weight == Some(0)) => errors.push(format!("server #{index}: weights of proxy_pass_pool must be greater than 0")),
+            _ => {}
+        }
+        match &server.proxy_pass_srv {
+            Some(_) if !server.proxy_pass.is_empty() || server.proxy_pass_pool.is_some() => errors.push(format!("server #{index}: proxy_pass_srv can't be used with proxy_pass or proxy_pass_pool")),
+            Some(name) if !is_domain_name(name) => errors.push(format!("server #{index}: proxy_pass_srv must be a domain name")),
+            _ => {}
+        }
+        if server.sticky_sessions == Some(true) && server.proxy_pass_pool.is_none() {
+            errors.push(format!("server #{index}: sticky_sessions needs proxy_pass_pool"));
+        }
+        if server.upstream_hostname.as_ref().is_some_and(|x| x.is_empty() || x.contains('\0')) {
+            errors.push(format!("server #{index}: upstream_hostname must be a host name"));
+        }
+        if server.preserve_handshake == Some(true) && (server.upstream_hostname.is_some() || server.rewrite_port.is_some()) {
+            errors.push(format!("server #{index}: preserve_handshake can't be used with upstream_hostname or rewrite_port"));
+        }
+        if server.upstream_send_buffer_size == Some(0) {
+            errors.push(format!("server #{index}: upstream_send_buffer_size must be greater than 0"));
+        }
+        if server.upstream_recv_buffer_size == Some(0) {
+            errors.push(format!("server #{index}: upstream_recv_buffer_size must be greater than 0"));
+        }
+        if server.status_cache_ttl_ms == Some(0) {
+            errors.push(format!("server #{index}: status_cache_ttl_ms must be greater than 0"));
+        }
+        if server.block_countries.is_some() && config.geoip_country_database.is_none() {
+            errors.push(format!("server #{index}: block_countries needs geoip_country_database"));
+        }
+        if server.block_asns.is_some() && config.geoip_asn_database.is_none() {
+            errors.push(format!("server #{index}: block_asns needs geoip_asn_database"));
+        }
+        if server.max_bandwidth_bytes_per_sec == Some(0) {
+            errors.push(format!("server #{index}: max_bandwidth_bytes_per_sec must be greater than 0"));
+        }
+        if let Some(buffer_size) = server.buffer_size {
+            if buffer_size == 0 || buffer_size > MAX_BUFFER_SIZE {
+                errors.push(format!("server #{index}: buffer_size must be between 1 and {MAX_BUFFER_SIZE}"));
+            }
+        }
+    }
+    for address in unique_listen_addresses(config) {
+        let servers: Vec<usize> = config.servers.iter()
+            .enumerate()
+            .filter(|(_, x)| x.listen.addresses().contains(address))
+            .map(|(index, _)| index)
+            .collect();
+        let accepts = |index: &usize| config.servers[*index].accept_proxy_protocol == Some(true);
+        if servers.iter().any(accepts) && !servers.iter().all(accepts) {
+            let servers: Vec<String> = servers.iter().map(|x| format!("#{x}")).collect();
+            errors.push(format!("servers {} listen '{address}', accept_proxy_protocol must be the same for all of them", servers.join(", ")));
+        }
+    }
+    for overlap in server_name_overlaps(config) {
+        if let Overlap::DuplicateServerName { .. } = overlap {
+            errors.push(overlap.to_string());
+        }
+    }
+    errors.extend(validate_addresses(config).await);
+    errors
+}
+
+/// `server_names` of different servers which match the same domain on one `listen` address
+#[derive(Debug, PartialEq)]
+pub enum Overlap {
+    /// The same `server_name` is listed by two servers, an error of `validate` since the second server never gets its clients
+    DuplicateServerName { server_name: String, listen: String, winner: usize, ignored: usize, upstream: String },
+    /// An exact `server_name` is also covered by a wildcard of another server, the exact one wins, only a warning
+    WildcardOverlap { server_name: String, wildcard: String, listen: String, winner: usize, ignored: usize, upstream: String }
+}
+
+impl Overlap {
+    /// The overlapping name and servers, regardless of the address
+    fn servers(&self) -> (&str, usize, usize) {
+        match self {
+            Overlap::DuplicateServerName { server_name, winner, ignored, .. } => (server_name, *winner, *ignored),
+            Overlap::WildcardOverlap { server_name, winner, ignored, .. } => (server_name, *winner, *ignored)
+        }
+    }
+}
+
+impl Display for Overlap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Overlap::DuplicateServerName { server_name, listen, winner, ignored, upstream } => write!(f,
+                "server_name '{server_name}' of server #{ignored} is already used by server #{winner} on {listen}, connections go to {upstream}"),
+            Overlap::WildcardOverlap { server_name, wildcard, listen, winner, ignored, upstream } => write!(f,
+                "server_name '{server_name}' of server #{winner} also matches '{wildcard}' of server #{ignored} on {listen}, connections go to {upstream}")
+        }
+    }
+}
+
+/// `*.example.com` matches any subdomain of `example.com`, but not `example.com` itself
+pub fn wildcard_matches(wildcard: &str, domain: &str, case_insensitive: bool) -> bool {
+    match wildcard.strip_prefix('*') {
+        Some(suffix) => suffix.starts_with('.')
+            && domain.len() > suffix.len()
+            && domain.get(domain.len() - suffix.len()..).is_some_and(|tail| names_equal(tail, suffix, case_insensitive)),
+        None => false
+    }
+}
+
+pub fn names_equal(left: &str, right: &str, case_insensitive: bool) -> bool {
+    match case_insensitive {
+        true => left.eq_ignore_ascii_case(right),
+        false => left == right
+    }
+}
+
+/// Only the overlaps of a wildcard with an exact `server_name` of another server, the exact one gets its clients anyway  
+/// Duplicates are not here, they are errors of `validate`
+pub fn server_name_warnings(config: &MineginxConfig) -> Vec<Overlap> {
+    server_name_overlaps(config).into_iter()
+        .filter(|x| matches!(x, Overlap::WildcardOverlap { .. }))
+        .collect()
+}
+
+/// Finds `server_names` which are covered by more than one server on the same `listen` address, both duplicates and wildcard overlaps  
+/// Letter case is ignored unless `domain_matching` says otherwise
+pub fn server_name_overlaps(config: &MineginxConfig) -> Vec<Overlap> {
+    let mut overlaps: Vec<Overlap> = vec![];
+    for listen in unique_listen_addresses(config) {
+        for overlap in server_name_overlaps_on(config, listen) {
+            // servers sharing several addresses overlap on each of them
+            if !overlaps.iter().any(|x| x.servers() == overlap.servers()) {
+                overlaps.push(overlap);
+            }
+        }
+    }
+    overlaps
+}
+
+fn server_name_overlaps_on(config: &MineginxConfig, listen: &str) -> Vec<Overlap> {
+    let case_insensitive = config.domain_matching().case_insensitive();
+    let key = |server_name: &String| match case_insensitive {
+        true => server_name.to_ascii_lowercase(),
+        false => server_name.clone()
+    };
+    let servers: Vec<(usize, &MinecraftServerDescription)> = config.servers.iter()
+        .enumerate()
+        .filter(|(_, x)| x.listen.addresses().iter().any(|x| x == listen))
+        .collect();
+    let mut warnings = vec![];
+    let mut owners = HashMap::<String, usize>::new();
+    for &(index, server) in &servers {
+        for server_name in &server.server_names {
+            match owners.get(&key(server_name)) {
+                // listed twice by the same server, which changes nothing
+                Some(&winner) if winner == index => {},
+                Some(&winner) => warnings.push(Overlap::DuplicateServerName {
+                    server_name: server_name.clone(),
+                    listen: listen.to_string(),
+                    winner,
+                    ignored: index,
+                    upstream: config.servers[winner].label()
+                }),
+                None => _ = owners.insert(key(server_name), index)
+            }
+        }
+    }
+    for &(index, server) in &servers {
+        for server_name in &server.server_names {
+            if owners.get(&key(server_name)) != Some(&index) || server_name.starts_with('*') {
+                continue;
+            }
+            for &(other, other_server) in &servers {
+                if other == index {
+                    continue;
+                }
+                if let Some(wildcard) = other_server.server_names.iter().find(|x| wildcard_matches(x, server_name, case_insensitive)) {
+                    warnings.push(Overlap::WildcardOverlap {
+                        server_name: server_name.clone(),
+                        wildcard: wildcard.clone(),
+                        listen: listen.to_string(),
+                        winner: index,
+                        ignored: other,
+                        upstream: server.label()
+                    });
+                }
+            }
+        }
+    }
+    warnings
+}
@@ -0,0 +1,128 @@
+use std::{io, path::{Path, PathBuf}, sync::Arc};
+
+use log::{error, info, warn};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{
+    bans::BanEntry,
+    config::{read_config_file, server_name_warnings, unique_listen_addresses, validate},
+    domain::normalize_domain,
+    listener::Listener,
+    state::State
+};
+
+/// Serves the commands of `control_listen`, one per line:  
+/// `reload`, `ban <ip or network>`, `unban <ip or network>`, `kick <domain>` and `status`  
+/// Every command is answered with one line, `ok`, `error: <reason>` or the status
+pub async fn serve_control(listener: Listener, state: Arc<State>, config_path: Option<PathBuf>) {
+    let config_path = Arc::new(config_path);
+    match listener {
+        Listener::Tcp(listener) => loop {
+            let socket = match listener.accept().await {
+                Ok((x, _address)) => x,
+                Err(e) => {
+                    error!("failed to accept control client: {e}");
+                    continue;
+                }
+            };
+            tokio::spawn(serve_client(socket, state.clone(), config_path.clone()));
+        },
+        #[cfg(unix)]
+        Listener::Unix(listener) => loop {
+            let socket = match listener.accept().await {
+                Ok((x, _address)) => x,
+                Err(e) => {
+                    error!("failed to accept control client: {e}");
+                    continue;
+                }
+            };
+            tokio::spawn(serve_client(socket, state.clone(), config_path.clone()));
+        }
+    }
+}
+
+async fn serve_client<S>(socket: S, state: Arc<State>, config_path: Arc<Option<PathBuf>>) where S: AsyncRead + AsyncWrite + Unpin {
+    let (reader, mut writer) = tokio::io::split(socket);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match execute(&line, &state, config_path.as_deref()).await {
+            Ok(x) => x,
+            Err(err) => format!("error: {err}")
+        };
+        if writer.write_all(format!("{response}\n").as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn execute(line: &str, state: &State, config_path: Option<&Path>) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        ["reload"] => {
+            reload(state, config_path).await?;
+            Ok("ok".to_string())
+        },
+        ["ban", entry] => {
+            let entry = parse_entry(entry)?;
+            info!("banned {entry}");
+            state.bans.add(entry).await.map_err(save_error)?;
+            Ok("ok".to_string())
+        },
+        ["unban", entry] => {
+            let entry = parse_entry(entry)?;
+            if !state.bans.remove(&entry).await.map_err(save_error)? {
+                return Err(format!("{entry} is not banned"));
+            }
+            info!("unbanned {entry}");
+            Ok("ok".to_string())
+        },
+        ["kick", domain] => {
+            let domain = normalize_domain(domain, &state.config.get().domain_matching());
+            info!("kick clients of domain {domain}");
+            // there may be no clients at all, which is not an error
+            _ = state.kicks.send(domain);
+            Ok("ok".to_string())
+        },
+        ["status"] => Ok(state.summary()),
+        _ => Err(format!("unknown command '{}'", line.trim()))
+    }
+}
+
+fn parse_entry(entry: &str) -> Result<BanEntry, String> {
+    entry.parse().map_err(|_| format!("invalid ip '{entry}'"))
+}
+
+fn save_error(err: io::Error) -> String {
+    error!("failed to save bans: {err}");
+    format!("the ban list is changed, but not saved: {err}")
+}
+
+/// Clients which are already connected keep the config they started with
+async fn reload(state: &State, config_path: Option<&Path>) -> Result<(), String> {
+    let path = config_path.ok_or("the config was not loaded from a file")?;
+    let config = read_config_file(path)?;
+    for warning in server_name_warnings(&config) {
+        warn!("{warning}");
+    }
+    let errors = validate(&config).await;
+    if !errors.is_empty() {
+        return Err(errors.join(", "));
+    }
+    let current = state.config.get();
+    if unique_listen_addresses(&current) != unique_listen_addresses(&config)
+        || current.health_listen != config.health_listen
+        || current.access_log != config.access_log
+        || current.control_listen != config.control_listen
+        || current.bans_file != config.bans_file
+        || current.geoip_country_database != config.geoip_country_database
+        || current.geoip_asn_database != config.geoip_asn_database {
+        warn!("changes of listen, health_listen, access_log, control_listen, bans_file and geoip databases take effect after restart");
+    }
+    state.resolver.follow(&config).await;
+    state.config.set(Arc::new(config));
+    info!("reloaded config {}", path.display());
+    Ok(())
+}
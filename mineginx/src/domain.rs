@@ -0,0 +1,44 @@
+use minecraft::serialization::truncate_to_zero;
+
+use crate::config::DomainMatching;
+
+/// Forge clients append one of these to the handshake domain
+const FORGE_MARKERS: [&str; 3] = ["\0FML\0", "\0FML2\0", "\0FML3\0"];
+
+/// Removes a trailing Forge marker from the handshake domain  
+/// `mc.example.com\0FML3\0` becomes `mc.example.com`
+pub fn strip_forge_marker(domain: &str) -> &str {
+    for marker in FORGE_MARKERS {
+        if let Some(host) = domain.strip_suffix(marker) {
+            return host;
+        }
+    }
+    domain
+}
+
+/// The part of the handshake domain used to find an upstream  
+/// Anything else after `\0` is not a part of the host either
+pub fn matching_host(domain: &str) -> &str {
+    truncate_to_zero(strip_forge_marker(domain))
+}
+
+/// Puts `host` in place of the host of the handshake domain  
+/// What follows the host, like the Forge marker or the forwarding data of BungeeCord, is kept
+pub fn replace_host(domain: &str, host: &str) -> String {
+    format!("{host}{}", &domain[matching_host(domain).len()..])
+}
+
+/// Applies `domain_matching` to the handshake domain
+pub fn normalize_domain(domain: &str, matching: &DomainMatching) -> String {
+    let mut host = match matching.strip_fml() {
+        true => matching_host(domain),
+        false => domain
+    };
+    if matching.strip_trailing_dot() {
+        host = host.strip_suffix('.').unwrap_or(host);
+    }
+    match matching.case_insensitive() {
+        true => host.to_ascii_lowercase(),
+        false => host.to_string()
+    }
+}
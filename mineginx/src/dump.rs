@@ -0,0 +1,72 @@
+use std::{io, pin::Pin, task::{ready, Context, Poll}};
+
+use log::trace;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How many bytes of each direction are dumped for servers with `debug_dump`
+pub const DEBUG_DUMP_BYTES: usize = 4096;
+
+/// Logs the first bytes of one direction of a connection as hex at trace level
+pub struct Dump {
+    prefix: String,
+    offset: usize,
+    remaining: usize
+}
+
+impl Dump {
+    pub fn new(prefix: String, limit: usize) -> Dump {
+        Dump {
+            prefix,
+            offset: 0,
+            remaining: limit
+        }
+    }
+
+    pub fn disabled() -> Dump {
+        Dump::new(String::new(), 0)
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        if self.remaining == 0 {
+            return;
+        }
+        let data = &data[..data.len().min(self.remaining)];
+        for line in hex_lines(self.offset, data) {
+            trace!("{} {line}", self.prefix);
+        }
+        self.offset += data.len();
+        self.remaining -= data.len();
+    }
+}
+
+/// `00000010  00 f5 05 09 6c 6f 63 61  6c 68 6f 73 74 63 dd 02  |....localhostc..|`
+pub fn hex_lines(offset: usize, data: &[u8]) -> Vec<String> {
+    data.chunks(16).enumerate().map(|(index, chunk)| {
+        let hex: Vec<String> = chunk.iter().map(|x| format!("{x:02x}")).collect();
+        let (left, right) = hex.split_at(hex.len().min(8));
+        let ascii: String = chunk.iter().map(|&x| if x.is_ascii_graphic() || x == b' ' { x as char } else { '.' }).collect();
+        format!("{:08x}  {:<23}  {:<23}  |{ascii}|", offset + index * 16, left.join(" "), right.join(" "))
+    }).collect()
+}
+
+/// Passes reads through, dumping what was read
+pub struct DumpReader<R> {
+    inner: R,
+    dump: Dump
+}
+
+impl<R> DumpReader<R> {
+    pub fn new(inner: R, dump: Dump) -> DumpReader<R> {
+        DumpReader { inner, dump }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DumpReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.dump.write(&buf.filled()[before..]);
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,57 @@
+use std::{net::IpAddr, path::Path};
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use crate::config::MinecraftServerDescription;
+
+/// MaxMind databases of `geoip_country_database` and `geoip_asn_database`  
+/// Nothing is looked up for servers without `block_countries` and `block_asns`
+#[derive(Default)]
+pub struct GeoIp {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>
+}
+
+impl GeoIp {
+    /// The whole files are read into memory
+    pub fn open(country: Option<&Path>, asn: Option<&Path>) -> Result<GeoIp, (String, MaxMindDBError)> {
+        let open = |path: Option<&Path>| match path {
+            Some(x) => Reader::open_readfile(x).map(Some).map_err(|err| (x.display().to_string(), err)),
+            None => Ok(None)
+        };
+        Ok(GeoIp {
+            country: open(country)?,
+            asn: open(asn)?
+        })
+    }
+
+    /// ISO 3166-1 code like `US`, `None` if the address is not in the database
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let country: geoip2::Country = self.country.as_ref()?.lookup(ip).ok()?;
+        country.country?.iso_code.map(|x| x.to_string())
+    }
+
+    pub fn asn(&self, ip: IpAddr) -> Option<u32> {
+        let asn: geoip2::Asn = self.asn.as_ref()?.lookup(ip).ok()?;
+        asn.autonomous_system_number
+    }
+
+    /// The reason if `ip` is in one of `block_countries` or `block_asns` of `server`
+    pub fn blocks(&self, server: &MinecraftServerDescription, ip: IpAddr) -> Option<String> {
+        if let Some(block_countries) = &server.block_countries {
+            if let Some(country) = self.country(ip) {
+                if block_countries.iter().any(|x| x.eq_ignore_ascii_case(&country)) {
+                    return Some(format!("country {country}"));
+                }
+            }
+        }
+        if let Some(block_asns) = &server.block_asns {
+            if let Some(asn) = self.asn(ip) {
+                if block_asns.contains(&asn) {
+                    return Some(format!("AS{asn}"));
+                }
+            }
+        }
+        None
+    }
+}
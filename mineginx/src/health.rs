@@ -0,0 +1,41 @@
+use std::{sync::Arc, time::Duration};
+
+use log::error;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::timeout};
+
+use crate::state::State;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const OK: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\nok\n";
+const DRAINING: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 9\r\nConnection: close\r\n\r\ndraining\n";
+
+/// Answers any http request with 200 while mineginx accepts clients and 503 once shutdown begins
+pub async fn serve_health(listener: TcpListener, state: Arc<State>) {
+    loop {
+        let (socket, _address) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("failed to accept health check: {e}");
+                continue;
+            }
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            respond(socket, &state).await;
+        });
+    }
+}
+
+async fn respond(mut socket: TcpStream, state: &State) {
+    // the request itself doesn't matter, but it must be read before closing
+    let mut request = [0; 1024];
+    if let Err(_) | Ok(Err(_)) = timeout(REQUEST_TIMEOUT, socket.read(&mut request)).await {
+        return;
+    }
+    let response = match state.is_shutting_down() {
+        true => DRAINING,
+        false => OK
+    };
+    _ = socket.write_all(response).await;
+    _ = socket.shutdown().await;
+}
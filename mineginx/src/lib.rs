@@ -0,0 +1,574 @@
+//! Lightweight minecraft proxy server  
+//! The binary is a thin wrapper around [`Proxy`], which can be embedded in other tokio applications the same way
+//!
+//! ```
+//! use mineginx::{config::{MinecraftServerDescription, MineginxConfig}, Proxy};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let config = MineginxConfig {
+//!     servers: vec![MinecraftServerDescription {
+//!         listen: "127.0.0.1:0".into(),
+//!         server_names: vec!["mc.example.com".to_string()],
+//!         proxy_pass: "127.0.0.1:7878".to_string(),
+//!         ..Default::default()
+//!     }],
+//!     ..Default::default()
+//! };
+//! let proxy = Proxy::new(config).bind().await.unwrap();
+//! let address = proxy.local_addrs()[0];
+//! let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+//! let running = tokio::spawn(proxy.run(async { _ = stopped.await; }));
+//!
+//! tokio::net::TcpStream::connect(address).await.unwrap();
+//! stop.send(()).unwrap();
+//! running.await.unwrap();
+//! # }
+//! ```
+use std::{borrow::BorrowMut, future::pending, io::{self, Cursor}, net::SocketAddr, sync::{atomic::Ordering, Arc}, time::{Duration, Instant}};
+use access_log::Session;
+use config::{MinecraftServerDescription, MineginxConfig, DEFAULT_BUFFER_SIZE, TRANSFER_DENIED_MESSAGE};
+use log::{debug, error, info, warn};
+use minecraft::{packets::{HandshakeC2SPacket, MinecraftPacket}, serialization::{MinecraftStream, ReadingError}};
+use tokio::{io::{AsyncRead, AsyncWrite}, net::TcpStream, sync::{broadcast::error::RecvError, oneshot}, time::timeout};
+use stream::{forward_stream, AbortOnDrop, Closed, SplitStream};
+use domain::{normalize_domain, replace_host};
+use dump::{Dump, DumpReader, DEBUG_DUMP_BYTES};
+use listener::Listener;
+use respond::{answer_status, is_login, kick, read_login_start, read_status_request, serve_maintenance, NEXT_STATE_STATUS, NEXT_STATE_TRANSFER};
+use status_cache::{fetch_status, StatusKey};
+use state::{State, Stats};
+use on_demand::{hold_client, hold_timeout, wait_upstream, HOLD_KEEP_ALIVE_INTERVAL};
+use observer::ObservedSession;
+use proxy_protocol::{encode_header, read_header};
+use protocol::ProtocolVersion;
+use upstream::{connect_upstream, write_upstream, ConnectOptions};
+
+pub use proxy::{BoundProxy, Proxy, Shutdown, StartError};
+
+pub mod stream;
+pub mod config;
+pub mod cli;
+pub mod upstream;
+mod throttle;
+mod listener;
+mod domain;
+pub mod access_log;
+pub mod bans;
+pub mod state;
+mod health;
+pub mod geoip;
+mod control;
+pub mod resolver;
+mod dump;
+mod respond;
+mod status_cache;
+pub mod pool;
+pub mod srv;
+mod probe;
+pub mod router;
+pub mod observer;
+mod proxy;
+mod proxy_protocol;
+mod protocol;
+pub mod on_demand;
+pub mod app;
+
+#[cfg(test)]
+mod tests;
+
+/// The handshake and its bytes as the client sent them, for `preserve_handshake`
+async fn read_handshake_packet<S>(client: &mut MinecraftStream<&mut S>) -> Result<(HandshakeC2SPacket, Vec<u8>), ReadingError> where S: AsyncRead + AsyncWrite + Unpin {
+    let raw = client.read_raw_packet().await?;
+    let size = raw.len();
+    let mut raw = Cursor::new(raw);
+    let mut packet = MinecraftStream::new(&mut raw, size);
+    // the whole packet is there, so ending early means it is malformed
+    let signature = packet.read_signature().await.map_err(|_| ReadingError::Invalid)?;
+    if signature.packet_id != 0 {
+        return Err(ReadingError::Invalid);
+    }
+    let handshake = packet.read_data_bounded::<HandshakeC2SPacket>(signature).await?;
+    Ok((handshake, raw.into_inner()))
+}
+
+async fn handle_client<S>(mut client: S, state: Arc<State>, listen: &str, mut peer_address: Option<SocketAddr>) where S: SplitStream {
+    let config = state.config.get();
+    let started = Instant::now();
+    let timeout_future = config.listener_handshake_timeout(listen);
+    if config.listener_accepts_proxy_protocol(listen) {
+        match timeout(timeout_future, read_header(&mut client)).await {
+            Ok(Ok(Some(x))) => peer_address = Some(x),
+            // the load balancer checks if mineginx is alive
+            Ok(Ok(None)) => {},
+            Ok(Err(err)) => {
+                warn!("failed to read PROXY protocol header from {}: {err}", peer_name(peer_address));
+                return;
+            },
+            Err(err) => {
+                error!("PROXY protocol header timeout for {} {err}", peer_name(peer_address));
+                return;
+            }
+        }
+    }
+    // clients of a dual-stack `[::]` listener come as `::ffff:1.2.3.4`, bans and logs use the IPv4 form
+    let peer_address = peer_address.map(|x| SocketAddr::new(x.ip().to_canonical(), x.port()));
+    let peer = peer_name(peer_address);
+    if peer_address.is_some_and(|x| state.is_banned(&x.ip())) {
+        info!("rejected banned {peer}");
+        return;
+    }
+    let timeout_future = timeout_future.saturating_sub(started.elapsed());
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), config.handshake_buffer_size());
+    // also bounds the packets read after the handshake, such as the status request of maintenance
+    minecraft.set_read_timeout(Some(timeout_future));
+    let handshake_result = timeout(timeout_future, read_handshake_packet(&mut minecraft)).await;
+    let (handshake, raw_handshake) = match handshake_result {
+        Ok(result) => match result {
+            Ok(handshake) => {
+                handshake
+            }
+            Err(ReadingError::ClosedEmpty) => {
+                Stats::increment(&state.stats.empty_connections);
+                debug!("{peer} closed the connection without a handshake");
+                return;
+            }
+            Err(ReadingError::Closed) => {
+                Stats::increment(&state.stats.handshake_failures);
+                info!("{peer} closed the connection in the middle of the handshake");
+                return;
+            }
+            Err(err) => {
+                Stats::increment(&state.stats.handshake_failures);
+                error!("handshake failed for {peer}: {err}");
+                return;
+            }
+        },
+        Err(err) => {
+            Stats::increment(&state.stats.handshake_failures);
+            error!("handshake timeout for {peer} {err}");
+            return;
+        }
+    };
+
+    state.observer.on_handshake(&handshake, peer_address);
+    let domain = normalize_domain(&handshake.domain, &config.domain_matching());
+    if handshake.next_state != NEXT_STATE_STATUS && !is_login(handshake.next_state) {
+        warn!("unknown next_state {} from {peer} for domain {:#?}", handshake.next_state, &domain);
+        return;
+    }
+    if config.is_honeypot(&domain) {
+        Stats::increment(&state.stats.scanners);
+        warn!("scanner {peer} asked for honeypot domain {:#?}", &domain);
+        return;
+    }
+    let mut upstream_server = match state.router.resolve(&handshake, listen, peer_address).await {
+        Some(x) => x,
+        None => {
+            Stats::increment(&state.stats.misses);
+            warn!("there is no upstream for domain {:#?}", &domain);
+            return;
+        }
+    };
+    // the backend of a sticky pool depends on the player, who is known from Login Start
+    let mut login_start = vec![];
+    let mut player = None;
+    if upstream_server.proxy_pass_pool.is_some() && upstream_server.sticky_sessions == Some(true) && is_login(handshake.next_state) {
+        match read_login_start(&mut minecraft, handshake.protocol_version).await {
+            Ok((packet, key)) => (login_start, player) = (packet, key),
+            Err(err) => {
+                warn!("failed to read login start from {peer} for domain {domain}: {err}");
+                return;
+            }
+        }
+    }
+    if let Some(pool) = &upstream_server.proxy_pass_pool {
+        let backend = match &player {
+            Some(player) => state.pools.select_sticky(pool, player),
+            None => state.pools.select(pool)
+        };
+        match backend {
+            Some(backend) => upstream_server.proxy_pass = backend.addr.clone(),
+            None => {
+                warn!("proxy_pass_pool is empty for domain {:#?}", &domain);
+                return;
+            }
+        }
+    }
+    if let Some(name) = &upstream_server.proxy_pass_srv {
+        match state.srv.resolve(name).await {
+            Ok(target) => upstream_server.proxy_pass = target,
+            Err(err) => {
+                error!("failed to resolve SRV record {name} for domain {:#?}: {err}", &domain);
+                return;
+            }
+        }
+    }
+    state.observer.on_upstream_selected(&handshake, &upstream_server);
+    if started.elapsed() > config.handshake_timeout(&upstream_server) {
+        error!("handshake timeout for domain {:#?}", &domain);
+        return;
+    }
+
+    if let Some(reason) = peer_address.and_then(|x| state.geoip.blocks(&upstream_server, x.ip())) {
+        info!("blocked {peer} from {reason} for domain {}, upstream: {}", &domain, upstream_server.label());
+        return;
+    }
+
+    if let Some(maintenance) = &upstream_server.maintenance {
+        info!("maintenance for domain {}, upstream: {}", &domain, upstream_server.label());
+        if serve_maintenance(&mut minecraft, &handshake, maintenance).await.is_none() {
+            warn!("failed to serve maintenance for domain {:#?}", &domain);
+        }
+        return;
+    }
+    if handshake.next_state == NEXT_STATE_TRANSFER {
+        if !config.allows_transfer(&upstream_server) {
+            info!("transfer denied for domain {}, upstream: {}", &domain, upstream_server.label());
+            if kick(&mut minecraft, TRANSFER_DENIED_MESSAGE).await.is_none() {
+                warn!("failed to kick transferred client for domain {:#?}", &domain);
+            }
+            return;
+        }
+        info!("transfer from another server for domain {}, upstream: {}", &domain, upstream_server.label());
+    }
+    if is_login(handshake.next_state) && !upstream_server.allows_protocol_version(handshake.protocol_version) {
+        info!("unsupported protocol_version {} for domain {}, upstream: {}", ProtocolVersion(handshake.protocol_version), &domain, upstream_server.label());
+        if kick(&mut minecraft, upstream_server.unsupported_version_message()).await.is_none() {
+            warn!("failed to kick client with unsupported version for domain {:#?}", &domain);
+        }
+        return;
+    }
+    if upstream_server.blocks_protocol_version(handshake.protocol_version) {
+        info!("blocked protocol_version {} for domain {}, upstream: {}", ProtocolVersion(handshake.protocol_version), &domain, upstream_server.label());
+        return;
+    }
+
+    if handshake.next_state == NEXT_STATE_STATUS && upstream_server.status_cache_ttl().is_some() {
+        serve_cached_status(&mut minecraft, &state, &config, &handshake, &domain, &upstream_server, peer_address).await;
+        return;
+    }
+
+    let logged = config.samples_connection_log();
+    if logged {
+        info!("new connection (protocol_version: {}, domain: {}, upstream: {})", ProtocolVersion(handshake.protocol_version), &domain, upstream_server.label());
+    }
+
+    let connect_options = ConnectOptions {
+        addresses: state.resolver.cached(&upstream_server.proxy_pass),
+        ..config.connect_options(&upstream_server)
+    };
+    let mut upstream = match connect_upstream(&upstream_server.proxy_pass, &connect_options).await {
+        Ok(x) => x,
+        Err(e) => match &upstream_server.on_demand {
+            // the player waits in the login state while the server starts
+            Some(on_demand) if is_login(handshake.next_state) => {
+                if login_start.is_empty() {
+                    match read_login_start(&mut minecraft, handshake.protocol_version).await {
+                        Ok((packet, _)) => login_start = packet,
+                        Err(err) => {
+                            warn!("failed to read login start from {peer} for domain {domain}: {err}");
+                            return;
+                        }
+                    }
+                }
+                state.launcher.launch(&upstream_server.proxy_pass, on_demand);
+                let wait = wait_upstream(&upstream_server.proxy_pass, &connect_options, hold_timeout(on_demand, handshake.protocol_version), on_demand.poll_interval());
+                match hold_client(&mut minecraft, handshake.protocol_version, HOLD_KEEP_ALIVE_INTERVAL, wait).await {
+                    Ok(Ok(x)) => x,
+                    Err(err) => {
+                        info!("{peer} left while waiting for upstream: {}, {err}", upstream_server.label());
+                        return;
+                    },
+                    Ok(Err(e)) => {
+                        warn!("upstream is not started: {}, {e}", upstream_server.label());
+                        if kick(&mut minecraft, on_demand.starting_message()).await.is_none() {
+                            warn!("failed to kick client waiting for upstream: {}", upstream_server.label());
+                        }
+                        return;
+                    }
+                }
+            },
+            _ => {
+                error!("failed to connect upstream: {}, {e}", upstream_server.label());
+                connect_failed(&state, &upstream_server);
+                return;
+            }
+        }
+    };
+    if let Err(e) = send_proxy_header(&mut upstream, &upstream_server, peer_address, config.upstream_write_timeout()).await {
+        error!("failed to send PROXY protocol header to upstream: {}, {e}", upstream_server.label());
+        return;
+    }
+    let packet = if upstream_server.preserve_handshake == Some(true) {
+        raw_handshake
+    }
+    else {
+        match MinecraftPacket::make_raw(0, &upstream_handshake(&handshake, &upstream_server)) {
+            Some(v) => v,
+            None => return
+        }
+    };
+    let handshake_len = packet.len() as u64;
+    // the unread buffer may already contain the next packets of the client,
+    // they are forwarded as they are, the client gets one handshake parsed and never another one, even if it sends it
+    let unread = minecraft.take_buffer();
+    let forwarded = (packet.len() + login_start.len() + unread.len()) as u64;
+    let (mut client_dump, upstream_dump) = match upstream_server.debug_dump {
+        Some(true) => (
+            Dump::new(format!("dump {peer} > {}", upstream_server.label()), DEBUG_DUMP_BYTES),
+            Dump::new(format!("dump {peer} < {}", upstream_server.label()), DEBUG_DUMP_BYTES)
+        ),
+        _ => (Dump::disabled(), Dump::disabled())
+    };
+    let first = [packet, login_start, unread].concat();
+    client_dump.write(&first);
+    if let Err(e) = write_upstream(&mut upstream, &first, config.upstream_write_timeout()).await {
+        if e.kind() == io::ErrorKind::TimedOut {
+            Stats::increment(&state.stats.upstream_write_timeouts);
+        }
+        error!("failed to send handshake to upstream: {}, {e}", upstream_server.label());
+        return;
+    }
+    if is_login(handshake.next_state) {
+        state.stats.update_upstream(&upstream_server.label(), |x| x.logins += 1);
+    }
+    let _online = is_login(handshake.next_state).then(|| state.stats.online(upstream_server.label()));
+    state.observer.on_connected(&handshake, &upstream_server);
+    let mut observed = ObservedSession {
+        observer: state.observer.as_ref(),
+        upstream: &upstream_server,
+        bytes_in: forwarded,
+        bytes_out: 0
+    };
+
+    let (client_reader, client_writer) = client.split_halves();
+    let (upstream_reader, upstream_writer) = upstream.into_split();
+    let mut kicks = state.kicks.subscribe();
+    let (client_close_sender, client_close_receiver) = oneshot::channel::<()>();
+    let (upstream_close_sender, upstream_close_receiver) = oneshot::channel::<()>();
+    let (sent_bandwidth, received_bandwidth) = match upstream_server.max_bandwidth_bytes_per_sec {
+        Some(limit) => {
+            let (sent, received) = state.bandwidth.buckets(&upstream_server.label(), limit);
+            (Some(sent), Some(received))
+        },
+        None => (None, None)
+    };
+    let sent = forward_stream(
+        client_close_sender,
+        upstream_close_receiver,
+        DumpReader::new(client_reader, client_dump),
+        upstream_writer,
+        upstream_server.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE) as usize,
+        sent_bandwidth);
+    let received = forward_stream(
+        upstream_close_sender,
+        client_close_receiver,
+        DumpReader::new(upstream_reader, upstream_dump),
+        client_writer,
+        upstream_server.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE) as usize,
+        received_bandwidth);
+    // aborting the session at shutdown stops forwarding too
+    let (sent, received) = (AbortOnDrop(sent), AbortOnDrop(received));
+    let kicked = async {
+        loop {
+            match kicks.recv().await {
+                Ok(kick) if kick == domain => return,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => pending::<()>().await
+            }
+        }
+    };
+    let (sent, received) = tokio::select! {
+        result = async { tokio::join!(sent, received) } => match result {
+            (Ok(sent), Ok(received)) => (sent, received),
+            _ => return
+        },
+        _ = kicked => {
+            info!("kicked {peer} from domain {}", &domain);
+            return;
+        }
+    };
+    // reading the upstream fails when the backend crashes or resets the connection
+    if let Closed::ReadError(_) = received.closed {
+        warn!("upstream connection failed for domain {}, upstream: {}, {}", &domain, upstream_server.label(), received.closed);
+    }
+    if logged {
+        info!("connection closed for domain {} (client: {}, upstream: {})", &domain, sent.closed, received.closed);
+    }
+    if handshake.next_state == NEXT_STATE_STATUS && received.bytes > 0 {
+        state.stats.update_upstream(&upstream_server.label(), |x| x.status_fetches += 1);
+    }
+    // a real server list ping sends the status request right after the handshake
+    if handshake.next_state == NEXT_STATE_STATUS && forwarded + sent.bytes == handshake_len && sent.closed == Closed::Eof {
+        Stats::increment(&state.stats.scanners);
+        info!("scanner {peer} left right after status handshake for domain {}", &domain);
+    }
+    state.stats.bytes_in.fetch_add(forwarded + sent.bytes, Ordering::Relaxed);
+    state.stats.bytes_out.fetch_add(received.bytes, Ordering::Relaxed);
+    observed.bytes_in += sent.bytes;
+    observed.bytes_out = received.bytes;
+    if let Some(access_log) = &state.access_log {
+        access_log.write(&Session {
+            client: &peer,
+            domain: &domain,
+            upstream: &upstream_server.proxy_pass,
+            bytes_in: forwarded + sent.bytes,
+            bytes_out: received.bytes,
+            duration: started.elapsed()
+        });
+    }
+}
+
+/// Answers a status handshake from `status_cache`, the upstream is asked only when the cached response is expired
+async fn serve_cached_status<S>(
+    client: &mut MinecraftStream<&mut S>,
+    state: &State,
+    config: &MineginxConfig,
+    handshake: &HandshakeC2SPacket,
+    domain: &str,
+    server: &MinecraftServerDescription,
+    peer_address: Option<SocketAddr>
+) where S: AsyncRead + AsyncWrite + Unpin {
+    let peer = peer_name(peer_address);
+    match read_status_request(client).await {
+        Ok(()) => {},
+        Err(ReadingError::Closed | ReadingError::ClosedEmpty) => {
+            Stats::increment(&state.stats.scanners);
+            info!("scanner {peer} left right after status handshake for domain {domain}");
+            return;
+        },
+        Err(err) => {
+            warn!("failed to read status request from {peer} for domain {domain}: {err}");
+            return;
+        }
+    }
+    let key = StatusKey {
+        proxy_pass: server.proxy_pass.clone(),
+        domain: domain.to_string(),
+        protocol_version: handshake.protocol_version
+    };
+    let json_response = match state.status_cache.get(&key) {
+        Some(x) => x,
+        None => {
+            let connect_options = ConnectOptions {
+                addresses: state.resolver.cached(&server.proxy_pass),
+                ..config.connect_options(server)
+            };
+            let mut upstream = match connect_upstream(&server.proxy_pass, &connect_options).await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("failed to connect upstream: {}, {e}", server.label());
+                    connect_failed(state, server);
+                    return;
+                }
+            };
+            if let Err(e) = send_proxy_header(&mut upstream, server, peer_address, config.upstream_write_timeout()).await {
+                error!("failed to send PROXY protocol header to upstream: {}, {e}", server.label());
+                return;
+            }
+            // the upstream gets as long to answer as the client had to send its handshake
+            let fetched = fetch_status(&mut upstream, &upstream_handshake(handshake, server), config.handshake_buffer_size(), config.handshake_timeout(server)).await;
+            let json_response = match fetched {
+                Some(x) => x,
+                None => {
+                    warn!("failed to fetch status from upstream: {}", server.label());
+                    return;
+                }
+            };
+            info!("fetched status for domain {domain}, upstream: {}", server.label());
+            state.stats.update_upstream(&server.label(), |x| x.status_fetches += 1);
+            if let Some(ttl) = server.status_cache_ttl() {
+                state.status_cache.insert(key, json_response.clone(), ttl);
+            }
+            json_response
+        }
+    };
+    if answer_status(client, json_response).await.is_none() {
+        debug!("{peer} didn't finish the status ping for domain {domain}");
+    }
+}
+
+/// A backend of a pool which can't be connected gets no clients for a while
+fn connect_failed(state: &State, server: &MinecraftServerDescription) {
+    if server.proxy_pass_pool.is_some() {
+        state.pools.mark_down(&server.proxy_pass);
+    }
+}
+
+/// The handshake as `proxy_pass` gets it, with `upstream_hostname` in the domain and `rewrite_port`
+fn upstream_handshake(handshake: &HandshakeC2SPacket, server: &MinecraftServerDescription) -> HandshakeC2SPacket {
+    HandshakeC2SPacket {
+        protocol_version: handshake.protocol_version,
+        domain: match &server.upstream_hostname {
+            Some(host) => replace_host(&handshake.domain, host),
+            None => handshake.domain.clone()
+        },
+        server_port: server.rewrite_port.unwrap_or(handshake.server_port),
+        next_state: handshake.next_state
+    }
+}
+
+/// Sends the header of `send_proxy_protocol`, before anything else is written to the upstream
+async fn send_proxy_header(upstream: &mut TcpStream, server: &MinecraftServerDescription, client: Option<SocketAddr>, write_timeout: Duration) -> io::Result<()> {
+    let version = match server.send_proxy_protocol {
+        Some(x) => x,
+        None => return Ok(())
+    };
+    let header = encode_header(version, client, upstream.peer_addr().ok());
+    write_upstream(upstream, &header, write_timeout).await
+}
+
+fn peer_name(peer_address: Option<SocketAddr>) -> String {
+    match peer_address {
+        Some(x) => x.ip().to_string(),
+        None => "unix".to_string()
+    }
+}
+
+async fn handle_address(listener: Listener, state: Arc<State>, address: String) {
+    match listener {
+        Listener::Tcp(listener) => loop {
+            let (socket, peer) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("failed to accept client: {e}");
+                    continue;
+                }
+            };
+            if let Err(e) = socket.set_nodelay(state.config.get().tcp_nodelay()) {
+                error!("failed to set no_delay for client: {}", e);
+                continue;
+            }
+            spawn_client(socket, state.clone(), address.clone(), Some(peer));
+        },
+        #[cfg(unix)]
+        Listener::Unix(listener) => loop {
+            let (socket, _address) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("failed to accept client: {e}");
+                    continue;
+                }
+            };
+            spawn_client(socket, state.clone(), address.clone(), None);
+        }
+    }
+}
+
+/// Proxies a client accepted by the embedding application, such as one after TLS termination, see `stream::SharedStream`  
+/// `listen` is the `listen` address of the servers it may reach, the session is awaited at shutdown like the accepted ones
+pub fn serve_stream<S>(stream: S, state: &Arc<State>, listen: &str, peer_address: Option<SocketAddr>) where S: SplitStream {
+    spawn_client(stream, state.clone(), listen.to_string(), peer_address);
+}
+
+fn spawn_client<S>(socket: S, state: Arc<State>, listen: String, peer: Option<SocketAddr>) where S: SplitStream {
+    let mut sessions = state.sessions.lock().unwrap();
+    // a finished session keeps its result until it is joined
+    while sessions.try_join_next().is_some() {}
+    let session_state = state.clone();
+    sessions.spawn(async move {
+        handle_client(socket, session_state, &listen, peer).await;
+    });
+}
+
@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+
+use log::{error, info};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use crate::config::{unique_listen_addresses, MineginxConfig};
+
+/// `listen` addresses with this prefix are paths to unix domain sockets
+pub const UNIX_PREFIX: &str = "unix:";
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener)
+}
+
+impl Listener {
+    /// The bound address of a tcp listener, with the port the system picked for port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(x) => x.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(_) => None
+        }
+    }
+}
+
+pub async fn bind_address(address: &str) -> Option<Listener> {
+    if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+        return bind_unix(path);
+    }
+    match TcpListener::bind(address).await {
+        Ok(x) => Some(Listener::Tcp(x)),
+        Err(err) => {
+            error!("failed to listen {address}: {err}");
+            None
+        }
+    }
+}
+
+/// A socket file left by the previous run is removed, anything else at the path is an error
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Option<Listener> {
+    use std::{fs, os::unix::fs::FileTypeExt};
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            if let Err(err) = fs::remove_file(path) {
+                error!("failed to remove old socket {path}: {err}");
+                return None;
+            }
+        }
+    }
+    match UnixListener::bind(path) {
+        Ok(x) => Some(Listener::Unix(x)),
+        Err(err) => {
+            error!("failed to listen {UNIX_PREFIX}{path}: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &str) -> Option<Listener> {
+    error!("failed to listen {UNIX_PREFIX}{path}: unix domain sockets are not supported on this platform");
+    None
+}
+
+/// Binds every unique `listen` address of the config  
+/// An address that can't be bound is skipped, so the others keep working
+pub async fn bind_listeners(config: &MineginxConfig) -> Vec<(String, Listener)> {
+    let mut listeners = vec![];
+    for address in unique_listen_addresses(config) {
+        if let Some(listener) = bind_address(address).await {
+            match listener.local_addr() {
+                Some(bound) if bound.to_string() != *address => info!("listening {address} on {bound}"),
+                _ => info!("listening {address}")
+            }
+            listeners.push((address.clone(), listener));
+        }
+    }
+    listeners
+}
@@ -0,0 +1,41 @@
+use std::net::SocketAddr;
+
+use minecraft::packets::HandshakeC2SPacket;
+
+use crate::router::UpstreamTarget;
+
+/// Callbacks on the way of each client, for accounting of an application which embeds the proxy  
+/// They are called on the task of the client, so they should be quick, every method does nothing by default
+pub trait ConnectionObserver: Send + Sync {
+    /// The handshake is read, `peer` is `None` for unix domain sockets
+    fn on_handshake(&self, _handshake: &HandshakeC2SPacket, _peer: Option<SocketAddr>) {}
+
+    /// The router chose `upstream`, the client may still be rejected, for example by `block_countries`
+    fn on_upstream_selected(&self, _handshake: &HandshakeC2SPacket, _upstream: &UpstreamTarget) {}
+
+    /// The upstream got the handshake and the client is proxied to it from now on
+    fn on_connected(&self, _handshake: &HandshakeC2SPacket, _upstream: &UpstreamTarget) {}
+
+    /// The session after `on_connected` ended, however it ended, with the bytes forwarded from the client and to it  
+    /// The bytes are only the ones of the handshake if the session was kicked or aborted at shutdown
+    fn on_closed(&self, _upstream: &UpstreamTarget, _bytes_in: u64, _bytes_out: u64) {}
+}
+
+/// The observer used unless another one is given
+pub struct NoopObserver;
+
+impl ConnectionObserver for NoopObserver {}
+
+/// Calls `on_closed` when dropped, so it is called when the session task is aborted too
+pub(crate) struct ObservedSession<'a> {
+    pub observer: &'a dyn ConnectionObserver,
+    pub upstream: &'a UpstreamTarget,
+    pub bytes_in: u64,
+    pub bytes_out: u64
+}
+
+impl Drop for ObservedSession<'_> {
+    fn drop(&mut self) {
+        self.observer.on_closed(self.upstream, self.bytes_in, self.bytes_out);
+    }
+}
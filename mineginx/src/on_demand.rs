@@ -0,0 +1,151 @@
+use std::{collections::HashMap, future::Future, io, sync::Mutex, time::{Duration, Instant}};
+
+use log::{error, info, warn};
+use minecraft::{packets::LoginPluginRequestS2CPacket, serialization::{read_varint, MinecraftStream, ReadingError}};
+use tokio::{io::{AsyncRead, AsyncWrite}, net::TcpStream, process::Command, time::{interval, sleep}};
+
+use crate::{config::OnDemand, upstream::{connect_upstream, ConnectOptions}};
+
+/// Login Plugin Request exists since 1.13, older clients get nothing while they wait
+const LOGIN_PLUGIN_VERSION: i32 = 393;
+const LOGIN_PLUGIN_REQUEST_ID: i32 = 4;
+const LOGIN_PLUGIN_RESPONSE_ID: i32 = 2;
+/// The channel of the requests which keep a waiting client, the clients answer that they don't know it
+pub const HOLD_CHANNEL: &str = "mineginx:hold";
+/// How often a waiting client gets a Login Plugin Request, the client disconnects after 30 seconds without a packet
+pub const HOLD_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// The longest wait of a client which can't be sent anything, within its 30 seconds
+const LEGACY_HOLD_TIMEOUT: Duration = Duration::from_secs(25);
+/// A waiting client which doesn't answer for this long is gone
+const HOLD_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Remembers which servers are starting, so their command doesn't run for every waiting player
+#[derive(Default)]
+pub struct Launcher {
+    started: Mutex<HashMap<String, Instant>>
+}
+
+impl Launcher {
+    /// Runs `on_demand.command` unless it already ran for `proxy_pass` within `start_timeout_ms`
+    /// Returns whether the command is started by this call, never without a command
+    pub fn launch(&self, proxy_pass: &str, on_demand: &OnDemand) -> bool {
+        let command = match &on_demand.command {
+            Some(x) => x,
+            None => return false
+        };
+        {
+            let mut started = self.started.lock().unwrap();
+            if started.get(proxy_pass).is_some_and(|x| x.elapsed() < on_demand.start_timeout()) {
+                return false;
+            }
+            started.insert(proxy_pass.to_string(), Instant::now());
+        }
+        info!("starting upstream {proxy_pass}: {command}");
+        let mut child = match shell(command).spawn() {
+            Ok(x) => x,
+            Err(err) => {
+                error!("failed to start upstream {proxy_pass}: {err}");
+                return true;
+            }
+        };
+        let proxy_pass = proxy_pass.to_string();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) if !status.success() => warn!("start command of upstream {proxy_pass} exited with {status}"),
+                Err(err) => warn!("failed to wait for start command of upstream {proxy_pass}: {err}"),
+                _ => {}
+            }
+        });
+        true
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Connects to `proxy_pass` every `poll_interval` until it comes up or `timeout` passes
+pub async fn wait_upstream(proxy_pass: &str, options: &ConnectOptions, timeout: Duration, poll_interval: Duration) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let attempt = ConnectOptions {
+            timeout: remaining.min(options.timeout),
+            retries: 0,
+            addresses: options.addresses.clone(),
+            ..*options
+        };
+        let err = match connect_upstream(proxy_pass, &attempt).await {
+            Ok(x) => return Ok(x),
+            Err(err) => err
+        };
+        if deadline.saturating_duration_since(Instant::now()) <= poll_interval {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("upstream is not up in {}ms: {err}", timeout.as_millis())));
+        }
+        sleep(poll_interval).await;
+    }
+}
+
+/// How long a client of `protocol_version` may wait for the server, clients before 1.13 can't be kept longer than their timeout
+pub fn hold_timeout(on_demand: &OnDemand, protocol_version: i32) -> Duration {
+    match protocol_version >= LOGIN_PLUGIN_VERSION {
+        true => on_demand.start_timeout(),
+        false => on_demand.start_timeout().min(LEGACY_HOLD_TIMEOUT)
+    }
+}
+
+/// Keeps the client in the login state until `upstream` is done, the client must have sent Login Start  
+/// It gets a Login Plugin Request every `keep_alive` and its answers are dropped, so the upstream never sees them  
+/// `Err` once the client leaves, `upstream` is dropped then and stops polling
+pub async fn hold_client<S, F>(client: &mut MinecraftStream<&mut S>, protocol_version: i32, keep_alive: Duration, upstream: F) -> Result<io::Result<TcpStream>, ReadingError>
+where S: AsyncRead + AsyncWrite + Unpin, F: Future<Output = io::Result<TcpStream>> {
+    tokio::pin!(upstream);
+    client.set_read_timeout(Some(HOLD_READ_TIMEOUT));
+    let sends_requests = protocol_version >= LOGIN_PLUGIN_VERSION;
+    let mut ticks = interval(keep_alive);
+    let mut sent = 0;
+    let mut answered = 0;
+    let result = loop {
+        // reading a whole packet is cancel safe, the bytes stay buffered until it is complete
+        tokio::select! {
+            result = &mut upstream => break result,
+            _ = ticks.tick(), if sends_requests => {
+                let request = LoginPluginRequestS2CPacket { message_id: sent, channel: HOLD_CHANNEL.to_string() };
+                client.write_packet_with_id(LOGIN_PLUGIN_REQUEST_ID, &request).await.ok_or(ReadingError::Closed)?;
+                sent += 1;
+            },
+            packet = client.read_raw_packet() => {
+                read_plugin_response(&packet?, answered)?;
+                answered += 1;
+            }
+        }
+    };
+    // the answers are not forwarded, so none may be left in the stream, a client which is kicked needn't answer
+    while result.is_ok() && answered < sent {
+        read_plugin_response(&client.read_raw_packet().await?, answered)?;
+        answered += 1;
+    }
+    Ok(result)
+}
+
+/// A client sends nothing else in the login state before the server answers its Login Start
+fn read_plugin_response(packet: &[u8], message_id: i32) -> Result<(), ReadingError> {
+    // the packet is whole, so a field which doesn't fit in it is invalid
+    let (_length, size) = read_varint(packet).map_err(|_| ReadingError::Invalid)?;
+    let (packet_id, id_size) = read_varint(&packet[size..]).map_err(|_| ReadingError::Invalid)?;
+    let (answered, _) = read_varint(&packet[size + id_size..]).map_err(|_| ReadingError::Invalid)?;
+    if packet_id != LOGIN_PLUGIN_RESPONSE_ID || answered != message_id {
+        return Err(ReadingError::Invalid);
+    }
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Mutex, time::{Duration, Instant}};
+
+use crate::config::PoolBackend;
+
+/// How long a backend of a pool gets no clients after a failed connection
+pub const BACKEND_DOWN_INTERVAL: Duration = Duration::from_secs(10);
+/// Players whose backend `sticky_sessions` remembers
+pub const STICKY_SESSIONS_LIMIT: usize = 100_000;
+
+/// Chooses backends of `proxy_pass_pool` with the smooth weighted round robin of nginx:  
+/// each choice adds the weights to the current ones of the backends, the biggest current one wins and loses the total weight  
+/// So `a: 5, b: 1, c: 1` goes `a a b a c a a` rather than five times `a` in a row
+#[derive(Default)]
+pub struct Pools {
+    /// Current weights of each pool, a reloaded pool with other backends starts over
+    current: Mutex<HashMap<Vec<PoolBackend>, Vec<i64>>>,
+    /// Backends which failed to connect and when
+    down: Mutex<HashMap<String, Instant>>,
+    affinity: Affinity
+}
+
+impl Pools {
+    /// Backends which are down are skipped, unless all of them are
+    pub fn select<'a>(&self, pool: &'a [PoolBackend]) -> Option<&'a PoolBackend> {
+        let up: Vec<bool> = pool.iter().map(|x| !self.is_down(&x.addr)).collect();
+        let candidate = |index: usize| up[index] || !up.contains(&true);
+        let mut pools = self.current.lock().unwrap();
+        let current = pools.entry(pool.to_vec()).or_insert_with(|| vec![0; pool.len()]);
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for (index, backend) in pool.iter().enumerate().filter(|(index, _)| candidate(*index)) {
+            current[index] += backend.weight() as i64;
+            total += backend.weight() as i64;
+            match best {
+                Some(x) if current[x] >= current[index] => {},
+                _ => best = Some(index)
+            }
+        }
+        let best = best?;
+        current[best] -= total;
+        Some(&pool[best])
+    }
+
+    /// The backend `player` got from this pool before, unless it is down, otherwise the one `select` chooses
+    pub fn select_sticky<'a>(&self, pool: &'a [PoolBackend], player: &str) -> Option<&'a PoolBackend> {
+        let addresses: Vec<&str> = pool.iter().map(|x| x.addr.as_str()).collect();
+        let key = format!("{}/{player}", addresses.join(","));
+        let remembered = self.affinity.get(&key)
+            .and_then(|addr| pool.iter().find(|x| x.addr == addr))
+            .filter(|x| !self.is_down(&x.addr));
+        if remembered.is_some() {
+            return remembered;
+        }
+        let backend = self.select(pool)?;
+        self.affinity.insert(key, backend.addr.clone());
+        Some(backend)
+    }
+
+    fn is_down(&self, addr: &str) -> bool {
+        let mut down = self.down.lock().unwrap();
+        down.retain(|_, since| since.elapsed() < BACKEND_DOWN_INTERVAL);
+        down.contains_key(addr)
+    }
+
+    /// The backend gets no clients for `BACKEND_DOWN_INTERVAL` unless the rest of its pool is down too
+    pub fn mark_down(&self, addr: &str) {
+        self.down.lock().unwrap().insert(addr.to_string(), Instant::now());
+    }
+}
+
+/// Backends of the players of `sticky_sessions`, once it is full the player not seen for the longest is forgotten
+pub struct Affinity {
+    capacity: usize,
+    entries: Mutex<AffinityEntries>
+}
+
+#[derive(Default)]
+struct AffinityEntries {
+    /// The backend of each player and when the player was last seen
+    backends: HashMap<String, (String, u64)>,
+    /// Players by when they were last seen, the oldest first
+    seen: BTreeMap<u64, String>,
+    clock: u64
+}
+
+impl Default for Affinity {
+    fn default() -> Affinity {
+        Affinity::new(STICKY_SESSIONS_LIMIT)
+    }
+}
+
+impl Affinity {
+    pub fn new(capacity: usize) -> Affinity {
+        Affinity {
+            capacity,
+            entries: Mutex::new(AffinityEntries::default())
+        }
+    }
+
+    /// The backend of the player, who is now the last one seen
+    pub fn get(&self, player: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        entries.clock += 1;
+        let (backend, seen) = entries.backends.get_mut(player)?;
+        entries.seen.remove(seen);
+        *seen = entries.clock;
+        entries.seen.insert(entries.clock, player.to_string());
+        Some(backend.clone())
+    }
+
+    pub fn insert(&self, player: String, backend: String) {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        entries.clock += 1;
+        if let Some((_, seen)) = entries.backends.remove(&player) {
+            entries.seen.remove(&seen);
+        }
+        while entries.backends.len() >= self.capacity.max(1) {
+            match entries.seen.pop_first() {
+                Some((_, oldest)) => entries.backends.remove(&oldest),
+                None => break
+            };
+        }
+        entries.seen.insert(entries.clock, player.clone());
+        entries.backends.insert(player, (backend, entries.clock));
+    }
+}
@@ -0,0 +1,80 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use minecraft::packets::HandshakeC2SPacket;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    respond::NEXT_STATE_STATUS,
+    send_proxy_header,
+    srv::SrvCache,
+    status_cache::fetch_status,
+    upstream::connect_upstream,
+    upstream_handshake
+};
+
+/// Server list pingers send it when they don't know the version of the server
+pub const PROBE_PROTOCOL_VERSION: i32 = -1;
+
+/// What `--probe` found out about one `proxy_pass`, or `proxy_pass_srv`
+#[derive(Debug)]
+pub struct Probe {
+    pub proxy_pass: String,
+    /// How long connecting and fetching the status took, or why it failed
+    pub result: Result<Duration, String>
+}
+
+/// Asks each `proxy_pass` of `config`, each backend of `proxy_pass_pool` and the target of each `proxy_pass_srv` for its status the way mineginx does for a client of the first server with it  
+/// The upstreams are probed at the same time, the results keep the order of the servers
+pub async fn probe_upstreams(config: Arc<MineginxConfig>) -> Vec<Probe> {
+    let mut probes: Vec<(String, JoinHandle<Result<Duration, String>>)> = vec![];
+    for server in &config.servers {
+        for proxy_pass in server.upstream_addresses() {
+            if probes.iter().any(|(x, _)| x == proxy_pass) {
+                continue;
+            }
+            let config = config.clone();
+            let server = MinecraftServerDescription { proxy_pass: proxy_pass.clone(), ..server.clone() };
+            probes.push((proxy_pass.clone(), tokio::spawn(async move { probe(&config, &server).await })));
+        }
+        if let Some(name) = server.proxy_pass_srv.clone() {
+            if probes.iter().any(|(x, _)| *x == name) {
+                continue;
+            }
+            let config = config.clone();
+            let server = server.clone();
+            probes.push((name.clone(), tokio::spawn(async move {
+                let target = SrvCache::default().resolve(&name).await.map_err(|e| format!("failed to resolve SRV record: {e}"))?;
+                probe(&config, &MinecraftServerDescription { proxy_pass: target, ..server }).await
+            })));
+        }
+    }
+    let mut results = vec![];
+    for (proxy_pass, probe) in probes {
+        let result = match probe.await {
+            Ok(x) => x,
+            Err(e) => Err(e.to_string())
+        };
+        results.push(Probe { proxy_pass, result });
+    }
+    results
+}
+
+async fn probe(config: &MineginxConfig, server: &MinecraftServerDescription) -> Result<Duration, String> {
+    let started = Instant::now();
+    let options = config.connect_options(server);
+    let mut upstream = connect_upstream(&server.proxy_pass, &options).await.map_err(|e| e.to_string())?;
+    // there is no client, so the header tells that the address is unknown
+    send_proxy_header(&mut upstream, server, None, config.upstream_write_timeout()).await
+        .map_err(|e| format!("failed to send PROXY protocol header: {e}"))?;
+    let handshake = HandshakeC2SPacket {
+        protocol_version: PROBE_PROTOCOL_VERSION,
+        domain: server.server_names.first().cloned().unwrap_or_default(),
+        server_port: upstream.peer_addr().map(|x| x.port()).unwrap_or_default(),
+        next_state: NEXT_STATE_STATUS
+    };
+    match fetch_status(&mut upstream, &upstream_handshake(&handshake, server), config.handshake_buffer_size(), options.timeout).await {
+        Some(_) => Ok(started.elapsed()),
+        None => Err(format!("no status response in {}ms", options.timeout.as_millis()))
+    }
+}
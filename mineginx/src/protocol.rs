@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// Release versions of each protocol number, a new release is one more line  
+/// https://wiki.vg/Protocol_version_numbers
+const VERSIONS: &[(i32, &str)] = &[
+    (47, "1.8-1.8.9"),
+    (107, "1.9"),
+    (108, "1.9.1"),
+    (109, "1.9.2"),
+    (110, "1.9.3-1.9.4"),
+    (210, "1.10-1.10.2"),
+    (315, "1.11"),
+    (316, "1.11.1-1.11.2"),
+    (335, "1.12"),
+    (338, "1.12.1"),
+    (340, "1.12.2"),
+    (393, "1.13"),
+    (401, "1.13.1"),
+    (404, "1.13.2"),
+    (477, "1.14"),
+    (480, "1.14.1"),
+    (485, "1.14.2"),
+    (490, "1.14.3"),
+    (498, "1.14.4"),
+    (573, "1.15"),
+    (575, "1.15.1"),
+    (578, "1.15.2"),
+    (735, "1.16"),
+    (736, "1.16.1"),
+    (751, "1.16.2"),
+    (753, "1.16.3"),
+    (754, "1.16.4-1.16.5"),
+    (755, "1.17"),
+    (756, "1.17.1"),
+    (757, "1.18-1.18.1"),
+    (758, "1.18.2"),
+    (759, "1.19"),
+    (760, "1.19.1-1.19.2"),
+    (761, "1.19.3"),
+    (762, "1.19.4"),
+    (763, "1.20-1.20.1"),
+    (764, "1.20.2"),
+    (765, "1.20.3-1.20.4"),
+    (766, "1.20.5-1.20.6"),
+    (767, "1.21-1.21.1"),
+    (768, "1.21.2-1.21.3"),
+    (769, "1.21.4"),
+    (770, "1.21.5"),
+    (771, "1.21.6"),
+    (772, "1.21.7-1.21.8"),
+    (773, "1.21.9-1.21.10")
+];
+
+/// Minecraft versions which use `protocol_version`, like `1.20.3-1.20.4` for 765
+pub fn version_name(protocol_version: i32) -> Option<&'static str> {
+    VERSIONS.iter().find(|(protocol, _)| *protocol == protocol_version).map(|(_, name)| *name)
+}
+
+/// Shows the number with its versions for logs, like `765 (1.20.3-1.20.4)` or `999 (unknown)`
+pub struct ProtocolVersion(pub i32);
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.0, version_name(self.0).unwrap_or("unknown"))
+    }
+}
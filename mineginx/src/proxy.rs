@@ -0,0 +1,255 @@
+use std::{fmt, future::Future, io, net::SocketAddr, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use log::{error, info, warn};
+use maxminddb::MaxMindDBError;
+use tokio::{net::TcpListener, task::JoinSet, time::timeout};
+
+use crate::{
+    access_log::AccessLog,
+    bans::Bans,
+    config::MineginxConfig,
+    control::serve_control,
+    handle_address,
+    geoip::GeoIp,
+    health::serve_health,
+    listener::{bind_address, bind_listeners, Listener},
+    observer::ConnectionObserver,
+    router::Router,
+    srv::{SrvCache, SrvLookup},
+    state::State
+};
+
+/// A proxy which is configured, but doesn't listen yet
+pub struct Proxy {
+    config: Arc<MineginxConfig>,
+    router: Option<Arc<dyn Router>>,
+    observer: Option<Arc<dyn ConnectionObserver>>,
+    srv_lookup: Option<Arc<dyn SrvLookup>>,
+    config_path: Option<PathBuf>
+}
+
+/// A proxy with bound listeners, which doesn't accept clients until `run`
+pub struct BoundProxy {
+    state: Arc<State>,
+    listeners: Vec<(String, Listener)>
+}
+
+/// How the proxy stopped after the shutdown signal
+#[derive(PartialEq, Debug)]
+pub enum Shutdown {
+    /// Every session finished within `shutdown_grace_ms`, or there is no grace period
+    Graceful,
+    /// Sessions still open after `shutdown_grace_ms` were aborted
+    Forced
+}
+
+#[derive(Debug)]
+pub enum StartError {
+    /// None of the `listen` addresses could be bound
+    NoListeners,
+    AccessLog(String, io::Error),
+    Bans(String, io::Error),
+    GeoIp(String, MaxMindDBError)
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartError::NoListeners => write!(f, "there are no addresses to listen"),
+            StartError::AccessLog(path, err) => write!(f, "failed to open access log '{path}': {err}"),
+            StartError::Bans(path, err) => write!(f, "failed to read bans file '{path}': {err}"),
+            StartError::GeoIp(path, err) => write!(f, "failed to open geoip database '{path}': {err}")
+        }
+    }
+}
+
+impl Proxy {
+    /// The config is expected to be validated already, see `config::validate`
+    pub fn new(config: MineginxConfig) -> Proxy {
+        Proxy {
+            config: Arc::new(config),
+            router: None,
+            observer: None,
+            srv_lookup: None,
+            config_path: None
+        }
+    }
+
+    /// Replaces the routing by `server_names`
+    pub fn with_router(mut self, router: Arc<dyn Router>) -> Proxy {
+        self.router = Some(router);
+        self
+    }
+
+    /// Gets the callbacks of each client, they do nothing by default
+    pub fn with_observer(mut self, observer: Arc<dyn ConnectionObserver>) -> Proxy {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Replaces the DNS queries of `proxy_pass_srv`, such as by the resolver the application already has
+    pub fn with_srv_lookup(mut self, lookup: Arc<dyn SrvLookup>) -> Proxy {
+        self.srv_lookup = Some(lookup);
+        self
+    }
+
+    /// The file which `reload` of the control socket reads
+    pub fn with_config_path(mut self, path: PathBuf) -> Proxy {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Binds the `listen` addresses and starts the access log, health check, control socket and resolving of upstreams
+    /// An address that can't be bound is skipped, unless it is the only one
+    pub async fn bind(self) -> Result<BoundProxy, StartError> {
+        let config = self.config;
+        let listeners = bind_listeners(&config).await;
+        if listeners.is_empty() {
+            return Err(StartError::NoListeners);
+        }
+        let access_log = match &config.access_log {
+            Some(path) => match AccessLog::open(Path::new(path)).await {
+                Ok(x) => Some(x),
+                Err(err) => return Err(StartError::AccessLog(path.clone(), err))
+            },
+            None => None
+        };
+        #[cfg(unix)]
+        if let Some(access_log) = access_log.clone() {
+            reopen_on_hangup(access_log);
+        }
+        let bans = match &config.bans_file {
+            Some(path) => match Bans::load(Path::new(path)) {
+                Ok(x) => x,
+                Err(err) => return Err(StartError::Bans(path.clone(), err))
+            },
+            None => Bans::default()
+        };
+        let geoip = GeoIp::open(config.geoip_country_database.as_deref().map(Path::new), config.geoip_asn_database.as_deref().map(Path::new))
+            .map_err(|(path, err)| StartError::GeoIp(path, err))?;
+        let default = State::new(config.clone());
+        let state = Arc::new(State {
+            access_log,
+            bans,
+            geoip,
+            router: self.router.unwrap_or(default.router.clone()),
+            observer: self.observer.unwrap_or(default.observer.clone()),
+            srv: match self.srv_lookup {
+                Some(lookup) => SrvCache::new(lookup),
+                None => default.srv
+            },
+            ..default
+        });
+        #[cfg(unix)]
+        log_stats_on_signal(state.clone());
+        if let Some(health_listen) = &config.health_listen {
+            match TcpListener::bind(health_listen).await {
+                Ok(listener) => {
+                    info!("health check listening {health_listen}");
+                    tokio::spawn(serve_health(listener, state.clone()));
+                },
+                Err(err) => error!("failed to listen health check {health_listen}: {err}")
+            }
+        }
+        if let Some(control_listen) = &config.control_listen {
+            if let Some(listener) = bind_address(control_listen).await {
+                info!("control listening {control_listen}");
+                tokio::spawn(serve_control(listener, state.clone(), self.config_path));
+            }
+        }
+        state.resolver.follow(&config).await;
+        Ok(BoundProxy { state, listeners })
+    }
+
+    /// Binds and serves clients until `shutdown` completes
+    pub async fn run<F>(self, shutdown: F) -> Result<Shutdown, StartError> where F: Future<Output = ()> {
+        Ok(self.bind().await?.run(shutdown).await)
+    }
+}
+
+impl BoundProxy {
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    /// Actual addresses of the tcp listeners, the port is known here even if `listen` asked for port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(|(_, listener)| listener.local_addr()).collect()
+    }
+
+    /// Accepts clients until `shutdown` completes, then stops accepting  
+    /// Clients which are already proxied get `shutdown_grace_ms` to finish, then they are aborted  
+    /// Without the grace period they are not interrupted
+    pub async fn run<F>(self, shutdown: F) -> Shutdown where F: Future<Output = ()> {
+        let mut listening = vec![];
+        for (address, listener) in self.listeners {
+            listening.push(tokio::spawn(handle_address(listener, self.state.clone(), address)));
+        }
+        shutdown.await;
+        self.state.begin_shutdown();
+        for task in listening {
+            task.abort();
+        }
+        let mut sessions = std::mem::take(&mut *self.state.sessions.lock().unwrap());
+        let grace = match self.state.config.get().shutdown_grace() {
+            Some(x) => x,
+            None => {
+                sessions.detach_all();
+                return Shutdown::Graceful;
+            }
+        };
+        drain(&mut sessions, grace).await
+    }
+}
+
+/// Waits for `sessions` to finish, aborts the ones left after `grace`
+async fn drain(sessions: &mut JoinSet<()>, grace: Duration) -> Shutdown {
+    while sessions.try_join_next().is_some() {}
+    if sessions.is_empty() {
+        return Shutdown::Graceful;
+    }
+    info!("waiting up to {}ms for {} sessions to finish", grace.as_millis(), sessions.len());
+    if timeout(grace, async { while sessions.join_next().await.is_some() {} }).await.is_ok() {
+        return Shutdown::Graceful;
+    }
+    warn!("{} sessions are still open after {}ms, aborting them", sessions.len(), grace.as_millis());
+    sessions.shutdown().await;
+    Shutdown::Forced
+}
+
+/// logrotate sends SIGHUP after moving the file away
+#[cfg(unix)]
+fn reopen_on_hangup(access_log: AccessLog) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("failed to listen for SIGHUP, access log will not be reopened: {err}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("reopen access log");
+            access_log.reopen();
+        }
+    });
+}
+
+/// `kill -USR1` writes the counters of the control `status` to the log
+#[cfg(unix)]
+fn log_stats_on_signal(state: Arc<State>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut user_defined = match signal(SignalKind::user_defined1()) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("failed to listen for SIGUSR1, stats will not be logged: {err}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while user_defined.recv().await.is_some() {
+            info!("stats: {}", state.summary());
+        }
+    });
+}
@@ -0,0 +1,167 @@
+use std::{io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::config::ProxyProtocolVersion;
+
+/// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// The longest v1 header, `PROXY UNKNOWN` with two ipv6 addresses and ports
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+const V2_HEADER_LEN: usize = 16;
+/// `PROXY UNKNOWN\r\n` is the shortest header of both versions, reading it at once can't take bytes of the handshake
+const MIN_HEADER_LEN: usize = 15;
+
+/// Reads a PROXY protocol v1 or v2 header, and nothing after it  
+/// Returns the client address the header carries, `None` if the sender doesn't know it, such as a health check of the load balancer
+pub async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>> where S: AsyncRead + Unpin {
+    let mut header = vec![0; MIN_HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    if header.starts_with(V1_PREFIX) {
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= V1_MAX_LEN {
+                return Err(invalid("PROXY v1 header is too long"));
+            }
+            header.push(stream.read_u8().await?);
+        }
+        return parse_v1(&header);
+    }
+    if header.starts_with(&V2_SIGNATURE) {
+        header.push(stream.read_u8().await?);
+        let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+        let mut addresses = vec![0; len];
+        stream.read_exact(&mut addresses).await?;
+        return parse_v2(&header, &addresses);
+    }
+    Err(invalid("there is no PROXY protocol header"))
+}
+
+/// `header` is the whole line including `\r\n`, like `PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565\r\n`
+pub fn parse_v1(header: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = header.strip_suffix(b"\r\n")
+        .and_then(|x| std::str::from_utf8(x).ok())
+        .ok_or_else(|| invalid("PROXY v1 header is not a text line"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    if let ["PROXY", "UNKNOWN", ..] = fields[..] {
+        return Ok(None);
+    }
+    let (protocol, source, destination, source_port, destination_port) = match fields[..] {
+        ["PROXY", protocol, source, destination, source_port, destination_port] => (protocol, source, destination, source_port, destination_port),
+        _ => return Err(invalid(&format!("malformed PROXY v1 header '{line}'")))
+    };
+    let source: IpAddr = match protocol {
+        "TCP4" => source.parse::<Ipv4Addr>().map(IpAddr::V4),
+        "TCP6" => source.parse::<Ipv6Addr>().map(IpAddr::V6),
+        _ => return Err(invalid(&format!("unknown PROXY v1 protocol '{protocol}'")))
+    }.map_err(|_| invalid(&format!("invalid PROXY v1 source address '{source}'")))?;
+    let destination_is_valid = match protocol {
+        "TCP4" => destination.parse::<Ipv4Addr>().is_ok(),
+        _ => destination.parse::<Ipv6Addr>().is_ok()
+    };
+    if !destination_is_valid {
+        return Err(invalid(&format!("invalid PROXY v1 destination address '{destination}'")));
+    }
+    let port = |x: &str| x.parse::<u16>().map_err(|_| invalid(&format!("invalid PROXY v1 port '{x}'")));
+    let source_port = port(source_port)?;
+    port(destination_port)?;
+    Ok(Some(SocketAddr::new(source, source_port)))
+}
+
+/// `header` is the fixed 16 bytes, `addresses` is the rest, its length is taken from the header
+pub fn parse_v2(header: &[u8], addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if header.len() != V2_HEADER_LEN || !header.starts_with(&V2_SIGNATURE) {
+        return Err(invalid("malformed PROXY v2 header"));
+    }
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0F;
+    if version != 2 {
+        return Err(invalid(&format!("unsupported PROXY protocol version {version}")));
+    }
+    match command {
+        // LOCAL, the connection is made by the proxy itself
+        0 => return Ok(None),
+        1 => {},
+        _ => return Err(invalid(&format!("unknown PROXY v2 command {command}")))
+    }
+    match header[13] >> 4 {
+        // AF_INET
+        1 => {
+            let bytes = addresses.get(..12).ok_or_else(|| invalid("PROXY v2 ipv4 addresses are truncated"))?;
+            let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([bytes[8], bytes[9]]))))
+        },
+        // AF_INET6
+        2 => {
+            let bytes = addresses.get(..36).ok_or_else(|| invalid("PROXY v2 ipv6 addresses are truncated"))?;
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&bytes[..16]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), u16::from_be_bytes([bytes[32], bytes[33]]))))
+        },
+        // AF_UNSPEC or AF_UNIX, there is no ip to report
+        0 | 3 => Ok(None),
+        family => Err(invalid(&format!("unknown PROXY v2 address family {family}")))
+    }
+}
+
+/// The header for a connection from `source` to `destination`  
+/// Without either of them the header tells that the address is unknown, like a health check does
+pub fn encode_header(version: ProxyProtocolVersion, source: Option<SocketAddr>, destination: Option<SocketAddr>) -> Vec<u8> {
+    let addresses = source.zip(destination).map(|(source, destination)| same_family(source, destination));
+    match version {
+        ProxyProtocolVersion::V1 => encode_v1(addresses),
+        ProxyProtocolVersion::V2 => encode_v2(addresses)
+    }
+}
+
+/// Both addresses of a header are of one family, an ipv4 one is mapped to ipv6 if the other is ipv6
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let to_ipv6 = |x: SocketAddr| match x.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), x.port()),
+        IpAddr::V6(_) => x
+    };
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => (source, destination),
+        _ => (to_ipv6(source), to_ipv6(destination))
+    }
+}
+
+fn encode_v1(addresses: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let (source, destination) = match addresses {
+        Some(x) => x,
+        None => return b"PROXY UNKNOWN\r\n".to_vec()
+    };
+    let protocol = match source {
+        SocketAddr::V4(_) => "TCP4",
+        SocketAddr::V6(_) => "TCP6"
+    };
+    format!("PROXY {protocol} {} {} {} {}\r\n", source.ip(), destination.ip(), source.port(), destination.port()).into_bytes()
+}
+
+fn encode_v2(addresses: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    let (source, destination) = match addresses {
+        Some(x) => x,
+        None => {
+            // LOCAL with AF_UNSPEC
+            header.extend([0x20, 0x00, 0x00, 0x00]);
+            return header;
+        }
+    };
+    let (family, mut addresses) = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => (0x11, [source.octets(), destination.octets()].concat()),
+        (IpAddr::V6(source), IpAddr::V6(destination)) => (0x21, [source.octets(), destination.octets()].concat()),
+        _ => unreachable!("the addresses are of one family, see same_family")
+    };
+    addresses.extend(source.port().to_be_bytes());
+    addresses.extend(destination.port().to_be_bytes());
+    // PROXY over STREAM
+    header.extend([0x21, family]);
+    header.extend((addresses.len() as u16).to_be_bytes());
+    header.extend(addresses);
+    header
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
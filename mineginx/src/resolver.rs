@@ -0,0 +1,91 @@
+use std::{collections::HashMap, io, net::SocketAddr, sync::{Arc, Mutex, RwLock}, time::Duration};
+
+use log::{debug, warn};
+use tokio::{net::lookup_host, time::sleep};
+
+use crate::{config::MineginxConfig, stream::AbortOnDrop};
+
+/// Keeps resolved addresses of `proxy_pass` names, so new connections don't wait for DNS
+#[derive(Default)]
+pub struct Resolver {
+    cache: RwLock<HashMap<String, Vec<SocketAddr>>>,
+    /// The background resolution of each address with its interval, they stop with the resolver
+    refreshes: Mutex<HashMap<String, (Duration, AbortOnDrop<()>)>>
+}
+
+impl Resolver {
+    pub fn cached(&self, address: &str) -> Option<Vec<SocketAddr>> {
+        self.cache.read().unwrap().get(address).cloned()
+    }
+
+    pub fn store(&self, address: &str, resolved: Vec<SocketAddr>) {
+        self.cache.write().unwrap().insert(address.to_string(), resolved);
+    }
+
+    /// On failure the previously resolved addresses are kept
+    pub async fn refresh(&self, address: &str) -> io::Result<()> {
+        let resolved: Vec<SocketAddr> = lookup_host(address).await?.collect();
+        if resolved.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses"));
+        }
+        debug!("resolved {address}: {resolved:?}");
+        self.store(address, resolved);
+        Ok(())
+    }
+
+    /// Resolves `address` every `interval` in background, instead of the previous resolution of it
+    pub fn start(self: &Arc<Self>, address: String, interval: Duration) {
+        // the task doesn't keep the resolver, so it stops once the resolver is dropped
+        let resolver = Arc::downgrade(self);
+        let task = tokio::spawn({
+            let address = address.clone();
+            async move {
+                loop {
+                    sleep(interval).await;
+                    let resolver = match resolver.upgrade() {
+                        Some(x) => x,
+                        None => return
+                    };
+                    if let Err(err) = resolver.refresh(&address).await {
+                        warn!("failed to resolve {address}: {err}");
+                    }
+                }
+            }
+        });
+        self.refreshes.lock().unwrap().insert(address, (interval, AbortOnDrop(task)));
+    }
+
+    /// Resolves the upstreams of the servers with `resolve_interval_ms` in background, the ones no longer in `config` are stopped  
+    /// New addresses are resolved before this returns, a failure is only logged
+    pub async fn follow(self: &Arc<Self>, config: &MineginxConfig) {
+        let mut wanted = HashMap::<String, Duration>::new();
+        for server in &config.servers {
+            let interval = match server.resolve_interval_ms {
+                Some(x) => Duration::from_millis(x),
+                None => continue
+            };
+            for proxy_pass in server.upstream_addresses() {
+                wanted.entry(proxy_pass.clone()).or_insert(interval);
+            }
+        }
+        self.refreshes.lock().unwrap().retain(|address, _| wanted.contains_key(address));
+        for (address, interval) in wanted {
+            let running = self.refreshes.lock().unwrap().get(&address).map(|x| x.0);
+            match running {
+                Some(x) if x == interval => continue,
+                Some(_) => {},
+                None => if let Err(err) = self.refresh(&address).await {
+                    warn!("failed to resolve {address}: {err}");
+                }
+            }
+            self.start(address, interval);
+        }
+    }
+
+    /// Addresses resolved in background
+    pub fn refreshing(&self) -> Vec<String> {
+        let mut addresses: Vec<String> = self.refreshes.lock().unwrap().keys().cloned().collect();
+        addresses.sort();
+        addresses
+    }
+}
@@ -0,0 +1,119 @@
+use minecraft::{
+    buffer::Buffer,
+    packets::{HandshakeC2SPacket, LoginDisconnectS2CPacket, PingRequestC2SPacket, PongResponseS2CPacket, StatusRequestC2SPacket, StatusResponseS2CPacket},
+    serialization::{read_varint, FieldWriter, MinecraftStream, ReadingError}
+};
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
+
+use crate::config::Maintenance;
+
+pub const NEXT_STATE_STATUS: i32 = 1;
+pub const NEXT_STATE_LOGIN: i32 = 2;
+/// Since 1.20.5, the client joins because another server sent it a Transfer packet, it continues like login
+pub const NEXT_STATE_TRANSFER: i32 = 3;
+
+/// Whether the client continues in the login state after the handshake
+pub fn is_login(next_state: i32) -> bool {
+    next_state == NEXT_STATE_LOGIN || next_state == NEXT_STATE_TRANSFER
+}
+
+/// Sends a login disconnect with `message`, the client must be in the login state
+pub async fn kick<S>(client: &mut MinecraftStream<&mut S>, message: &str) -> Option<()>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let reason = json!({ "text": message }).to_string();
+    client.write_packet_with_id(0, &LoginDisconnectS2CPacket { reason }).await
+}
+
+/// Answers the client as the server would during maintenance  
+/// Login gets a disconnect with `kick_message`, status gets `motd` and a pong
+pub async fn serve_maintenance<S>(client: &mut MinecraftStream<&mut S>, handshake: &HandshakeC2SPacket, maintenance: &Maintenance) -> Option<()>
+where S: AsyncRead + AsyncWrite + Unpin {
+    match handshake.next_state {
+        NEXT_STATE_STATUS => serve_status(client, handshake, maintenance).await,
+        NEXT_STATE_LOGIN | NEXT_STATE_TRANSFER => kick(client, &maintenance.kick_message).await,
+        _ => None
+    }
+}
+
+async fn serve_status<S>(client: &mut MinecraftStream<&mut S>, handshake: &HandshakeC2SPacket, maintenance: &Maintenance) -> Option<()>
+where S: AsyncRead + AsyncWrite + Unpin {
+    read_status_request(client).await.ok()?;
+    let mut json_response = json!({
+        "version": { "name": "maintenance", "protocol": handshake.protocol_version },
+        "players": { "max": 0, "online": 0 },
+        "description": { "text": maintenance.motd }
+    });
+    if maintenance.show_version == Some(true) {
+        // the clients ignore fields they don't know
+        json_response["mineginx"] = json!({ "version": env!("MINEGINX_VERSION"), "hash": env!("MINEGINX_HASH") });
+    }
+    answer_status(client, json_response.to_string()).await
+}
+
+/// The length of Status Request, only its packet id
+const STATUS_REQUEST_LENGTH: usize = 1;
+
+/// Reads the status request which follows a status handshake
+pub async fn read_status_request<S>(client: &mut MinecraftStream<&mut S>) -> Result<(), ReadingError>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let signature = client.read_signature().await?;
+    // the request has no fields, so a longer packet with the same id, such as a second handshake, is not one
+    if signature.packet_id != 0 || signature.length != STATUS_REQUEST_LENGTH {
+        return Err(ReadingError::Invalid);
+    }
+    client.read_data::<StatusRequestC2SPacket>(signature).await?;
+    Ok(())
+}
+
+/// Since 1.20.2, Login Start always has the UUID of the player after the name
+const LOGIN_START_UUID_VERSION: i32 = 764;
+/// From 1.19.3 to 1.20.1, the UUID follows the name if the boolean before it is set
+const LOGIN_START_OPTIONAL_UUID_VERSION: i32 = 761;
+
+/// Reads the Login Start which follows a login handshake  
+/// Returns the packet as it is forwarded and the player, see `player_key`
+pub async fn read_login_start<S>(client: &mut MinecraftStream<&mut S>, protocol_version: i32) -> Result<(Vec<u8>, Option<String>), ReadingError>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let signature = client.read_signature().await?;
+    if signature.packet_id != 0 {
+        return Err(ReadingError::Invalid);
+    }
+    let data = client.read_raw_data(signature).await?;
+    let mut packet = Buffer::new(data.len() + 6);
+    ((data.len() + 1) as i32).write(&mut packet).ok_or(ReadingError::Invalid)?;
+    0.write(&mut packet).ok_or(ReadingError::Invalid)?;
+    packet.write_bytes(&data);
+    Ok((packet.take().to_vec(), player_key(&data, protocol_version)))
+}
+
+/// The player of Login Start `data`, the UUID if the client sends it, otherwise the lowercase name  
+/// `None` if the name can't be read
+pub fn player_key(data: &[u8], protocol_version: i32) -> Option<String> {
+    let (length, size) = read_varint(data).ok()?;
+    let end = size.checked_add(usize::try_from(length).ok()?)?;
+    let name = std::str::from_utf8(data.get(size..end)?).ok()?;
+    let uuid = match protocol_version {
+        x if x >= LOGIN_START_UUID_VERSION => data.get(end..end + 16),
+        x if x >= LOGIN_START_OPTIONAL_UUID_VERSION && data.get(end) == Some(&1) => data.get(end + 1..end + 17),
+        _ => None
+    };
+    match uuid.and_then(|x| Uuid::from_slice(x).ok()) {
+        Some(uuid) => Some(uuid.to_string()),
+        None => Some(name.to_ascii_lowercase())
+    }
+}
+
+/// Sends `json_response` to the client which sent the status request, then answers its ping
+pub async fn answer_status<S>(client: &mut MinecraftStream<&mut S>, json_response: String) -> Option<()>
+where S: AsyncRead + AsyncWrite + Unpin {
+    client.write_packet_with_id(0, &StatusResponseS2CPacket { json_response }).await?;
+
+    let signature = client.read_signature().await.ok()?;
+    if signature.packet_id != 1 {
+        return None;
+    }
+    let ping = client.read_data::<PingRequestC2SPacket>(signature).await.ok()?;
+    client.write_packet_with_id(1, &PongResponseS2CPacket { payload: ping.payload }).await
+}
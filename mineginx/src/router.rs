@@ -0,0 +1,78 @@
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc};
+
+use minecraft::packets::HandshakeC2SPacket;
+
+use crate::{config::{names_equal, wildcard_matches, MinecraftServerDescription, MineginxConfig, SharedConfig}, domain::normalize_domain};
+
+/// `proxy_pass` of the chosen upstream along with the options the client is proxied with
+pub type UpstreamTarget = MinecraftServerDescription;
+
+pub type RouteFuture<'a> = Pin<Box<dyn Future<Output = Option<UpstreamTarget>> + Send + 'a>>;
+
+/// Chooses the upstream for a client once its handshake is read
+pub trait Router: Send + Sync {
+    /// `listen` is the address which accepted the client, `peer` is `None` for unix domain sockets  
+    /// The client is disconnected if there is no upstream
+    fn resolve<'a>(&'a self, handshake: &'a HandshakeC2SPacket, listen: &'a str, peer: Option<SocketAddr>) -> RouteFuture<'a>;
+}
+
+/// Routes by `server_names` of the config, used unless another router is given
+pub struct ConfigRouter {
+    config: Arc<SharedConfig>
+}
+
+impl ConfigRouter {
+    pub fn new(config: Arc<MineginxConfig>) -> ConfigRouter {
+        ConfigRouter::shared(Arc::new(SharedConfig::new(config)))
+    }
+
+    /// Follows the config when it is reloaded
+    pub fn shared(config: Arc<SharedConfig>) -> ConfigRouter {
+        ConfigRouter { config }
+    }
+}
+
+impl Router for ConfigRouter {
+    fn resolve<'a>(&'a self, handshake: &'a HandshakeC2SPacket, listen: &'a str, _peer: Option<SocketAddr>) -> RouteFuture<'a> {
+        Box::pin(async move {
+            let config = self.config.get();
+            let domain = normalize_domain(&handshake.domain, &config.domain_matching());
+            find_upstream(&domain, config, listen)
+        })
+    }
+}
+
+/// Only servers on the `listen` address which accepted the client are checked  
+/// Exact `server_names` are checked first, then the longest matching wildcard wins, then `server_name_patterns` in the order of servers  
+/// `domain` is expected to be normalized already, see `normalize_domain`
+pub fn find_upstream(domain: &str, config: Arc<MineginxConfig>, listen: &str) -> Option<MinecraftServerDescription> {
+    let case_insensitive = config.domain_matching().case_insensitive();
+    let servers = || config.servers.iter().filter(|x| x.listen.addresses().iter().any(|x| x == listen));
+    for x in servers() {
+        for server_name in &x.server_names {
+            if names_equal(server_name, domain, case_insensitive) {
+                return Some(x.clone());
+            }
+        }
+    }
+    let mut found: Option<(usize, &MinecraftServerDescription)> = None;
+    for x in servers() {
+        for server_name in &x.server_names {
+            if !wildcard_matches(server_name, domain, case_insensitive) {
+                continue;
+            }
+            if let Some((length, _)) = found {
+                if length >= server_name.len() {
+                    continue;
+                }
+            }
+            found = Some((server_name.len(), x));
+        }
+    }
+    if let Some((_, x)) = found {
+        return Some(x.clone());
+    }
+    servers()
+        .find(|x| x.server_name_patterns.iter().flatten().any(|x| x.matches(domain, case_insensitive)))
+        .cloned()
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant}
+};
+
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig},
+    name_server::TokioConnectionProvider,
+    proto::ProtoErrorKind,
+    Name,
+    ResolveError,
+    TokioResolver
+};
+use log::warn;
+
+use crate::config::random_fraction;
+
+/// How long a nameserver gets to answer
+pub const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One record of `_minecraft._tcp.example.com`, like `0 5 25565 mc1.example.com.`
+#[derive(PartialEq, Debug, Clone)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SrvAnswer {
+    pub records: Vec<SrvRecord>,
+    /// How long the records may be kept
+    pub ttl: Duration
+}
+
+pub type SrvFuture<'a> = Pin<Box<dyn Future<Output = io::Result<SrvAnswer>> + Send + 'a>>;
+
+/// Looks up SRV records of `proxy_pass_srv`
+pub trait SrvLookup: Send + Sync {
+    fn lookup<'a>(&'a self, name: &'a str) -> SrvFuture<'a>;
+}
+
+/// Asks the nameservers of /etc/resolv.conf with hickory-resolver, unless another nameserver is given  
+/// It follows `search`, `options` and every `nameserver` of resolv.conf, checks that the answer is to the question asked
+/// and asks again over TCP when the answer is truncated
+#[derive(Default)]
+pub struct DnsSrvLookup {
+    nameserver: Option<SocketAddr>,
+    /// Made by the first lookup, so a broken resolv.conf fails the lookups instead of the start
+    resolver: Mutex<Option<TokioResolver>>
+}
+
+impl DnsSrvLookup {
+    pub fn with_nameserver(nameserver: SocketAddr) -> DnsSrvLookup {
+        DnsSrvLookup {
+            nameserver: Some(nameserver),
+            ..Default::default()
+        }
+    }
+
+    fn resolver(&self) -> io::Result<TokioResolver> {
+        let mut resolver = self.resolver.lock().unwrap();
+        if let Some(x) = &*resolver {
+            return Ok(x.clone());
+        }
+        let mut builder = match self.nameserver {
+            Some(x) => {
+                let nameservers = NameServerConfigGroup::from_ips_clear(&[x.ip()], x.port(), true);
+                let mut builder = TokioResolver::builder_with_config(ResolverConfig::from_parts(None, vec![], nameservers), TokioConnectionProvider::default());
+                builder.options_mut().timeout = DNS_TIMEOUT;
+                builder
+            },
+            None => TokioResolver::builder_tokio().map_err(io::Error::other)?
+        };
+        // the records are kept by `SrvCache`
+        builder.options_mut().cache_size = 0;
+        Ok(resolver.insert(builder.build()).clone())
+    }
+}
+
+impl SrvLookup for DnsSrvLookup {
+    fn lookup<'a>(&'a self, name: &'a str) -> SrvFuture<'a> {
+        Box::pin(async move {
+            let answer = self.resolver()?.srv_lookup(name).await.map_err(lookup_error)?;
+            let records: Vec<SrvRecord> = answer.iter()
+                .map(|x| SrvRecord {
+                    priority: x.priority(),
+                    weight: x.weight(),
+                    port: x.port(),
+                    target: x.target().to_utf8()
+                })
+                .collect();
+            if records.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no SRV records"));
+            }
+            Ok(SrvAnswer { records, ttl: answer.as_lookup().valid_until().saturating_duration_since(Instant::now()) })
+        })
+    }
+}
+
+fn lookup_error(err: ResolveError) -> io::Error {
+    if err.is_nx_domain() || err.is_no_records_found() {
+        return io::Error::new(io::ErrorKind::NotFound, err);
+    }
+    match err.proto().map(|x| x.kind()) {
+        Some(ProtoErrorKind::Timeout) => io::Error::new(io::ErrorKind::TimedOut, err),
+        _ => io::Error::other(err)
+    }
+}
+
+/// Whether `name` can be looked up, such as `_minecraft._tcp.example.com`
+pub fn is_domain_name(name: &str) -> bool {
+    !name.trim_end_matches('.').is_empty() && Name::from_ascii(name).is_ok()
+}
+
+/// The record to connect to as RFC 2782 says, `random` is in `[0, 1)`  
+/// One of the lowest priority, each chosen as often as its weight says, records of weight 0 only if all of them have it
+pub fn select_record(records: &[SrvRecord], random: f64) -> Option<&SrvRecord> {
+    let priority = records.iter().map(|x| x.priority).min()?;
+    let candidates: Vec<&SrvRecord> = records.iter().filter(|x| x.priority == priority).collect();
+    let total: u64 = candidates.iter().map(|x| x.weight as u64).sum();
+    if total == 0 {
+        let index = (random * candidates.len() as f64) as usize;
+        return candidates.get(index).or(candidates.last()).copied();
+    }
+    let mut point = (random * total as f64) as u64;
+    for record in &candidates {
+        if point < record.weight as u64 {
+            return Some(record);
+        }
+        point -= record.weight as u64;
+    }
+    candidates.last().copied()
+}
+
+/// Records of `proxy_pass_srv` names, each answer is kept for its TTL
+pub struct SrvCache {
+    lookup: Arc<dyn SrvLookup>,
+    /// The records and when they expire
+    answers: Mutex<HashMap<String, (Vec<SrvRecord>, Instant)>>
+}
+
+impl Default for SrvCache {
+    fn default() -> SrvCache {
+        SrvCache::new(Arc::new(DnsSrvLookup::default()))
+    }
+}
+
+impl SrvCache {
+    pub fn new(lookup: Arc<dyn SrvLookup>) -> SrvCache {
+        SrvCache {
+            lookup,
+            answers: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// `host:port` of a record chosen by `select_record` for each call, so the records of the same priority share the players  
+    /// If the lookup fails, the expired records are used until the next lookup succeeds
+    pub async fn resolve(&self, name: &str) -> io::Result<String> {
+        let cached = self.answers.lock().unwrap().get(name).cloned();
+        let records = match cached {
+            Some((records, expires)) if expires > Instant::now() => records,
+            cached => match self.lookup.lookup(name).await {
+                Ok(answer) => {
+                    self.answers.lock().unwrap().insert(name.to_string(), (answer.records.clone(), Instant::now() + answer.ttl));
+                    answer.records
+                },
+                Err(err) => match cached {
+                    Some((records, _)) => {
+                        warn!("failed to resolve SRV record {name}, the last records are used again: {err}");
+                        records
+                    },
+                    None => return Err(err)
+                }
+            }
+        };
+        let record = select_record(&records, random_fraction())
+            .filter(|x| !x.target.is_empty() && x.target != ".")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{name} says the service is not available")))?;
+        Ok(format!("{}:{}", record.target.trim_end_matches('.'), record.port))
+    }
+}
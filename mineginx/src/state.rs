@@ -0,0 +1,154 @@
+use std::{collections::HashMap, net::IpAddr, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex}};
+
+use tokio::{sync::broadcast, task::JoinSet};
+
+use crate::{access_log::AccessLog, bans::Bans, geoip::GeoIp, config::{MineginxConfig, SharedConfig}, observer::{ConnectionObserver, NoopObserver}, on_demand::Launcher, pool::Pools, resolver::Resolver, router::{ConfigRouter, Router}, srv::SrvCache, status_cache::StatusCache, throttle::Bandwidth};
+
+/// Everything shared by the listeners and their clients
+pub struct State {
+    pub config: Arc<SharedConfig>,
+    pub access_log: Option<AccessLog>,
+    pub resolver: Arc<Resolver>,
+    pub router: Arc<dyn Router>,
+    pub observer: Arc<dyn ConnectionObserver>,
+    pub launcher: Launcher,
+    pub stats: Stats,
+    pub shutting_down: AtomicBool,
+    /// Tasks of the connected clients, so shutdown can wait for them
+    pub sessions: Mutex<JoinSet<()>>,
+    pub bans: Bans,
+    pub geoip: GeoIp,
+    /// Normalized domains whose proxied clients are disconnected, see `kick` of the control socket
+    pub kicks: broadcast::Sender<String>,
+    pub status_cache: StatusCache,
+    pub pools: Pools,
+    /// Targets of `proxy_pass_srv`
+    pub srv: SrvCache,
+    pub bandwidth: Bandwidth
+}
+
+/// Counters since start
+#[derive(Default)]
+pub struct Stats {
+    /// Upstreams which didn't take the handshake in `upstream_write_timeout_ms`
+    pub upstream_write_timeouts: AtomicU64,
+    /// Clients which asked for one of `honeypot_domains` or left right after a status handshake
+    pub scanners: AtomicU64,
+    /// Clients which closed the connection without sending anything, such as tcp health checks
+    pub empty_connections: AtomicU64,
+    /// Handshakes which were broken or didn't arrive in time
+    pub handshake_failures: AtomicU64,
+    /// Handshakes for domains without an upstream
+    pub misses: AtomicU64,
+    /// Bytes of the finished sessions, from the clients and to them
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    /// Counters of each upstream by its `label`, so a named server is told apart from another one with the same `proxy_pass`
+    pub upstreams: Mutex<HashMap<String, UpstreamStats>>
+}
+
+/// Counters of one upstream since start
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct UpstreamStats {
+    /// Status responses the upstream sent, forwarded to a client or fetched for `status_cache_ttl_ms`
+    pub status_fetches: u64,
+    /// Clients which logged in or were transferred to the upstream
+    pub logins: u64,
+    /// Logged in clients which are still connected
+    pub online: u64
+}
+
+/// Keeps a client in `online` of its upstream until dropped
+pub struct Online<'a> {
+    stats: &'a Stats,
+    upstream: String
+}
+
+impl Drop for Online<'_> {
+    fn drop(&mut self) {
+        self.stats.update_upstream(&self.upstream, |x| x.online -= 1);
+    }
+}
+
+impl Stats {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `upstream` is the `label` of the server
+    pub fn update_upstream<F>(&self, upstream: &str, update: F) where F: FnOnce(&mut UpstreamStats) {
+        update(self.upstreams.lock().unwrap().entry(upstream.to_string()).or_default());
+    }
+
+    pub fn online(&self, upstream: String) -> Online<'_> {
+        self.update_upstream(&upstream, |x| x.online += 1);
+        Online { stats: self, upstream }
+    }
+
+    pub fn upstream(&self, upstream: &str) -> UpstreamStats {
+        self.upstreams.lock().unwrap().get(upstream).copied().unwrap_or_default()
+    }
+}
+
+impl State {
+    pub fn new(config: Arc<MineginxConfig>) -> State {
+        let config = Arc::new(SharedConfig::new(config));
+        State {
+            router: Arc::new(ConfigRouter::shared(config.clone())),
+            config,
+            observer: Arc::new(NoopObserver),
+            access_log: None,
+            resolver: Arc::new(Resolver::default()),
+            launcher: Launcher::default(),
+            stats: Stats::default(),
+            shutting_down: AtomicBool::new(false),
+            sessions: Mutex::new(JoinSet::new()),
+            bans: Bans::default(),
+            geoip: GeoIp::default(),
+            kicks: broadcast::channel(16).0,
+            status_cache: StatusCache::default(),
+            pools: Pools::default(),
+            srv: SrvCache::default(),
+            bandwidth: Bandwidth::default()
+        }
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.bans.contains(ip)
+    }
+
+    /// One line of the counters, the ones of each upstream follow the global ones, like
+    /// `online[127.0.0.1:7878]=1 status_fetches[127.0.0.1:7878]=3 logins[127.0.0.1:7878]=1`, a named server is `online[lobby (127.0.0.1:7878)]=1`
+    pub fn summary(&self) -> String {
+        let sessions = {
+            let mut sessions = self.sessions.lock().unwrap();
+            while sessions.try_join_next().is_some() {}
+            sessions.len()
+        };
+        let stats = &self.stats;
+        let mut summary = format!("sessions={} scanners={} empty_connections={} handshake_failures={} misses={} upstream_write_timeouts={} bytes_in={} bytes_out={} bans={}",
+            sessions,
+            stats.scanners.load(Ordering::Relaxed),
+            stats.empty_connections.load(Ordering::Relaxed),
+            stats.handshake_failures.load(Ordering::Relaxed),
+            stats.misses.load(Ordering::Relaxed),
+            stats.upstream_write_timeouts.load(Ordering::Relaxed),
+            stats.bytes_in.load(Ordering::Relaxed),
+            stats.bytes_out.load(Ordering::Relaxed),
+            self.bans.len());
+        let mut upstreams: Vec<(String, UpstreamStats)> = stats.upstreams.lock().unwrap().iter().map(|(k, v)| (k.clone(), *v)).collect();
+        upstreams.sort_by(|a, b| a.0.cmp(&b.0));
+        for (label, upstream) in upstreams {
+            summary.push_str(&format!(" online[{label}]={} status_fetches[{label}]={} logins[{label}]={}", upstream.online, upstream.status_fetches, upstream.logins));
+        }
+        summary
+    }
+
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+}
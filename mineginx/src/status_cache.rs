@@ -0,0 +1,76 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+use minecraft::{
+    packets::{HandshakeC2SPacket, StatusRequestC2SPacket, StatusResponseS2CPacket},
+    serialization::MinecraftStream
+};
+use tokio::{io::{AsyncRead, AsyncWrite}, time::timeout};
+
+/// The upstream may answer differently depending on the domain and the protocol version of the client
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct StatusKey {
+    pub proxy_pass: String,
+    pub domain: String,
+    pub protocol_version: i32
+}
+
+/// The most responses kept, the domain of the key comes from the client, so scanners must not grow the cache without end
+pub const MAX_STATUS_CACHE_ENTRIES: usize = 1024;
+
+/// Status responses of upstreams with `status_cache_ttl_ms`
+#[derive(Default)]
+pub struct StatusCache {
+    /// The response and when it expires
+    entries: Mutex<HashMap<StatusKey, (Instant, String)>>
+}
+
+impl StatusCache {
+    /// The response if it is not expired yet
+    pub fn get(&self, key: &StatusKey) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((expires, json_response)) if *expires > Instant::now() => Some(json_response.clone()),
+            _ => None
+        }
+    }
+
+    /// Expired responses are dropped here, so domains asked only once don't stay forever  
+    /// Once there are `MAX_STATUS_CACHE_ENTRIES`, the response which expires first makes room
+    pub fn insert(&self, key: StatusKey, json_response: String, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (expires, _)| *expires > now);
+        if entries.len() >= MAX_STATUS_CACHE_ENTRIES && !entries.contains_key(&key) {
+            let oldest = entries.iter().min_by_key(|(_, (expires, _))| *expires).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, (now + ttl, json_response));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Asks the upstream for its status the way a client does, the ping is not sent
+pub async fn fetch_status<S>(upstream: &mut S, handshake: &HandshakeC2SPacket, buffer_size: usize, limit: Duration) -> Option<String>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let mut upstream = MinecraftStream::new(upstream, buffer_size);
+    let fetch = async {
+        upstream.write_packet_with_id(0, handshake).await?;
+        upstream.write_packet_with_id(0, &StatusRequestC2SPacket {}).await?;
+        let signature = upstream.read_signature().await.ok()?;
+        if signature.packet_id != 0 {
+            return None;
+        }
+        let response = upstream.read_data::<StatusResponseS2CPacket>(signature).await.ok()?;
+        Some(response.json_response)
+    };
+    timeout(limit, fetch).await.ok().flatten()
+}
@@ -1,62 +1,257 @@
+use std::{fmt, future::Future, io, pin::Pin, task::{Context, Poll}};
+
 use tokio::{
-    task::JoinHandle,
+    io::{ReadBuf, ReadHalf, WriteHalf},
+    task::{JoinError, JoinHandle},
     sync::oneshot::{
-        Sender, Receiver, error::TryRecvError
-    },
-    net::tcp::{
-        OwnedReadHalf, OwnedWriteHalf
+        Sender, Receiver
     },
-    io::{AsyncReadExt, AsyncWriteExt}
+    net::{tcp, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}
 };
+#[cfg(unix)]
+use tokio::net::{unix, UnixStream};
+
+use crate::throttle::{consume_shared, SharedBucket};
+
+/// A client or upstream connection which can be split into independently owned halves
+pub trait SplitStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    type Reader: AsyncRead + Unpin + Send + 'static;
+    type Writer: AsyncWrite + Unpin + Send + 'static;
+
+    fn split_halves(self) -> (Self::Reader, Self::Writer);
+}
+
+impl SplitStream for TcpStream {
+    type Reader = tcp::OwnedReadHalf;
+    type Writer = tcp::OwnedWriteHalf;
+
+    fn split_halves(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+}
+
+#[cfg(unix)]
+impl SplitStream for UnixStream {
+    type Reader = unix::OwnedReadHalf;
+    type Writer = unix::OwnedWriteHalf;
+
+    fn split_halves(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+}
+
+/// Any other stream, such as a TLS one, split by `tokio::io::split`  
+/// Its halves share a lock for each read and write, so tcp and unix sockets keep their own halves
+pub struct SharedStream<S>(pub S);
+
+impl<S> SplitStream for SharedStream<S> where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    type Reader = ReadHalf<S>;
+    type Writer = WriteHalf<S>;
+
+    fn split_halves(self) -> (Self::Reader, Self::Writer) {
+        tokio::io::split(self.0)
+    }
+}
+
+impl<S> AsyncRead for SharedStream<S> where S: AsyncRead + Unpin {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for SharedStream<S> where S: AsyncWrite + Unpin {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Why a direction of the proxied connection stopped
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Closed {
+    /// The reader sent EOF
+    Eof,
+    ReadError(io::ErrorKind),
+    WriteError(io::ErrorKind),
+    /// The other direction stopped first
+    ByOther
+}
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Closed::Eof => write!(f, "eof"),
+            Closed::ReadError(kind) => write!(f, "read error: {kind}"),
+            Closed::WriteError(kind) => write!(f, "write error: {kind}"),
+            Closed::ByOther => write!(f, "closed by other side")
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Forwarded {
+    pub bytes: u64,
+    pub closed: Closed
+}
+
+/// Awaits the task like its `JoinHandle`, but aborts it when dropped
+pub struct AbortOnDrop<T>(pub JoinHandle<T>);
 
-pub fn forward_stream(
+impl<T> Future for AbortOnDrop<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The forwarding buffer grows up to this size, unless `buffer_size` is already bigger
+pub const MAX_FORWARD_BUFFER_SIZE: usize = 64 * 1024;
+/// Reads in a row which fill the whole buffer before it doubles
+const GROW_AFTER_FULL_READS: u32 = 4;
+/// Reads in a row which take at most a quarter of the buffer before it halves
+const SHRINK_AFTER_SMALL_READS: u32 = 64;
+
+/// The buffer of `forward_stream`, it starts at `buffer_size` and doubles while the reads keep filling it,
+/// such as during a modpack download, then halves back towards `buffer_size` once the reads are small again, such as during idle play  
+/// It is resized at most once per `GROW_AFTER_FULL_READS` or `SHRINK_AFTER_SMALL_READS` reads, so it rarely allocates
+pub struct AdaptiveBuffer {
+    buf: Vec<u8>,
+    base: usize,
+    max: usize,
+    full_reads: u32,
+    small_reads: u32
+}
+
+impl AdaptiveBuffer {
+    pub fn new(base: usize, max: usize) -> AdaptiveBuffer {
+        AdaptiveBuffer {
+            buf: vec![0; base],
+            base,
+            max: max.max(base),
+            full_reads: 0,
+            small_reads: 0
+        }
+    }
+
+    /// Where the next read goes
+    pub fn get_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// The first `size` bytes, the ones of the last read
+    pub fn filled(&self, size: usize) -> &[u8] {
+        &self.buf[..size]
+    }
+
+    /// Bytes the next read may take
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Adapts the size to a read of `size` bytes, once they are written
+    pub fn record(&mut self, size: usize) {
+        let len = self.buf.len();
+        if size == len {
+            self.small_reads = 0;
+            self.full_reads += 1;
+            if self.full_reads >= GROW_AFTER_FULL_READS && len < self.max {
+                self.full_reads = 0;
+                self.buf.resize((len * 2).min(self.max), 0);
+            }
+        }
+        else if size <= len / 4 {
+            self.full_reads = 0;
+            self.small_reads += 1;
+            if self.small_reads >= SHRINK_AFTER_SMALL_READS && len > self.base {
+                self.small_reads = 0;
+                self.buf.truncate((len / 2).max(self.base));
+                self.buf.shrink_to_fit();
+            }
+        }
+        else {
+            self.full_reads = 0;
+            self.small_reads = 0;
+        }
+    }
+}
+
+/// Copies `reader` to `writer` until either direction closes, the task returns the number of forwarded bytes and the reason it stopped  
+/// `bandwidth` may be shared with the other connections of the server
+pub fn forward_stream<R, W>(
     close: Sender<()>,
     close_by_other: Receiver<()>,
-    mut reader: OwnedReadHalf,
-    mut writer: OwnedWriteHalf,
-    buffer_size: usize) -> JoinHandle<()> {
+    mut reader: R,
+    mut writer: W,
+    buffer_size: usize,
+    bandwidth: Option<SharedBucket>) -> JoinHandle<Forwarded>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static {
     tokio::spawn(async move {
-        let mut buf = vec![0; buffer_size];
-        let mut close = Some(close);
-        let mut close_by_other = Some(close_by_other);
-        let mut closed = false;
-        loop {
-            if let Some(mut receiver) = close_by_other.take() {
-                match receiver.try_recv() {
-                    Err(e ) => closed |= e == TryRecvError::Closed,
-                    Ok(_) => closed = true
-                }
-            }
-            if closed {
-                return;
-            }
-            let res = reader.read(&mut buf).await;
-            match res {
-                Ok(size) => {
-                    if size == 0 {
-                        if let Some(sender) = close.take() {
-                            closed = true;
-                            _ = sender.send(());
-                        }
+        let mut buf = AdaptiveBuffer::new(buffer_size, MAX_FORWARD_BUFFER_SIZE);
+        let mut close_by_other = close_by_other;
+        let mut bytes = 0;
+        let closed = loop {
+            // the other direction may close while this one waits for data
+            let res = tokio::select! {
+                res = reader.read(buf.get_mut()) => res,
+                _ = &mut close_by_other => return Forwarded { bytes, closed: Closed::ByOther }
+            };
+            let size = match res {
+                Ok(0) => break Closed::Eof,
+                Ok(size) => size,
+                Err(err) => break Closed::ReadError(err.kind())
+            };
+            // a writer which doesn't drain or the bandwidth limit must not hold the connection open either
+            let res = tokio::select! {
+                res = async {
+                    if let Some(bucket) = &bandwidth {
+                        consume_shared(bucket, size).await;
                     }
-                    let writed = writer.write_all(&buf[..size]).await;
-                    match writed {
-                        Ok(_) => { },
-                        Err(_) => {
-                            if let Some(sender) = close.take() {
-                                _ = sender.send(())
-                            }
-                            return;
-                        }
-                    }
-                },
-                Err(_) => {
-                    if let Some(sender) = close.take() {
-                        _ = sender.send(());
-                    }
-                    return;
-                }
+                    write_counted(&mut writer, buf.filled(size), &mut bytes).await
+                } => Some(res),
+                _ = &mut close_by_other => None
+            };
+            match res {
+                Some(Ok(())) => buf.record(size),
+                Some(Err(err)) => break Closed::WriteError(err.kind()),
+                None => return Forwarded { bytes, closed: Closed::ByOther }
             }
-        }
+        };
+        _ = close.send(());
+        Forwarded { bytes, closed }
     })
 }
+
+/// Writes the whole `data` like `write_all`, but counts each partial write, so the bytes sent before an error are counted too  
+/// The next read waits until everything is written, so a slow writer holds back the reader instead of buffering  
+/// `WouldBlock` and `Interrupted` of a writer which isn't ready yet are retried
+async fn write_counted<W>(writer: &mut W, mut data: &[u8], bytes: &mut u64) -> io::Result<()> where W: AsyncWrite + Unpin {
+    while !data.is_empty() {
+        match writer.write(data).await {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(size) => {
+                *bytes += size as u64;
+                data = &data[size..];
+            },
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => tokio::task::yield_now().await,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(())
+}
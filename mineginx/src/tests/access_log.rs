@@ -0,0 +1,107 @@
+use std::{path::Path, time::{Duration, UNIX_EPOCH}};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, time::sleep};
+
+use crate::{
+    access_log::{format_session, AccessLog, Session},
+    config::{MinecraftServerDescription, MineginxConfig},
+    tests::harness::{handshake, Harness}
+};
+
+fn temp_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("mineginx-{}.log", uuid::Uuid::new_v4()))
+}
+
+async fn read_lines(path: &Path, count: usize) -> Vec<String> {
+    for _ in 0..100 {
+        let text = tokio::fs::read_to_string(path).await.unwrap_or_default();
+        let lines: Vec<String> = text.lines().map(|x| x.to_string()).collect();
+        if lines.len() >= count {
+            return lines;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("there are less than {count} lines in {}", path.display());
+}
+
+fn session(client: &str) -> Session<'_> {
+    Session {
+        client,
+        domain: "mc.example.com",
+        upstream: "127.0.0.1:7878",
+        bytes_in: 10,
+        bytes_out: 20,
+        duration: Duration::from_millis(1500)
+    }
+}
+
+#[test]
+fn format_line() {
+    let line = format_session(&session("127.0.0.1"), UNIX_EPOCH + Duration::from_millis(1_700_000_000_123));
+    assert_eq!(line, "1700000000.123 client=127.0.0.1 domain=mc.example.com upstream=127.0.0.1:7878 bytes_in=10 bytes_out=20 duration_ms=1500");
+}
+
+#[test]
+fn domain_is_escaped() {
+    let domain = "x\nclient=1.2.3.4 domain=mc.example.com\r\t%20é.example.com";
+    let line = format_session(&Session { domain, ..session("127.0.0.1") }, UNIX_EPOCH);
+    assert_eq!(line.lines().count(), 1);
+    assert_eq!(line, "0.000 client=127.0.0.1 domain=x%0Aclient=1.2.3.4%20domain=mc.example.com%0D%09%2520%C3%A9.example.com upstream=127.0.0.1:7878 bytes_in=10 bytes_out=20 duration_ms=1500");
+}
+
+#[tokio::test]
+async fn completed_session_is_appended() {
+    let path = temp_path();
+    let harness = Harness::start_with(MineginxConfig {
+        access_log: Some(path.to_string_lossy().into_owned()),
+        ..Default::default()
+    }, MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        ..Default::default()
+    }).await;
+    let upstream_address = harness.upstream.local_addr().unwrap().to_string();
+
+    let handshake = handshake("localhost", 2);
+    let mut client = harness.connect(&handshake).await;
+    let mut backend = harness.accept().await;
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    backend.write_all(b"pong").await.unwrap();
+    let mut reply = [0; 4];
+    client.read_exact(&mut reply).await.unwrap();
+    drop(backend);
+    drop(client);
+
+    let lines = read_lines(&path, 1).await;
+    let fields: Vec<&str> = lines[0].split(' ').collect();
+    assert_eq!(fields.len(), 7);
+    assert!(fields[0].split_once('.').is_some_and(|(secs, millis)| secs.parse::<u64>().is_ok() && millis.len() == 3));
+    assert_eq!(fields[1..6], [
+        "client=127.0.0.1".to_string(),
+        "domain=localhost".to_string(),
+        format!("upstream={upstream_address}"),
+        format!("bytes_in={}", handshake.len()),
+        "bytes_out=4".to_string()
+    ]);
+    assert!(fields[6].starts_with("duration_ms="));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn reopen_after_rotation() {
+    let path = temp_path();
+    let rotated = path.with_extension("log.1");
+    let access_log = AccessLog::open(&path).await.unwrap();
+    access_log.write(&session("10.0.0.1"));
+    read_lines(&path, 1).await;
+
+    std::fs::rename(&path, &rotated).unwrap();
+    access_log.reopen();
+    access_log.write(&session("10.0.0.2"));
+    let lines = read_lines(&path, 1).await;
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("client=10.0.0.2"));
+    assert_eq!(read_lines(&rotated, 1).await.len(), 1);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&rotated).unwrap();
+}
@@ -0,0 +1,104 @@
+use std::{fs, path::{Path, PathBuf}, process::ExitCode};
+
+use tokio::net::TcpListener;
+
+use crate::{app::{run, Exit}, tests::upstream::free_address, Shutdown, StartError};
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mineginx-{}", uuid::Uuid::new_v4())).join(name)
+}
+
+fn write_config(name: &str, text: &str) -> PathBuf {
+    let path = temp_path(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, text).unwrap();
+    path
+}
+
+async fn run_with(args: &[&str], path: &Path) -> Exit {
+    let mut args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+    args.push("-c".to_string());
+    args.push(path.display().to_string());
+    run(args, None, async {}).await
+}
+
+fn valid_config(listen: &str) -> String {
+    format!("servers:\n- listen: \"{listen}\"\n  server_names: [\"localhost\"]\n  proxy_pass: \"127.0.0.1:7878\"\n")
+}
+
+#[test]
+fn exit_codes() {
+    assert_eq!(Exit::Ok as u8, 0);
+    assert_eq!(Exit::InvalidConfig as u8, 1);
+    assert_eq!(Exit::ConfigGeneration as u8, 2);
+    assert_eq!(Exit::NoListeners as u8, 3);
+    assert_eq!(Exit::ForcedShutdown as u8, 4);
+    assert_eq!(Exit::Unreachable as u8, 5);
+    assert_eq!(ExitCode::from(Exit::NoListeners), ExitCode::from(3));
+    assert_eq!(Exit::from(Shutdown::Graceful), Exit::Ok);
+    assert_eq!(Exit::from(Shutdown::Forced), Exit::ForcedShutdown);
+    assert_eq!(Exit::from(&StartError::NoListeners), Exit::NoListeners);
+}
+
+#[tokio::test]
+async fn unknown_argument_is_invalid() {
+    assert_eq!(run(["--frobnicate".to_string()], None, async {}).await, Exit::InvalidConfig);
+}
+
+#[tokio::test]
+async fn stops_with_ok() {
+    let path = write_config("mineginx.yaml", &valid_config("127.0.0.1:0"));
+    assert_eq!(run_with(&[], &path).await, Exit::Ok);
+}
+
+#[tokio::test]
+async fn malformed_config_is_invalid_and_kept() {
+    let path = write_config("mineginx.yaml", "servers: [");
+    assert_eq!(run_with(&[], &path).await, Exit::InvalidConfig);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "servers: [");
+}
+
+#[tokio::test]
+async fn config_with_errors_is_invalid() {
+    let path = write_config("mineginx.yaml", "servers: []\n");
+    assert_eq!(run_with(&[], &path).await, Exit::InvalidConfig);
+    assert_eq!(run_with(&["-t"], &path).await, Exit::InvalidConfig);
+}
+
+#[tokio::test]
+async fn check_and_dump() {
+    let path = write_config("mineginx.yaml", &valid_config("127.0.0.1:0"));
+    assert_eq!(run_with(&["-t"], &path).await, Exit::Ok);
+    assert_eq!(run_with(&["--dump-config"], &path).await, Exit::Ok);
+    assert_eq!(run_with(&["--dump-config"], &temp_path("missing.yaml")).await, Exit::InvalidConfig);
+}
+
+#[tokio::test]
+async fn probe_exit_codes() {
+    let proxy_pass = free_address().await;
+    let path = write_config("mineginx.yaml", &valid_config("127.0.0.1:0").replace("127.0.0.1:7878", &proxy_pass));
+    assert_eq!(run_with(&["--probe"], &path).await, Exit::Unreachable);
+    assert_eq!(run_with(&["--probe"], &write_config("mineginx.yaml", "servers: []\n")).await, Exit::InvalidConfig);
+}
+
+#[tokio::test]
+async fn unwritable_default_config_fails_generation() {
+    // the parent of the config is a file, so the directory can't be created
+    let file = write_config("file", "");
+    assert_eq!(run_with(&[], &file.join("mineginx.yaml")).await, Exit::ConfigGeneration);
+}
+
+#[tokio::test]
+async fn busy_address_is_no_listeners() {
+    let busy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let path = write_config("mineginx.yaml", &valid_config(&busy.local_addr().unwrap().to_string()));
+    assert_eq!(run_with(&[], &path).await, Exit::NoListeners);
+}
+
+#[tokio::test]
+async fn unreadable_bans_file_is_invalid() {
+    let bans = write_config("bans.json", "[\"1.2.3\"]");
+    let config = format!("{}bans_file: \"{}\"\n", valid_config("127.0.0.1:0"), bans.display());
+    let path = write_config("mineginx.yaml", &config);
+    assert_eq!(run_with(&[], &path).await, Exit::InvalidConfig);
+}
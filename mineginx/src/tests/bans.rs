@@ -0,0 +1,162 @@
+use std::{fs, net::IpAddr, path::PathBuf, sync::Arc};
+
+use crate::{bans::{BanEntry, Bans}, config::{MinecraftServerDescription, MineginxConfig}, Proxy};
+
+fn temp_dir() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mineginx-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn ip(x: &str) -> IpAddr {
+    x.parse().unwrap()
+}
+
+fn entry(x: &str) -> BanEntry {
+    x.parse().unwrap()
+}
+
+fn saved(path: &PathBuf) -> Vec<String> {
+    serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn missing_file_is_empty() {
+    let path = temp_dir().join("bans.json");
+    let bans = Bans::load(&path).unwrap();
+    assert!(bans.is_empty());
+    assert!(!path.exists());
+}
+
+#[test]
+fn file_is_loaded() {
+    let path = temp_dir().join("bans.json");
+    fs::write(&path, r#"["1.2.3.4", "2001:db8::1", "2001:db8:1::/48"]"#).unwrap();
+    let bans = Bans::load(&path).unwrap();
+    assert_eq!(bans.len(), 3);
+    assert!(bans.contains(&ip("1.2.3.4")));
+    assert!(bans.contains(&ip("2001:db8::1")));
+    assert!(!bans.contains(&ip("5.6.7.8")));
+    assert!(bans.contains(&ip("2001:db8:1:2::3")));
+}
+
+#[test]
+fn malformed_file_is_an_error() {
+    let path = temp_dir().join("bans.json");
+    for text in ["[\"1.2.3\"]", "[\"1.2.3.4/40\"]", "{", "[1]"] {
+        fs::write(&path, text).unwrap();
+        assert!(Bans::load(&path).is_err(), "{text}");
+    }
+}
+
+#[tokio::test]
+async fn ipv4_network_matches() {
+    let bans = Bans::default();
+    bans.add(entry("10.1.2.3/16")).await.unwrap();
+    assert!(bans.contains(&ip("10.1.0.1")));
+    assert!(bans.contains(&ip("10.1.255.255")));
+    assert!(bans.contains(&ip("::ffff:10.1.0.1")));
+    assert!(!bans.contains(&ip("10.2.0.1")));
+    // the host bits are dropped
+    assert!(bans.remove(&entry("10.1.0.0/16")).await.unwrap());
+}
+
+#[tokio::test]
+async fn ipv6_network_matches() {
+    let bans = Bans::default();
+    bans.add(entry("2001:db8::/32")).await.unwrap();
+    assert!(bans.contains(&ip("2001:db8::1")));
+    assert!(bans.contains(&ip("2001:db8:ffff::1")));
+    assert!(!bans.contains(&ip("2001:db9::1")));
+    assert!(!bans.contains(&ip("1.2.3.4")));
+}
+
+#[tokio::test]
+async fn ipv4_mapped_addresses_are_ipv4() {
+    let bans = Bans::default();
+    bans.add(entry("1.2.3.4")).await.unwrap();
+    assert!(bans.contains(&ip("::ffff:1.2.3.4")));
+    assert_eq!(entry("::ffff:1.2.3.4"), entry("1.2.3.4"));
+    assert_eq!(entry("::ffff:1.2.3.0/120"), entry("1.2.3.0/24"));
+    assert!(bans.remove(&entry("::ffff:1.2.3.4")).await.unwrap());
+    assert!(!bans.contains(&ip("1.2.3.4")));
+}
+
+#[test]
+fn entry_text() {
+    assert_eq!(entry("1.2.3.4").to_string(), "1.2.3.4");
+    assert_eq!(entry("1.2.3.4/32").to_string(), "1.2.3.4");
+    assert_eq!(entry("1.2.3.4/24").to_string(), "1.2.3.0/24");
+    assert_eq!(entry("0.0.0.0/0").to_string(), "0.0.0.0/0");
+    assert_eq!(entry("2001:db8::1/32").to_string(), "2001:db8::/32");
+    for text in ["1.2.3.4/33", "2001:db8::/129", "1.2.3.4/", "1.2.3.4/+8", "1.2.3/8", "/8"] {
+        assert!(text.parse::<BanEntry>().is_err(), "{text}");
+    }
+}
+
+#[tokio::test]
+async fn changes_persist() {
+    let path = temp_dir().join("bans.json");
+    let bans = Bans::load(&path).unwrap();
+    bans.add(entry("5.6.7.8")).await.unwrap();
+    bans.add(entry("1.2.3.4")).await.unwrap();
+    bans.add(entry("10.0.0.0/8")).await.unwrap();
+    assert_eq!(saved(&path), ["1.2.3.4", "10.0.0.0/8", "5.6.7.8"]);
+    assert!(bans.remove(&entry("5.6.7.8")).await.unwrap());
+    assert!(!bans.remove(&entry("5.6.7.8")).await.unwrap());
+
+    let loaded = Bans::load(&path).unwrap();
+    assert!(loaded.contains(&ip("1.2.3.4")));
+    assert!(!loaded.contains(&ip("5.6.7.8")));
+    assert!(loaded.contains(&ip("10.20.30.40")));
+}
+
+#[tokio::test]
+async fn unsaved_ban_still_applies() {
+    let dir = temp_dir();
+    let bans = Bans::load(&dir.join("bans.json")).unwrap();
+    // the directory is gone, so the file can't be written
+    fs::remove_dir(&dir).unwrap();
+    assert!(bans.add(entry("1.2.3.4")).await.is_err());
+    assert!(bans.contains(&ip("1.2.3.4")));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_changes_keep_file_whole() {
+    let dir = temp_dir();
+    let path = dir.join("bans.json");
+    let bans = Arc::new(Bans::load(&path).unwrap());
+    let tasks: Vec<_> = (0..8).map(|task| {
+        let bans = bans.clone();
+        tokio::spawn(async move {
+            for i in 0..25 {
+                bans.add(entry(&format!("10.0.{task}.{i}"))).await.unwrap();
+            }
+        })
+    }).collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(saved(&path).len(), 200);
+    assert_eq!(Bans::load(&path).unwrap().len(), 200);
+    // only the file itself is left, the temporary one is renamed
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn proxy_loads_bans_on_start() {
+    let path = temp_dir().join("bans.json");
+    fs::write(&path, r#"["1.2.3.4"]"#).unwrap();
+    let config = MineginxConfig {
+        bans_file: Some(path.display().to_string()),
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: "127.0.0.1:7878".to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let proxy = Proxy::new(config).bind().await.unwrap();
+    assert!(proxy.state().is_banned(&ip("1.2.3.4")));
+}
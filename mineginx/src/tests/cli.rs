@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use crate::cli::{parse_args, Options, DEFAULT_CONFIG_FILE};
+
+#[test]
+fn default_options() {
+    assert_eq!(parse(&[], None), Ok(Options {
+        check_config: false,
+        dump_config: false,
+        probe: false,
+        config_path: PathBuf::from(DEFAULT_CONFIG_FILE)
+    }));
+}
+
+#[test]
+fn check_config() {
+    assert!(parse(&["-t"], None).unwrap().check_config);
+}
+
+#[test]
+fn dump_config() {
+    assert!(parse(&["--dump-config"], None).unwrap().dump_config);
+}
+
+#[test]
+fn probe() {
+    assert!(parse(&["--probe"], None).unwrap().probe);
+}
+
+#[test]
+fn custom_config_path() {
+    assert_eq!(parse(&["-c", "/etc/mineginx/mineginx.toml"], None).unwrap().config_path, PathBuf::from("/etc/mineginx/mineginx.toml"));
+    assert_eq!(parse(&["--config", "mineginx.json", "-t"], None), Ok(Options {
+        check_config: true,
+        dump_config: false,
+        probe: false,
+        config_path: PathBuf::from("mineginx.json")
+    }));
+}
+
+#[test]
+fn config_path_from_env() {
+    assert_eq!(parse(&[], Some("/config/env.yaml")).unwrap().config_path, PathBuf::from("/config/env.yaml"));
+    assert_eq!(parse(&[], Some("")).unwrap().config_path, PathBuf::from(DEFAULT_CONFIG_FILE));
+}
+
+#[test]
+fn cli_wins_over_env() {
+    assert_eq!(parse(&["-c", "cli.yaml"], Some("env.yaml")).unwrap().config_path, PathBuf::from("cli.yaml"));
+}
+
+#[test]
+fn invalid_args() {
+    assert_eq!(parse(&["-c"], None), Err("-c requires a path".to_string()));
+    assert_eq!(parse(&["--unknown"], None), Err("unknown argument '--unknown'".to_string()));
+}
+
+fn parse(args: &[&str], env_config: Option<&str>) -> Result<Options, String> {
+    parse_args(args.iter().map(|x| x.to_string()), env_config.map(|x| x.to_string()))
+}
@@ -0,0 +1,766 @@
+use std::{path::Path, time::Duration};
+
+use crate::config::{
+    parse_config, serialize_config, server_name_overlaps, server_name_warnings, substitute_env, unique_listen_addresses, validate, validate_addresses, wildcard_matches, with_default_port,
+    ConfigFormat, DomainMatching, Listen, MinecraftServerDescription, MineginxConfig, OnDemand, Overlap, PoolBackend, ServerNamePattern, MAX_BUFFER_SIZE
+};
+
+#[tokio::test]
+async fn valid_addresses() {
+    let config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    assert!(validate_addresses(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn malformed_listen() {
+    let config = make_config("0.0.0.0", "127.0.0.1:7878");
+    let errors = validate_addresses(&config).await;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("invalid listen '0.0.0.0'"));
+}
+
+#[tokio::test]
+async fn malformed_proxy_pass() {
+    let config = make_config("0.0.0.0:25565", "127.0.0.1:port");
+    let errors = validate_addresses(&config).await;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("invalid proxy_pass '127.0.0.1:port'"));
+}
+
+fn make_config(listen: &str, proxy_pass: &str) -> MineginxConfig {
+    let mut server = make_server(&["localhost"], proxy_pass);
+    server.listen = listen.into();
+    MineginxConfig {
+        servers: vec![server],
+        ..Default::default()
+    }
+}
+
+fn make_server(server_names: &[&str], proxy_pass: &str) -> MinecraftServerDescription {
+    MinecraftServerDescription {
+        listen: "0.0.0.0:25565".into(),
+        server_names: server_names.iter().map(|x| x.to_string()).collect(),
+        proxy_pass: proxy_pass.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn parse_valid_config() {
+    let yaml = b"
+handshake_timeout_ms: 1000
+servers:
+- listen: \"0.0.0.0:25565\"
+  server_names: [\"localhost\"]
+  proxy_pass: \"127.0.0.1:7878\"
+";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config, MineginxConfig {
+        handshake_timeout_ms: Some(1000),
+        ..make_config("0.0.0.0:25565", "127.0.0.1:7878")
+    });
+}
+
+#[test]
+fn parse_server_name_patterns() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_name_patterns: ['mc[0-9]+\\.example\\.com']\n  proxy_pass: 127.0.0.1:7878\n";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert!(config.servers[0].server_names.is_empty());
+    let patterns = config.servers[0].server_name_patterns.as_ref().unwrap();
+    assert_eq!(patterns[0].as_str(), "mc[0-9]+\\.example\\.com");
+    assert!(patterns[0].matches("mc7.example.com", true));
+    let serialized = serialize_config(&config, ConfigFormat::Json).unwrap();
+    assert_eq!(parse_config(serialized.as_bytes(), ConfigFormat::Json).unwrap(), config);
+}
+
+#[test]
+fn parse_invalid_server_name_pattern() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_name_patterns: ['mc[0-9']\n  proxy_pass: 127.0.0.1:7878\n";
+    let err = parse_config(yaml, ConfigFormat::Yaml).unwrap_err();
+    assert!(err.contains("invalid server_name_pattern 'mc[0-9'"), "{err}");
+}
+
+#[test]
+fn parse_malformed_config() {
+    assert!(parse_config(b"servers: [", ConfigFormat::Yaml).is_err());
+}
+
+#[test]
+fn parse_config_without_servers() {
+    assert!(parse_config(b"handshake_timeout_ms: 1000", ConfigFormat::Yaml).is_err());
+}
+
+#[tokio::test]
+async fn validate_valid_config() {
+    let config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    assert!(validate(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn validate_without_servers() {
+    let config = MineginxConfig::default();
+    assert_eq!(validate(&config).await, vec!["there are no servers"]);
+}
+
+#[tokio::test]
+async fn validate_empty_server_names() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names.clear();
+    assert_eq!(validate(&config).await, vec!["server #0: server_names must not be empty"]);
+}
+
+#[tokio::test]
+async fn validate_control_listen_is_local() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    for local in ["127.0.0.1:25580", "[::1]:25580", "unix:/run/mineginx.sock"] {
+        config.control_listen = Some(local.to_string());
+        assert!(validate(&config).await.is_empty(), "{local}");
+    }
+    config.control_listen = Some("0.0.0.0:25580".to_string());
+    assert_eq!(validate(&config).await, vec!["control_listen '0.0.0.0:25580' must be a loopback address or a unix socket"]);
+}
+
+#[tokio::test]
+async fn validate_server_with_only_patterns() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names.clear();
+    config.servers[0].server_name_patterns = Some(vec![ServerNamePattern::new(".*\\.example\\.com").unwrap()]);
+    assert!(validate(&config).await.is_empty());
+    config.servers[0].server_name_patterns = Some(vec![]);
+    assert_eq!(validate(&config).await, vec!["server #0: server_names must not be empty"]);
+}
+
+#[tokio::test]
+async fn validate_misplaced_wildcard() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names = vec!["*.example.com".to_string(), "mc.*.com".to_string()];
+    assert_eq!(validate(&config).await, vec!["server #0: server_name 'mc.*.com' may only contain a leading '*.' wildcard"]);
+}
+
+#[test]
+fn server_names_without_overlap() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers.push(make_server(&["*.example.com", "example.com"], "127.0.0.2:7878"));
+    assert!(server_name_overlaps(&config).is_empty());
+}
+
+#[test]
+fn duplicated_server_names() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers.push(make_server(&["localhost"], "127.0.0.2:7878"));
+    let overlaps = server_name_overlaps(&config);
+    assert_eq!(overlaps, vec![Overlap::DuplicateServerName {
+        server_name: "localhost".to_string(),
+        listen: "0.0.0.0:25565".to_string(),
+        winner: 0,
+        ignored: 1,
+        upstream: "127.0.0.1:7878".to_string()
+    }]);
+    assert_eq!(overlaps[0].to_string(), "server_name 'localhost' of server #1 is already used by server #0 on 0.0.0.0:25565, connections go to 127.0.0.1:7878");
+}
+
+#[tokio::test]
+async fn duplicated_server_names_are_errors() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers.push(make_server(&["localhost"], "127.0.0.2:7878"));
+    assert!(server_name_warnings(&config).is_empty());
+    assert_eq!(validate(&config).await, vec!["server_name 'localhost' of server #1 is already used by server #0 on 0.0.0.0:25565, connections go to 127.0.0.1:7878"]);
+    config.servers[1].listen = "0.0.0.0:25566".into();
+    assert!(validate(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn server_name_repeated_in_one_server() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names = vec!["localhost".to_string(), "LocalHost".to_string()];
+    assert!(server_name_overlaps(&config).is_empty());
+    assert!(validate(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn wildcard_overlap_is_not_an_error() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names = vec!["*.example.com".to_string()];
+    config.servers.push(make_server(&["mc.example.com"], "127.0.0.2:7878"));
+    assert_eq!(server_name_warnings(&config).len(), 1);
+    assert!(validate(&config).await.is_empty());
+}
+
+#[test]
+fn same_server_names_on_other_listen() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers.push(make_server(&["localhost", "mc.localhost"], "127.0.0.2:7878"));
+    config.servers[1].listen = "0.0.0.0:25566".into();
+    config.servers[0].server_names.push("*.localhost".to_string());
+    assert!(server_name_overlaps(&config).is_empty());
+}
+
+#[test]
+fn duplicated_server_names_on_shared_listen() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].listen = Listen::Multiple(vec!["0.0.0.0:25565".to_string(), "0.0.0.0:25566".to_string()]);
+    config.servers.push(make_server(&["localhost"], "127.0.0.2:7878"));
+    config.servers[1].listen = Listen::Multiple(vec!["0.0.0.0:25566".to_string(), "0.0.0.0:25565".to_string()]);
+    assert_eq!(server_name_overlaps(&config), vec![Overlap::DuplicateServerName {
+        server_name: "localhost".to_string(),
+        listen: "0.0.0.0:25565".to_string(),
+        winner: 0,
+        ignored: 1,
+        upstream: "127.0.0.1:7878".to_string()
+    }]);
+}
+
+#[test]
+fn duplicated_server_names_ignore_case() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers.push(make_server(&["LocalHost"], "127.0.0.2:7878"));
+    assert_eq!(server_name_overlaps(&config), vec![Overlap::DuplicateServerName {
+        server_name: "LocalHost".to_string(),
+        listen: "0.0.0.0:25565".to_string(),
+        winner: 0,
+        ignored: 1,
+        upstream: "127.0.0.1:7878".to_string()
+    }]);
+}
+
+#[test]
+fn case_sensitive_server_names_are_not_duplicated() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.domain_matching = Some(DomainMatching { case_insensitive: Some(false), ..Default::default() });
+    config.servers.push(make_server(&["LocalHost"], "127.0.0.2:7878"));
+    assert!(server_name_overlaps(&config).is_empty());
+}
+
+#[test]
+fn wildcard_overlaps_exact_server_name() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names = vec!["*.example.com".to_string()];
+    config.servers.push(make_server(&["mc.example.com"], "127.0.0.2:7878"));
+    let warnings = server_name_warnings(&config);
+    assert_eq!(warnings, vec![Overlap::WildcardOverlap {
+        server_name: "mc.example.com".to_string(),
+        wildcard: "*.example.com".to_string(),
+        listen: "0.0.0.0:25565".to_string(),
+        winner: 1,
+        ignored: 0,
+        upstream: "127.0.0.2:7878".to_string()
+    }]);
+    assert_eq!(warnings[0].to_string(), "server_name 'mc.example.com' of server #1 also matches '*.example.com' of server #0 on 0.0.0.0:25565, connections go to 127.0.0.2:7878");
+}
+
+#[test]
+fn warnings_leave_out_duplicates() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].server_names = vec!["*.example.com".to_string(), "localhost".to_string()];
+    config.servers.push(make_server(&["mc.example.com", "localhost"], "127.0.0.2:7878"));
+    assert_eq!(server_name_overlaps(&config).len(), 2);
+    let warnings = server_name_warnings(&config);
+    assert_eq!(warnings.len(), 1);
+    assert!(matches!(warnings[0], Overlap::WildcardOverlap { .. }));
+}
+
+#[test]
+fn wildcard_matching() {
+    assert!(wildcard_matches("*.example.com", "mc.example.com", true));
+    assert!(wildcard_matches("*.example.com", "eu.mc.example.com", true));
+    assert!(!wildcard_matches("*.example.com", "example.com", true));
+    assert!(!wildcard_matches("*.example.com", "badexample.com", true));
+    assert!(!wildcard_matches("example.com", "example.com", true));
+}
+
+#[tokio::test]
+async fn validate_zero_timeout_and_huge_buffer() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.handshake_timeout_ms = Some(0);
+    config.servers[0].buffer_size = Some(MAX_BUFFER_SIZE + 1);
+    assert_eq!(validate(&config).await, vec![
+        "handshake_timeout_ms must be greater than 0".to_string(),
+        format!("server #0: buffer_size must be between 1 and {MAX_BUFFER_SIZE}")
+    ]);
+}
+
+#[tokio::test]
+async fn validate_handshake_buffer_size() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.handshake_buffer_size = Some(0);
+    assert_eq!(validate(&config).await, [format!("handshake_buffer_size must be between 1 and {MAX_BUFFER_SIZE}")]);
+    config.handshake_buffer_size = Some(64);
+    assert!(validate(&config).await.is_empty());
+    assert_eq!(config.handshake_buffer_size(), 64);
+    assert_eq!(MineginxConfig::default().handshake_buffer_size(), 4096);
+}
+
+#[tokio::test]
+async fn validate_collects_all_errors() {
+    let mut config = make_config("0.0.0.0", "127.0.0.1:port");
+    config.servers[0].buffer_size = Some(0);
+    let errors = validate(&config).await;
+    assert_eq!(errors.len(), 3);
+    assert!(errors[0].starts_with("server #0: buffer_size"));
+    assert!(errors[1].starts_with("server #0: invalid listen '0.0.0.0'"));
+    assert!(errors[2].starts_with("server #0: invalid proxy_pass '127.0.0.1:port'"));
+}
+
+#[tokio::test]
+async fn ipv6_addresses() {
+    let config = make_config("[::]:25565", "[2001:db8::1]:7777");
+    assert!(validate_addresses(&config).await.is_empty());
+}
+
+#[test]
+fn same_config_in_all_formats() {
+    let yaml = b"
+handshake_timeout_ms: 1000
+servers:
+- listen: \"0.0.0.0:25565\"
+  server_names: [\"localhost\", \"*.localhost\"]
+  proxy_pass: \"127.0.0.1:7878\"
+  buffer_size: 4096
+";
+    let toml = b"
+handshake_timeout_ms = 1000
+
+[[servers]]
+listen = \"0.0.0.0:25565\"
+server_names = [\"localhost\", \"*.localhost\"]
+proxy_pass = \"127.0.0.1:7878\"
+buffer_size = 4096
+";
+    let json = br#"{
+    "handshake_timeout_ms": 1000,
+    "servers": [{
+        "listen": "0.0.0.0:25565",
+        "server_names": ["localhost", "*.localhost"],
+        "proxy_pass": "127.0.0.1:7878",
+        "buffer_size": 4096
+    }]
+}"#;
+    let expected = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(expected.servers[0].server_names.len(), 2);
+    assert_eq!(expected.servers[0].buffer_size, Some(4096));
+    assert_eq!(parse_config(toml, ConfigFormat::Toml).unwrap(), expected);
+    assert_eq!(parse_config(json, ConfigFormat::Json).unwrap(), expected);
+}
+
+#[test]
+fn format_from_extension() {
+    assert_eq!(ConfigFormat::from_path(Path::new("config/mineginx.yaml")), ConfigFormat::Yaml);
+    assert_eq!(ConfigFormat::from_path(Path::new("config/mineginx.yml")), ConfigFormat::Yaml);
+    assert_eq!(ConfigFormat::from_path(Path::new("config/mineginx.toml")), ConfigFormat::Toml);
+    assert_eq!(ConfigFormat::from_path(Path::new("config/mineginx.json")), ConfigFormat::Json);
+    assert_eq!(ConfigFormat::from_path(Path::new("config/mineginx")), ConfigFormat::Yaml);
+}
+
+#[test]
+fn serialize_in_all_formats() {
+    let config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    for format in [ConfigFormat::Yaml, ConfigFormat::Toml, ConfigFormat::Json] {
+        let data = serialize_config(&config, format).unwrap();
+        assert_eq!(parse_config(data.as_bytes(), format).unwrap(), config);
+    }
+}
+
+#[test]
+fn address_with_port_is_kept() {
+    assert_eq!(with_default_port("mc.example.com:25566"), "mc.example.com:25566");
+    assert_eq!(with_default_port("10.0.0.1:7878"), "10.0.0.1:7878");
+    assert_eq!(with_default_port("[::1]:7878"), "[::1]:7878");
+    assert_eq!(with_default_port("unix:/run/mineginx.sock"), "unix:/run/mineginx.sock");
+}
+
+#[test]
+fn address_without_port_gets_default() {
+    assert_eq!(with_default_port("mc.example.com"), "mc.example.com:25565");
+    assert_eq!(with_default_port("0.0.0.0"), "0.0.0.0:25565");
+}
+
+#[test]
+fn ipv6_without_port_gets_default() {
+    assert_eq!(with_default_port("[::1]"), "[::1]:25565");
+    assert_eq!(with_default_port("::1"), "[::1]:25565");
+    assert_eq!(with_default_port("2001:db8::1"), "[2001:db8::1]:25565");
+}
+
+#[test]
+fn parsed_addresses_get_default_port() {
+    let yaml = b"
+servers:
+- listen:
+  - \"0.0.0.0\"
+  - \"[::]:25566\"
+  server_names:
+  - \"mc.example.com\"
+  proxy_pass: \"backend.internal\"
+";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config.servers[0].listen.addresses(), ["0.0.0.0:25565", "[::]:25566"]);
+    assert_eq!(config.servers[0].proxy_pass, "backend.internal:25565");
+}
+
+#[test]
+fn substitute_present_variable() {
+    let text = substitute_env("proxy_pass: \"${BACKEND}:25565\"", |name| (name == "BACKEND").then(|| "10.0.0.1".to_string()));
+    assert_eq!(text, Ok("proxy_pass: \"10.0.0.1:25565\"".to_string()));
+}
+
+#[test]
+fn substitute_default_value() {
+    let text = substitute_env("listen: \"${LISTEN:-0.0.0.0:25565}\"", |_| None);
+    assert_eq!(text, Ok("listen: \"0.0.0.0:25565\"".to_string()));
+    let text = substitute_env("listen: \"${LISTEN:-0.0.0.0:25565}\"", |_| Some("[::]:25565".to_string()));
+    assert_eq!(text, Ok("listen: \"[::]:25565\"".to_string()));
+}
+
+#[test]
+fn substitute_missing_variable() {
+    let text = substitute_env("secret: ${SECRET}", |_| None);
+    assert_eq!(text, Err("environment variable 'SECRET' is not set".to_string()));
+}
+
+#[test]
+fn substitute_unclosed_placeholder() {
+    let text = substitute_env("secret: ${SECRET", |_| None);
+    assert_eq!(text, Err("unclosed placeholder '${SECRET'".to_string()));
+}
+
+#[test]
+fn substitute_without_placeholders() {
+    let text = substitute_env("price: $5 {not a placeholder}", |_| None);
+    assert_eq!(text, Ok("price: $5 {not a placeholder}".to_string()));
+}
+
+#[test]
+fn substitute_server_fields() {
+    let yaml = "
+servers:
+- listen: \"${LISTEN:-0.0.0.0:25565}\"
+  server_names:
+  - \"${DOMAIN}\"
+  proxy_pass: \"${BACKEND_ADDR}\"
+";
+    let variables = |name: &str| match name {
+        "DOMAIN" => Some("mc.example.com".to_string()),
+        "BACKEND_ADDR" => Some("10.0.0.1:25565".to_string()),
+        _ => None
+    };
+    let text = substitute_env(yaml, variables).unwrap();
+    let server = &parse_config(text.as_bytes(), ConfigFormat::Yaml).unwrap().servers[0];
+    assert_eq!(server.listen.addresses(), ["0.0.0.0:25565"]);
+    assert_eq!(server.server_names, ["mc.example.com"]);
+    assert_eq!(server.proxy_pass, "10.0.0.1:25565");
+    assert_eq!(substitute_env(yaml, |_| None), Err("environment variable 'DOMAIN' is not set".to_string()));
+}
+
+#[test]
+fn dump_minimal_config_with_defaults() {
+    let yaml = b"
+servers:
+- listen: \"0.0.0.0:25565\"
+  server_names: [\"localhost\"]
+  proxy_pass: \"127.0.0.1:7878\"
+";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap().with_defaults();
+    let dumped = serialize_config(&config, ConfigFormat::Yaml).unwrap();
+    assert_eq!(dumped, "\
+handshake_timeout_ms: 10000
+upstream_connect_timeout_ms: 10000
+upstream_connect_retries: 0
+upstream_retry_backoff_ms: 100
+upstream_write_timeout_ms: 10000
+tcp_nodelay: true
+handshake_buffer_size: 4096
+allow_transfer: true
+domain_matching:
+  strip_fml: true
+  case_insensitive: true
+  strip_trailing_dot: false
+connection_log_sample_rate: 1.0
+servers:
+- listen: 0.0.0.0:25565
+  server_names:
+  - localhost
+  proxy_pass: 127.0.0.1:7878
+  buffer_size: 2048
+  handshake_timeout_ms: 10000
+  connect_retries: 0
+  connect_retry_delay_ms: 100
+  address_family: auto
+  upstream_tcp_nodelay: true
+  allow_transfer: true
+");
+}
+
+#[test]
+fn defaults_keep_explicit_values() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.handshake_timeout_ms = Some(1);
+    config.servers[0].buffer_size = Some(4096);
+    let config = config.with_defaults();
+    assert_eq!(config.handshake_timeout_ms, Some(1));
+    assert_eq!(config.servers[0].buffer_size, Some(4096));
+}
+
+#[test]
+fn listen_single_or_list() {
+    let yaml = b"
+servers:
+- listen: \"0.0.0.0:25565\"
+  server_names: [\"localhost\"]
+  proxy_pass: \"127.0.0.1:7878\"
+- listen: [\"0.0.0.0:25565\", \"0.0.0.0:25566\"]
+  server_names: [\"example.com\"]
+  proxy_pass: \"127.0.0.1:7879\"
+";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config.servers[0].listen, Listen::Single("0.0.0.0:25565".to_string()));
+    assert_eq!(config.servers[1].listen.addresses(), ["0.0.0.0:25565", "0.0.0.0:25566"]);
+    assert_eq!(unique_listen_addresses(&config), ["0.0.0.0:25565", "0.0.0.0:25566"]);
+}
+
+#[tokio::test]
+async fn validate_empty_listen() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].listen = Listen::Multiple(vec![]);
+    assert_eq!(validate(&config).await, vec!["server #0: listen must not be empty"]);
+}
+
+#[test]
+fn handshake_timeout_precedence() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    assert_eq!(config.handshake_timeout(&config.servers[0]), Duration::from_millis(10_000));
+    config.handshake_timeout_ms = Some(5_000);
+    assert_eq!(config.handshake_timeout(&config.servers[0]), Duration::from_millis(5_000));
+    config.servers[0].handshake_timeout_ms = Some(60_000);
+    assert_eq!(config.handshake_timeout(&config.servers[0]), Duration::from_millis(60_000));
+}
+
+#[test]
+fn listener_waits_for_most_patient_server() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.handshake_timeout_ms = Some(5_000);
+    config.servers.push(MinecraftServerDescription {
+        handshake_timeout_ms: Some(60_000),
+        ..make_server(&["satellite.localhost"], "127.0.0.1:7879")
+    });
+    config.servers.push(MinecraftServerDescription {
+        listen: "0.0.0.0:25566".into(),
+        handshake_timeout_ms: Some(1_000),
+        ..make_server(&["strict.localhost"], "127.0.0.1:7880")
+    });
+    assert_eq!(config.listener_handshake_timeout("0.0.0.0:25565"), Duration::from_millis(60_000));
+    assert_eq!(config.listener_handshake_timeout("0.0.0.0:25566"), Duration::from_millis(1_000));
+    assert_eq!(config.listener_handshake_timeout("0.0.0.0:25567"), Duration::from_millis(5_000));
+}
+
+#[test]
+fn server_label() {
+    let mut server = make_server(&["localhost"], "127.0.0.1:7878");
+    assert_eq!(server.label(), "127.0.0.1:7878");
+    server.name = Some("eu-lobby".to_string());
+    assert_eq!(server.label(), "eu-lobby (127.0.0.1:7878)");
+}
+
+#[tokio::test]
+async fn named_server_in_errors() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].name = Some("eu-lobby".to_string());
+    config.servers.push(make_server(&["localhost"], "127.0.0.2:7878"));
+    assert_eq!(validate(&config).await, [ "server_name 'localhost' of server #1 is already used by server #0 on 0.0.0.0:25565, connections go to eu-lobby (127.0.0.1:7878)"]);
+}
+
+#[test]
+fn connect_options_precedence() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    let options = config.connect_options(&config.servers[0]);
+    assert_eq!((options.timeout, options.retries, options.retry_backoff), (Duration::from_millis(10_000), 0, Duration::from_millis(100)));
+    config.upstream_connect_timeout_ms = Some(3_000);
+    config.upstream_connect_retries = Some(2);
+    config.upstream_retry_backoff_ms = Some(200);
+    let options = config.connect_options(&config.servers[0]);
+    assert_eq!((options.timeout, options.retries, options.retry_backoff), (Duration::from_millis(3_000), 2, Duration::from_millis(200)));
+    config.servers[0].connect_retries = Some(5);
+    config.servers[0].connect_retry_delay_ms = Some(50);
+    let options = config.connect_options(&config.servers[0]);
+    assert_eq!((options.timeout, options.retries, options.retry_backoff), (Duration::from_millis(3_000), 5, Duration::from_millis(50)));
+}
+
+#[tokio::test]
+async fn validate_empty_allowed_protocol_versions() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].allowed_protocol_versions = Some(vec![]);
+    assert_eq!(validate(&config).await, ["server #0: allowed_protocol_versions must not be empty"]);
+}
+
+#[tokio::test]
+async fn validate_upstream_hostname() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].upstream_hostname = Some(String::new());
+    assert_eq!(validate(&config).await, ["server #0: upstream_hostname must be a host name"]);
+    config.servers[0].upstream_hostname = Some("vhost.example.com\0FML3\0".to_string());
+    assert_eq!(validate(&config).await, ["server #0: upstream_hostname must be a host name"]);
+    config.servers[0].upstream_hostname = Some("vhost.example.com".to_string());
+    assert!(validate(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn validate_preserve_handshake() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].preserve_handshake = Some(true);
+    assert!(validate(&config).await.is_empty());
+    config.servers[0].rewrite_port = Some(25566);
+    assert_eq!(validate(&config).await, ["server #0: preserve_handshake can't be used with upstream_hostname or rewrite_port"]);
+    config.servers[0].preserve_handshake = Some(false);
+    assert!(validate(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn validate_upstream_buffer_sizes() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].upstream_send_buffer_size = Some(0);
+    config.servers[0].upstream_recv_buffer_size = Some(0);
+    assert_eq!(validate(&config).await, [
+        "server #0: upstream_send_buffer_size must be greater than 0",
+        "server #0: upstream_recv_buffer_size must be greater than 0"
+    ]);
+    config.servers[0].upstream_send_buffer_size = Some(65536);
+    config.servers[0].upstream_recv_buffer_size = Some(65536);
+    assert!(validate(&config).await.is_empty());
+}
+
+#[test]
+fn rewrite_host_is_upstream_hostname() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_names: [play.example.com]\n  proxy_pass: 127.0.0.1:7878\n  rewrite_host: survival.internal\n";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config.servers[0].upstream_hostname.as_deref(), Some("survival.internal"));
+}
+
+#[tokio::test]
+async fn validate_proxy_pass_srv() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].proxy_pass_srv = Some("_minecraft._tcp.example.com".to_string());
+    assert_eq!(validate(&config).await, ["server #0: proxy_pass_srv can't be used with proxy_pass or proxy_pass_pool"]);
+    config.servers[0].proxy_pass = String::new();
+    assert!(validate(&config).await.is_empty());
+    config.servers[0].proxy_pass_srv = Some("_minecraft..example.com".to_string());
+    assert_eq!(validate(&config).await, ["server #0: proxy_pass_srv must be a domain name"]);
+}
+
+#[test]
+fn proxy_pass_srv_without_proxy_pass() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_names: [play.example.com]\n  proxy_pass_srv: _minecraft._tcp.backend.example.com\n";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config.servers[0].proxy_pass_srv.as_deref(), Some("_minecraft._tcp.backend.example.com"));
+    assert!(config.servers[0].upstream_addresses().is_empty());
+}
+
+#[tokio::test]
+async fn validate_proxy_pass_pool() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    let backend = |addr: &str, weight: Option<u32>| PoolBackend { addr: addr.to_string(), weight };
+    config.servers[0].proxy_pass_pool = Some(vec![backend("127.0.0.1:7879", None)]);
+    assert_eq!(validate(&config).await, ["server #0: proxy_pass and proxy_pass_pool can't be used together"]);
+    config.servers[0].proxy_pass = String::new();
+    assert!(validate(&config).await.is_empty());
+    config.servers[0].proxy_pass_pool = Some(vec![]);
+    assert_eq!(validate(&config).await, ["server #0: proxy_pass_pool must not be empty"]);
+    config.servers[0].proxy_pass_pool = Some(vec![backend("127.0.0.1:7879", Some(0))]);
+    assert_eq!(validate(&config).await, ["server #0: weights of proxy_pass_pool must be greater than 0"]);
+    config.servers[0].proxy_pass_pool = Some(vec![backend("127.0.0.1:7879", Some(2)), backend("127.0.0.1:port", None)]);
+    let errors = validate(&config).await;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("server #0: invalid proxy_pass '127.0.0.1:port'"));
+}
+
+#[tokio::test]
+async fn validate_sticky_sessions() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].sticky_sessions = Some(true);
+    assert_eq!(validate(&config).await, ["server #0: sticky_sessions needs proxy_pass_pool"]);
+    config.servers[0].proxy_pass = String::new();
+    config.servers[0].proxy_pass_pool = Some(vec![PoolBackend { addr: "127.0.0.1:7879".to_string(), weight: None }]);
+    assert!(validate(&config).await.is_empty());
+}
+
+#[test]
+fn parse_proxy_pass_pool() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_names: [localhost]\n  proxy_pass_pool:\n  - addr: 10.0.0.1:25565\n    weight: 3\n  - addr: 10.0.0.2:25565\n";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    let server = &config.servers[0];
+    assert_eq!(server.upstream_addresses(), ["10.0.0.1:25565", "10.0.0.2:25565"]);
+    let pool = config.with_defaults().servers[0].proxy_pass_pool.clone().unwrap();
+    assert_eq!(pool.iter().map(|x| x.weight).collect::<Vec<_>>(), [Some(3), Some(1)]);
+    assert!(!serialize_config(&config, ConfigFormat::Yaml).unwrap().contains("proxy_pass:"));
+}
+
+#[test]
+fn proxy_pass_pool_default_port() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_names: [localhost]\n  proxy_pass_pool:\n  - addr: backend\n  - addr: 10.0.0.2:25566\n  - addr: '::1'\n";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config.servers[0].upstream_addresses(), ["backend:25565", "10.0.0.2:25566", "[::1]:25565"]);
+}
+
+#[tokio::test]
+async fn validate_on_demand() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers[0].on_demand = Some(OnDemand {
+        command: Some(" ".to_string()),
+        start_timeout_ms: Some(0),
+        starting_message: None,
+        poll_interval_ms: Some(0)
+    });
+    assert_eq!(validate(&config).await, [
+        "server #0: on_demand.command must not be empty",
+        "server #0: on_demand.start_timeout_ms must be greater than 0",
+        "server #0: on_demand.poll_interval_ms must be greater than 0"
+    ]);
+    // holding the players without starting anything is fine
+    config.servers[0].on_demand = Some(OnDemand::default());
+    assert!(validate(&config).await.is_empty());
+}
+
+#[test]
+fn on_demand_hold_names() {
+    let yaml = br#"
+servers:
+  - listen: 0.0.0.0:25565
+    server_names: [mc.example.com]
+    proxy_pass: 127.0.0.1:7878
+    on_demand:
+      start_command: ./start.sh
+      max_wait_ms: 30000
+      poll_interval_ms: 250
+"#;
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    let on_demand = config.servers[0].on_demand.clone().unwrap();
+    assert_eq!(on_demand.command.as_deref(), Some("./start.sh"));
+    assert_eq!(on_demand.start_timeout(), Duration::from_secs(30));
+    assert_eq!(on_demand.poll_interval(), Duration::from_millis(250));
+    assert_eq!(OnDemand::default().poll_interval(), Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn validate_mixed_proxy_protocol() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    config.servers.push(MinecraftServerDescription {
+        listen: Listen::Multiple(vec!["0.0.0.0:25565".to_string(), "0.0.0.0:25566".to_string()]),
+        accept_proxy_protocol: Some(true),
+        ..make_server(&["other.example.com"], "127.0.0.1:7879")
+    });
+    assert_eq!(validate(&config).await, ["servers #0, #1 listen '0.0.0.0:25565', accept_proxy_protocol must be the same for all of them"]);
+    assert!(!config.listener_accepts_proxy_protocol("0.0.0.0:25567"));
+    assert!(config.listener_accepts_proxy_protocol("0.0.0.0:25566"));
+
+    config.servers[0].accept_proxy_protocol = Some(true);
+    assert!(validate(&config).await.is_empty());
+    assert!(config.listener_accepts_proxy_protocol("0.0.0.0:25565"));
+}
+
+#[test]
+fn allow_transfer_precedence() {
+    let mut config = make_config("0.0.0.0:25565", "127.0.0.1:7878");
+    assert!(config.allows_transfer(&config.servers[0]));
+    config.allow_transfer = Some(false);
+    assert!(!config.allows_transfer(&config.servers[0]));
+    config.servers[0].allow_transfer = Some(true);
+    assert!(config.allows_transfer(&config.servers[0]));
+}
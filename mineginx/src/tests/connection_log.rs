@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use tokio::{net::TcpListener, time::sleep};
+
+use crate::{
+    config::{validate, MinecraftServerDescription, MineginxConfig},
+    tests::{harness::{assert_closed, handshake, start_proxy}, logs::{capture_logs, captured}, upstream::free_address},
+    Proxy
+};
+
+fn sampled_config(rate: f64) -> MineginxConfig {
+    MineginxConfig {
+        connection_log_sample_rate: Some(rate),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn sample_rate_bounds() {
+    assert!(MineginxConfig::default().samples_connection_log());
+    assert!((0..100).all(|_| sampled_config(1.0).samples_connection_log()));
+    assert!((0..100).all(|_| !sampled_config(0.0).samples_connection_log()));
+    let half = sampled_config(0.5);
+    let sampled = (0..1000).filter(|_| half.samples_connection_log()).count();
+    assert!((300..700).contains(&sampled), "sampled {sampled} of 1000");
+}
+
+#[tokio::test]
+async fn validate_sample_rate() {
+    let server = MinecraftServerDescription {
+        listen: "0.0.0.0:25565".into(),
+        server_names: vec!["localhost".to_string()],
+        proxy_pass: "127.0.0.1:7878".to_string(),
+        ..Default::default()
+    };
+    for rate in [-0.1, 1.5, f64::NAN] {
+        let config = MineginxConfig { servers: vec![server.clone()], ..sampled_config(rate) };
+        assert_eq!(validate(&config).await, ["connection_log_sample_rate must be between 0 and 1"]);
+    }
+    for rate in [0.0, 0.01, 1.0] {
+        let config = MineginxConfig { servers: vec![server.clone()], ..sampled_config(rate) };
+        assert!(validate(&config).await.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn zero_rate_logs_only_failures() {
+    capture_logs();
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = |name: &str, proxy_pass: String| MinecraftServerDescription {
+        listen: "127.0.0.1:0".into(),
+        server_names: vec![name.to_string()],
+        proxy_pass,
+        ..Default::default()
+    };
+    let config = MineginxConfig {
+        servers: vec![
+            server("quiet.sampling.example.com", upstream.local_addr().unwrap().to_string()),
+            server("down.sampling.example.com", free_address().await)
+        ],
+        ..sampled_config(0.0)
+    };
+    let proxy = start_proxy(Proxy::new(config)).await;
+
+    let mut client = proxy.connect(&handshake("quiet.sampling.example.com", 2)).await;
+    let (backend, _) = upstream.accept().await.unwrap();
+    drop(backend);
+    assert_closed(&mut client).await;
+
+    let mut client = proxy.connect(&handshake("down.sampling.example.com", 2)).await;
+    assert_closed(&mut client).await;
+    // the failure is logged after the client is closed
+    for _ in 0..100 {
+        if !captured("failed to connect upstream").is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert!(captured("sampling.example.com").iter().all(|x| !x.starts_with("INFO")));
+    assert!(captured("failed to connect upstream").iter().any(|x| x.starts_with("ERROR")));
+}
+
+#[tokio::test]
+async fn named_server_in_connection_log() {
+    capture_logs();
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_pass = upstream.local_addr().unwrap().to_string();
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["named.log.example.com".to_string()],
+            proxy_pass: proxy_pass.clone(),
+            name: Some("eu-lobby".to_string()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let proxy = start_proxy(Proxy::new(config)).await;
+
+    let _client = proxy.connect(&handshake("named.log.example.com", 2)).await;
+    let _backend = upstream.accept().await.unwrap();
+    assert_eq!(captured("domain: named.log.example.com"), [
+        format!("INFO new connection (protocol_version: 765 (1.20.3-1.20.4), domain: named.log.example.com, upstream: eu-lobby ({proxy_pass}))")
+    ]);
+}
@@ -0,0 +1,206 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}, time::timeout};
+
+use crate::{
+    config::{serialize_config, ConfigFormat, MinecraftServerDescription, MineginxConfig},
+    control::serve_control,
+    listener::Listener,
+    state::{State, UpstreamStats},
+    tests::harness::{assert_closed, connect, handshake, start_proxy, Harness},
+    Proxy
+};
+
+/// A listener expecting PROXY headers, so the tests can connect from any ip, and the address of its control socket
+async fn start_control(config_path: Option<PathBuf>) -> (Harness, String) {
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        accept_proxy_protocol: Some(true),
+        ..Default::default()
+    }).await;
+    let control = control_socket(&harness.state, config_path).await;
+    (harness, control)
+}
+
+async fn control_socket(state: &Arc<State>, config_path: Option<PathBuf>) -> String {
+    let control = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = control.local_addr().unwrap().to_string();
+    tokio::spawn(serve_control(Listener::Tcp(control), state.clone(), config_path));
+    address
+}
+
+async fn command(control: &str, line: &str) -> String {
+    let mut stream = TcpStream::connect(control).await.unwrap();
+    stream.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).await.unwrap();
+    response.trim_end().to_string()
+}
+
+async fn connect_from(address: &str, ip: &str) -> TcpStream {
+    connect_with(address, ip, 2).await
+}
+
+async fn connect_with(address: &str, ip: &str, next_state: i32) -> TcpStream {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    let header = format!("PROXY TCP4 {ip} 10.0.0.1 51000 25565\r\n");
+    client.write_all(&[header.as_bytes(), &handshake("localhost", next_state)].concat()).await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn banned_ip_is_rejected() {
+    let (proxy, control) = start_control(None).await;
+    assert_eq!(command(&control, "ban 1.2.3.4").await, "ok");
+
+    let mut client = connect_from(&proxy.address, "1.2.3.4").await;
+    assert_closed(&mut client).await;
+    assert!(timeout(Duration::from_millis(200), proxy.upstream.accept()).await.is_err());
+
+    // other clients are still proxied
+    let _client = connect_from(&proxy.address, "5.6.7.8").await;
+    proxy.upstream.accept().await.unwrap();
+}
+
+#[tokio::test]
+async fn unbanned_ip_is_proxied() {
+    let (proxy, control) = start_control(None).await;
+    assert_eq!(command(&control, "ban 1.2.3.4").await, "ok");
+    assert_eq!(command(&control, "unban 1.2.3.4").await, "ok");
+    assert_eq!(command(&control, "unban 1.2.3.4").await, "error: 1.2.3.4 is not banned");
+
+    let _client = connect_from(&proxy.address, "1.2.3.4").await;
+    proxy.upstream.accept().await.unwrap();
+}
+
+#[tokio::test]
+async fn banned_network_is_rejected() {
+    let (proxy, control) = start_control(None).await;
+    assert_eq!(command(&control, "ban 1.2.3.0/24").await, "ok");
+    let mut client = connect_from(&proxy.address, "1.2.3.4").await;
+    assert_closed(&mut client).await;
+
+    assert_eq!(command(&control, "unban 1.2.3.4").await, "error: 1.2.3.4 is not banned");
+    assert_eq!(command(&control, "unban 1.2.3.0/24").await, "ok");
+    let _client = connect_from(&proxy.address, "1.2.3.4").await;
+    proxy.upstream.accept().await.unwrap();
+}
+
+#[tokio::test]
+async fn banned_ipv4_client_of_dual_stack_listener_is_rejected() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    // IPv4 clients of `[::]` are accepted as `::ffff:127.0.0.1`
+    let proxy = start_proxy(Proxy::new(MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "[::]:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: upstream.local_addr().unwrap().to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    })).await;
+    let control = control_socket(&proxy.state, None).await;
+
+    let ipv4_address = proxy.address.replace("[::]", "127.0.0.1");
+    let _client = connect(&ipv4_address, &handshake("localhost", 2)).await;
+    upstream.accept().await.unwrap();
+
+    assert_eq!(command(&control, "ban 127.0.0.1").await, "ok");
+    let mut client = connect(&ipv4_address, &handshake("localhost", 2)).await;
+    assert_closed(&mut client).await;
+    assert!(timeout(Duration::from_millis(200), upstream.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn kick_closes_clients_of_domain() {
+    let (proxy, control) = start_control(None).await;
+    let mut client = connect_from(&proxy.address, "1.2.3.4").await;
+    let (mut backend, _) = proxy.upstream.accept().await.unwrap();
+    // the client gets data from the backend once forwarding is running
+    backend.write_all(b"ping").await.unwrap();
+    let mut received = [0; 4];
+    client.read_exact(&mut received).await.unwrap();
+
+    assert_eq!(command(&control, "kick other.example.com").await, "ok");
+    let mut buf = [0; 16];
+    assert!(timeout(Duration::from_millis(200), client.read(&mut buf)).await.is_err());
+
+    assert_eq!(command(&control, "kick LocalHost").await, "ok");
+    assert_closed(&mut client).await;
+}
+
+#[tokio::test]
+async fn status_counts() {
+    let (_proxy, control) = start_control(None).await;
+    assert_eq!(command(&control, "ban 1.2.3.4").await, "ok");
+    assert_eq!(command(&control, "ban 2001:db8::1").await, "ok");
+    assert_eq!(command(&control, "status").await, "sessions=0 scanners=0 empty_connections=0 handshake_failures=0 misses=0 upstream_write_timeouts=0 bytes_in=0 bytes_out=0 bans=2");
+}
+
+#[tokio::test]
+async fn invalid_commands() {
+    let (_proxy, control) = start_control(None).await;
+    assert_eq!(command(&control, "ban 1.2.3").await, "error: invalid ip '1.2.3'");
+    assert_eq!(command(&control, "ban 1.2.3.0/33").await, "error: invalid ip '1.2.3.0/33'");
+    assert_eq!(command(&control, "ban").await, "error: unknown command 'ban'");
+    assert_eq!(command(&control, "restart now").await, "error: unknown command 'restart now'");
+    assert_eq!(command(&control, "reload").await, "error: the config was not loaded from a file");
+}
+
+#[tokio::test]
+async fn several_commands_in_one_connection() {
+    let (_proxy, control) = start_control(None).await;
+    let mut stream = TcpStream::connect(&control).await.unwrap();
+    stream.write_all(b"ban 1.2.3.4\n\nstatus\n").await.unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok");
+    assert!(lines.next_line().await.unwrap().unwrap().ends_with("bans=1"));
+}
+
+#[tokio::test]
+async fn reload_replaces_config() {
+    let path = std::env::temp_dir().join(format!("mineginx-{}.yaml", uuid::Uuid::new_v4()));
+    let (proxy, control) = start_control(Some(path.clone())).await;
+    let mut config = proxy.state.config.get().as_ref().clone();
+    config.servers[0].server_names = vec!["new.example.com".to_string()];
+    fs::write(&path, serialize_config(&config, ConfigFormat::Yaml).unwrap()).unwrap();
+
+    assert_eq!(command(&control, "reload").await, "ok");
+    assert_eq!(proxy.state.config.get().servers[0].server_names, ["new.example.com"]);
+
+    fs::write(&path, "servers: [").unwrap();
+    assert!(command(&control, "reload").await.starts_with("error: failed to parse config file"));
+    config.servers[0].server_names.clear();
+    fs::write(&path, serialize_config(&config, ConfigFormat::Yaml).unwrap()).unwrap();
+    assert_eq!(command(&control, "reload").await, "error: server #0: server_names must not be empty");
+    // a broken config is not applied
+    assert_eq!(proxy.state.config.get().servers[0].server_names, ["new.example.com"]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn status_fetches_are_counted_apart_from_logins() {
+    let (proxy, control) = start_control(None).await;
+    let proxy_pass = proxy.upstream.local_addr().unwrap().to_string();
+    for _ in 0..2 {
+        let mut client = connect_with(&proxy.address, "1.2.3.4", 1).await;
+        let (mut backend, _) = proxy.upstream.accept().await.unwrap();
+        backend.write_all(b"status").await.unwrap();
+        let mut received = [0; 6];
+        client.read_exact(&mut received).await.unwrap();
+    }
+    // a status connection which the upstream doesn't answer is not a fetch
+    let client = connect_with(&proxy.address, "1.2.3.4", 1).await;
+    let (backend, _) = proxy.upstream.accept().await.unwrap();
+    drop((client, backend));
+    let _client = connect_from(&proxy.address, "1.2.3.4").await;
+    let _backend = proxy.upstream.accept().await.unwrap();
+
+    timeout(Duration::from_secs(1), async {
+        while proxy.state.stats.upstream(&proxy_pass) != (UpstreamStats { status_fetches: 2, logins: 1, online: 1 }) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+    let status = command(&control, "status").await;
+    assert!(status.ends_with(&format!(" status_fetches[{proxy_pass}]=2 logins[{proxy_pass}]=1")), "{status}");
+}
@@ -0,0 +1,54 @@
+use crate::{config::DomainMatching, domain::{matching_host, normalize_domain, replace_host, strip_forge_marker}};
+
+#[test]
+fn strip_forge_markers() {
+    assert_eq!(strip_forge_marker("mc.example.com\0FML\0"), "mc.example.com");
+    assert_eq!(strip_forge_marker("mc.example.com\0FML2\0"), "mc.example.com");
+    assert_eq!(strip_forge_marker("mc.example.com\0FML3\0"), "mc.example.com");
+}
+
+#[test]
+fn keep_domain_without_marker() {
+    assert_eq!(strip_forge_marker("mc.example.com"), "mc.example.com");
+    assert_eq!(strip_forge_marker("mc.example.com\0FML4\0"), "mc.example.com\0FML4\0");
+}
+
+#[test]
+fn matching_host_cuts_unknown_suffix() {
+    assert_eq!(matching_host("mc.example.com\0FML3\0"), "mc.example.com");
+    assert_eq!(matching_host("mc.example.com\0extra"), "mc.example.com");
+    assert_eq!(matching_host("mc.example.com"), "mc.example.com");
+}
+
+#[test]
+fn normalize_with_defaults() {
+    let matching = DomainMatching::default();
+    assert_eq!(normalize_domain("MC.Example.com\0FML3\0", &matching), "mc.example.com");
+    assert_eq!(normalize_domain("mc.example.com.", &matching), "mc.example.com.");
+}
+
+#[test]
+fn normalize_keeps_fml() {
+    let matching = DomainMatching { strip_fml: Some(false), ..Default::default() };
+    assert_eq!(normalize_domain("mc.example.com\0FML3\0", &matching), "mc.example.com\0fml3\0");
+}
+
+#[test]
+fn normalize_keeps_case() {
+    let matching = DomainMatching { case_insensitive: Some(false), ..Default::default() };
+    assert_eq!(normalize_domain("MC.Example.com\0FML3\0", &matching), "MC.Example.com");
+}
+
+#[test]
+fn normalize_strips_trailing_dot() {
+    let matching = DomainMatching { strip_trailing_dot: Some(true), ..Default::default() };
+    assert_eq!(normalize_domain("mc.example.com.\0FML3\0", &matching), "mc.example.com");
+    assert_eq!(normalize_domain("mc.example.com", &matching), "mc.example.com");
+}
+
+#[test]
+fn replace_host_keeps_suffix() {
+    assert_eq!(replace_host("mc.example.com", "backend.host.net"), "backend.host.net");
+    assert_eq!(replace_host("mc.example.com\0FML3\0", "backend.host.net"), "backend.host.net\0FML3\0");
+    assert_eq!(replace_host("mc.example.com\x001.2.3.4\0uuid", "backend.host.net"), "backend.host.net\x001.2.3.4\0uuid");
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, time::sleep};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    dump::{hex_lines, Dump},
+    tests::{harness::{handshake, Harness}, logs::{capture_logs, captured}}
+};
+
+#[test]
+fn hex_line_format() {
+    let lines = hex_lines(16, b"\x00\x01localhost with a tail");
+    assert_eq!(lines, [
+        "00000010  00 01 6c 6f 63 61 6c 68  6f 73 74 20 77 69 74 68  |..localhost with|",
+        "00000020  20 61 20 74 61 69 6c                              | a tail|"
+    ]);
+}
+
+#[test]
+fn dump_is_bounded() {
+    capture_logs();
+    let mut dump = Dump::new("bounded-dump".to_string(), 20);
+    dump.write(&[0x41; 16]);
+    dump.write(&[0x42; 16]);
+    dump.write(&[0x43; 16]);
+    assert_eq!(captured("bounded-dump"), [
+        "TRACE bounded-dump 00000000  41 41 41 41 41 41 41 41  41 41 41 41 41 41 41 41  |AAAAAAAAAAAAAAAA|",
+        "TRACE bounded-dump 00000010  42 42 42 42                                       |BBBB|"
+    ]);
+}
+
+#[tokio::test]
+async fn dump_captures_handshake() {
+    capture_logs();
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        name: Some("dump-test".to_string()),
+        server_names: vec!["localhost".to_string()],
+        debug_dump: Some(true),
+        ..Default::default()
+    }).await;
+
+    let handshake = handshake("localhost", 2);
+    let mut client = harness.connect(&handshake).await;
+    let mut backend = harness.accept().await;
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    backend.write_all(b"pong").await.unwrap();
+    let mut reply = [0; 4];
+    client.read_exact(&mut reply).await.unwrap();
+    // the reader task logs right after the bytes are written to the client
+    sleep(Duration::from_millis(50)).await;
+
+    let sent = captured("> dump-test");
+    assert_eq!(sent.len(), 2);
+    assert!(sent[0].ends_with(&hex_lines(0, &handshake)[0]));
+    assert!(sent[1].ends_with(&hex_lines(0, &handshake)[1]));
+    let received = captured("< dump-test");
+    assert_eq!(received.len(), 1);
+    assert!(received[0].ends_with("00000000  70 6f 6e 67                                       |pong|"));
+}
@@ -0,0 +1,136 @@
+use std::sync::atomic::Ordering;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    tests::harness::{assert_closed, handshake, read_handshake, Harness}
+};
+
+#[tokio::test]
+async fn handshake_is_reserialized_for_upstream() {
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["play.example.com".to_string()],
+        upstream_hostname: Some("survival.internal".to_string()),
+        ..Default::default()
+    }).await;
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+
+    let _client = harness.connect(&[handshake("play.example.com", 2).as_slice(), &login].concat()).await;
+    let mut backend = harness.accept().await;
+    let received = read_handshake(&mut backend).await;
+    assert_eq!(received.protocol_version, 765);
+    assert_eq!(received.domain, "survival.internal");
+    assert_eq!(received.server_port, 25565);
+    assert_eq!(received.next_state, 2);
+    let mut received = [0; 6];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, login);
+}
+
+/// A Forge handshake with the length in two bytes instead of one and null bytes after the last field
+fn forge_handshake() -> Vec<u8> {
+    let domain = b"play.example.com\0FML3\0";
+    let data = [&[0x00, 0xFD, 0x05, domain.len() as u8], domain.as_slice(), &[0x63, 0xDD, 0x02, 0x00, 0x00]].concat();
+    [&[data.len() as u8 | 0x80, 0x00], data.as_slice()].concat()
+}
+
+#[tokio::test]
+async fn preserved_handshake_is_byte_identical() {
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["play.example.com".to_string()],
+        preserve_handshake: Some(true),
+        ..Default::default()
+    }).await;
+    let original = forge_handshake();
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+
+    let _client = harness.connect(&[original.as_slice(), &login].concat()).await;
+    let mut backend = harness.accept().await;
+    let mut received = vec![0; original.len() + login.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, [original.as_slice(), &login].concat());
+}
+
+#[tokio::test]
+async fn handshake_is_encoded_again_by_default() {
+    let harness = Harness::start(&["play.example.com"]).await;
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+
+    let _client = harness.connect(&[forge_handshake().as_slice(), &login].concat()).await;
+    let mut backend = harness.accept().await;
+    let received = read_handshake(&mut backend).await;
+    assert_eq!(received.domain, "play.example.com\0FML3\0");
+    assert_eq!(received.next_state, 2);
+    // the null bytes after the fields are dropped
+    let mut received = [0; 6];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, login);
+}
+
+#[tokio::test]
+async fn forwarding_works_both_ways() {
+    let harness = Harness::start(&["localhost"]).await;
+    let mut client = harness.connect(&handshake("localhost", 2)).await;
+    let mut backend = harness.accept().await;
+    assert_eq!(read_handshake(&mut backend).await.domain, "localhost");
+
+    for round in 0..3_u8 {
+        client.write_all(&[round; 100]).await.unwrap();
+        let mut from_client = [0; 100];
+        backend.read_exact(&mut from_client).await.unwrap();
+        assert_eq!(from_client, [round; 100]);
+
+        backend.write_all(&[round + 10; 1000]).await.unwrap();
+        let mut from_backend = [0; 1000];
+        client.read_exact(&mut from_backend).await.unwrap();
+        assert_eq!(from_backend, [round + 10; 1000]);
+    }
+
+    drop(client);
+    assert_closed(&mut backend).await;
+}
+
+#[tokio::test]
+async fn upstream_close_reaches_client() {
+    let harness = Harness::start(&["localhost"]).await;
+    let mut client = harness.connect(&handshake("localhost", 2)).await;
+    drop(harness.accept().await);
+    assert_closed(&mut client).await;
+}
+
+#[tokio::test]
+async fn unknown_domain_never_reaches_upstream() {
+    let harness = Harness::start(&["localhost"]).await;
+    let mut client = harness.connect(&handshake("unknown.example.com", 2)).await;
+    assert_closed(&mut client).await;
+    assert_eq!(harness.state.stats.misses.load(Ordering::Relaxed), 1);
+    let mut known = harness.connect(&handshake("localhost", 2)).await;
+    // the first connection the upstream gets is the one of the known domain
+    let mut backend = harness.accept().await;
+    assert_eq!(read_handshake(&mut backend).await.domain, "localhost");
+    backend.write_all(b"ok").await.unwrap();
+    let mut reply = [0; 2];
+    known.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"ok");
+}
+
+#[tokio::test]
+async fn second_handshake_is_forwarded_unparsed() {
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["play.example.com".to_string()],
+        upstream_hostname: Some("survival.internal".to_string()),
+        ..Default::default()
+    }).await;
+    let second = handshake("unknown.example.com", 1);
+
+    let _client = harness.connect(&[handshake("play.example.com", 2), second.clone()].concat()).await;
+    let mut backend = harness.accept().await;
+    assert_eq!(read_handshake(&mut backend).await.domain, "survival.internal");
+    // neither rewritten nor routed, these are just bytes of the session
+    let mut received = vec![0; second.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, second);
+    assert_eq!(harness.state.stats.misses.load(Ordering::Relaxed), 0);
+    assert_eq!(harness.state.stats.handshake_failures.load(Ordering::Relaxed), 0);
+}
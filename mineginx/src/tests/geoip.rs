@@ -0,0 +1,237 @@
+use std::{fs, net::{IpAddr, Ipv4Addr}, path::PathBuf, time::Duration};
+
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::{
+    config::{validate, MinecraftServerDescription, MineginxConfig},
+    geoip::GeoIp,
+    tests::harness::{assert_closed, handshake, Harness},
+    Proxy
+};
+
+/// Values of the MaxMind DB data section, only the types the fixtures need
+enum Value {
+    String(&'static str),
+    Uint16(u16),
+    Uint32(u32),
+    Map(Vec<(&'static str, Value)>),
+    Array(Vec<Value>)
+}
+
+impl Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::String(x) => {
+                out.push(2 << 5 | x.len() as u8);
+                out.extend(x.as_bytes());
+            },
+            Value::Uint16(x) => {
+                out.push(5 << 5 | 2);
+                out.extend(x.to_be_bytes());
+            },
+            Value::Uint32(x) => {
+                out.push(6 << 5 | 4);
+                out.extend(x.to_be_bytes());
+            },
+            Value::Map(entries) => {
+                out.push(7 << 5 | entries.len() as u8);
+                for (key, value) in entries {
+                    Value::String(key).encode(out);
+                    value.encode(out);
+                }
+            },
+            Value::Array(items) => {
+                // an extended type, 11 - 7
+                out.push(items.len() as u8);
+                out.push(4);
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// An ipv4 database with 24 bit records where each of `networks` has its own record, like `("1.2.3.0", 24, value)`
+/// See https://maxmind.github.io/MaxMind-DB/
+fn mmdb(database_type: &'static str, networks: Vec<(Ipv4Addr, u32, Value)>) -> Vec<u8> {
+    enum Record {
+        Empty,
+        Node(usize),
+        Data(usize)
+    }
+    let mut nodes: Vec<[Record; 2]> = vec![[Record::Empty, Record::Empty]];
+    let mut data = vec![];
+    for (network, prefix_len, value) in networks {
+        let bits = u32::from(network);
+        let mut node = 0;
+        for i in 0..prefix_len {
+            let bit = (bits >> (31 - i) & 1) as usize;
+            if i == prefix_len - 1 {
+                nodes[node][bit] = Record::Data(data.len());
+                break;
+            }
+            node = match nodes[node][bit] {
+                Record::Node(x) => x,
+                _ => {
+                    nodes.push([Record::Empty, Record::Empty]);
+                    nodes[node][bit] = Record::Node(nodes.len() - 1);
+                    nodes.len() - 1
+                }
+            };
+        }
+        value.encode(&mut data);
+    }
+    let node_count = nodes.len();
+    let mut database = vec![];
+    for records in &nodes {
+        for record in records {
+            let value = match record {
+                Record::Empty => node_count,
+                Record::Node(x) => *x,
+                Record::Data(offset) => node_count + 16 + offset
+            };
+            database.extend(&(value as u32).to_be_bytes()[1..]);
+        }
+    }
+    database.extend([0; 16]);
+    database.extend(data);
+    database.extend(b"\xAB\xCD\xEFMaxMind.com");
+    Value::Map(vec![
+        ("binary_format_major_version", Value::Uint16(2)),
+        ("binary_format_minor_version", Value::Uint16(0)),
+        ("build_epoch", Value::Uint32(0)),
+        ("database_type", Value::String(database_type)),
+        ("description", Value::Map(vec![])),
+        ("ip_version", Value::Uint16(4)),
+        ("languages", Value::Array(vec![Value::String("en")])),
+        ("node_count", Value::Uint32(node_count as u32)),
+        ("record_size", Value::Uint16(24))
+    ]).encode(&mut database);
+    database
+}
+
+fn country(iso_code: &'static str) -> Value {
+    Value::Map(vec![("country", Value::Map(vec![("iso_code", Value::String(iso_code))]))])
+}
+
+/// `1.2.3.0/24` is in `XX` and AS64500, `5.6.0.0/16` is in `YY` and AS64501
+fn write_databases() -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("mineginx-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let country_path = dir.join("country.mmdb");
+    fs::write(&country_path, mmdb("GeoLite2-Country", vec![
+        (Ipv4Addr::new(1, 2, 3, 0), 24, country("XX")),
+        (Ipv4Addr::new(5, 6, 0, 0), 16, country("YY"))
+    ])).unwrap();
+    let asn_path = dir.join("asn.mmdb");
+    fs::write(&asn_path, mmdb("GeoLite2-ASN", vec![
+        (Ipv4Addr::new(1, 2, 3, 0), 24, Value::Map(vec![("autonomous_system_number", Value::Uint32(64500))])),
+        (Ipv4Addr::new(5, 6, 0, 0), 16, Value::Map(vec![("autonomous_system_number", Value::Uint32(64501))]))
+    ])).unwrap();
+    (country_path, asn_path)
+}
+
+fn ip(x: &str) -> IpAddr {
+    x.parse().unwrap()
+}
+
+#[test]
+fn lookup_in_fixture() {
+    let (country_path, asn_path) = write_databases();
+    let geoip = GeoIp::open(Some(&country_path), Some(&asn_path)).unwrap();
+    assert_eq!(geoip.country(ip("1.2.3.4")), Some("XX".to_string()));
+    assert_eq!(geoip.country(ip("5.6.7.8")), Some("YY".to_string()));
+    assert_eq!(geoip.country(ip("9.9.9.9")), None);
+    assert_eq!(geoip.asn(ip("1.2.3.4")), Some(64500));
+    assert_eq!(geoip.asn(ip("9.9.9.9")), None);
+}
+
+#[test]
+fn blocks_listed_countries_and_asns() {
+    let (country_path, asn_path) = write_databases();
+    let geoip = GeoIp::open(Some(&country_path), Some(&asn_path)).unwrap();
+    let server = MinecraftServerDescription {
+        block_countries: Some(vec!["xx".to_string()]),
+        block_asns: Some(vec![64501]),
+        ..Default::default()
+    };
+    assert_eq!(geoip.blocks(&server, ip("1.2.3.4")), Some("country XX".to_string()));
+    assert_eq!(geoip.blocks(&server, ip("5.6.7.8")), Some("AS64501".to_string()));
+    assert_eq!(geoip.blocks(&server, ip("9.9.9.9")), None);
+    assert_eq!(geoip.blocks(&MinecraftServerDescription::default(), ip("1.2.3.4")), None);
+}
+
+#[test]
+fn without_databases_nothing_is_blocked() {
+    let server = MinecraftServerDescription {
+        block_countries: Some(vec!["XX".to_string()]),
+        ..Default::default()
+    };
+    assert_eq!(GeoIp::default().blocks(&server, ip("1.2.3.4")), None);
+}
+
+#[tokio::test]
+async fn block_lists_need_databases() {
+    let mut config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "0.0.0.0:25565".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: "127.0.0.1:7878".to_string(),
+            block_countries: Some(vec!["XX".to_string()]),
+            block_asns: Some(vec![64500]),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    assert_eq!(validate(&config).await, vec![
+        "server #0: block_countries needs geoip_country_database",
+        "server #0: block_asns needs geoip_asn_database"
+    ]);
+    config.geoip_country_database = Some("country.mmdb".to_string());
+    config.geoip_asn_database = Some("asn.mmdb".to_string());
+    assert!(validate(&config).await.is_empty());
+}
+
+#[tokio::test]
+async fn missing_database_fails_start() {
+    let config = MineginxConfig {
+        geoip_country_database: Some("/nonexistent/country.mmdb".to_string()),
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: "127.0.0.1:7878".to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    assert!(Proxy::new(config).bind().await.is_err());
+}
+
+/// Clients come with PROXY headers, so they can have any ip
+async fn connect_from(harness: &Harness, ip: &str) -> TcpStream {
+    let header = format!("PROXY TCP4 {ip} 10.0.0.1 51000 25565\r\n");
+    harness.connect(&[header.as_bytes(), &handshake("localhost", 2)].concat()).await
+}
+
+#[tokio::test]
+async fn blocked_country_is_rejected() {
+    let (country_path, _) = write_databases();
+    let config = MineginxConfig {
+        geoip_country_database: Some(country_path.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    let harness = Harness::start_with(config, MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        accept_proxy_protocol: Some(true),
+        block_countries: Some(vec!["XX".to_string()]),
+        ..Default::default()
+    }).await;
+
+    let mut blocked = connect_from(&harness, "1.2.3.4").await;
+    assert_closed(&mut blocked).await;
+    assert!(timeout(Duration::from_millis(200), harness.upstream.accept()).await.is_err());
+
+    let _allowed = connect_from(&harness, "5.6.7.8").await;
+    harness.accept().await;
+}
@@ -0,0 +1,152 @@
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use minecraft::{packets::{HandshakeC2SPacket, MinecraftPacket}, serialization::MinecraftStream};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, sync::oneshot, task::JoinHandle, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    state::State,
+    Proxy, Shutdown
+};
+
+/// A proxy run by `start_proxy`, it gets the shutdown signal when dropped
+pub struct RunningProxy {
+    /// Where the clients connect, the first `listen` address
+    pub address: String,
+    pub state: Arc<State>,
+    stop: Option<oneshot::Sender<()>>,
+    running: JoinHandle<Shutdown>
+}
+
+impl RunningProxy {
+    /// Sends the shutdown signal, see `stopped` for the outcome
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            _ = stop.send(());
+        }
+    }
+
+    /// Waits until the proxy stops after `stop`
+    pub async fn stopped(&mut self) -> Shutdown {
+        (&mut self.running).await.unwrap()
+    }
+
+    /// A client which already sent `data`, usually starting with a `handshake`
+    pub async fn connect(&self, data: &[u8]) -> TcpStream {
+        connect(&self.address, data).await
+    }
+}
+
+impl Drop for RunningProxy {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Binds and runs `proxy`, its servers usually listen on `127.0.0.1:0`
+pub async fn start_proxy(proxy: Proxy) -> RunningProxy {
+    let proxy = proxy.bind().await.unwrap();
+    let address = proxy.local_addrs()[0].to_string();
+    let state = proxy.state().clone();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let running = tokio::spawn(proxy.run(async { _ = stopped.await; }));
+    RunningProxy { address, state, stop: Some(stop), running }
+}
+
+/// A running proxy with one server, a fake upstream it passes clients to and a way to connect fake clients  
+/// The proxy stops when the harness is dropped
+pub struct Harness {
+    /// Where the clients connect
+    pub address: String,
+    /// The upstream of the server, nothing is accepted unless a test does it
+    pub upstream: TcpListener,
+    pub state: Arc<State>,
+    _proxy: RunningProxy
+}
+
+impl Harness {
+    /// Serves `server_names` with the defaults of the config
+    pub async fn start(server_names: &[&str]) -> Harness {
+        Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+            server_names: server_names.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }).await
+    }
+
+    /// Serves `server` as the only server of `config`, its `listen` and an empty `proxy_pass` are filled in
+    pub async fn start_with(config: MineginxConfig, server: MinecraftServerDescription) -> Harness {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_pass = match server.proxy_pass.is_empty() && server.proxy_pass_pool.is_none() {
+            true => upstream.local_addr().unwrap().to_string(),
+            false => server.proxy_pass.clone()
+        };
+        let config = MineginxConfig {
+            servers: vec![MinecraftServerDescription {
+                listen: "127.0.0.1:0".into(),
+                proxy_pass,
+                ..server
+            }],
+            ..config
+        };
+        let proxy = start_proxy(Proxy::new(config)).await;
+        Harness { address: proxy.address.clone(), upstream, state: proxy.state.clone(), _proxy: proxy }
+    }
+
+    /// A client which already sent `data`, usually starting with a `handshake`
+    pub async fn connect(&self, data: &[u8]) -> TcpStream {
+        connect(&self.address, data).await
+    }
+
+    /// The next connection the proxy opens to the upstream
+    pub async fn accept(&self) -> TcpStream {
+        let (backend, _) = timeout(Duration::from_secs(1), self.upstream.accept()).await.unwrap().unwrap();
+        backend
+    }
+}
+
+/// A client of `address` which already sent `data`
+pub async fn connect(address: &str, data: &[u8]) -> TcpStream {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(data).await.unwrap();
+    client
+}
+
+pub fn handshake(domain: &str, next_state: i32) -> Vec<u8> {
+    versioned_handshake(domain, 765, next_state)
+}
+
+pub fn versioned_handshake(domain: &str, protocol_version: i32, next_state: i32) -> Vec<u8> {
+    MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+        protocol_version,
+        domain: domain.to_string(),
+        server_port: 25565,
+        next_state
+    }).unwrap()
+}
+
+/// Parses the handshake the upstream got, the stream is left right after it
+pub async fn read_handshake(backend: &mut TcpStream) -> HandshakeC2SPacket {
+    // the length is read byte by byte, so nothing after the handshake is taken
+    let mut packet = vec![];
+    let mut length = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = backend.read_u8().await.unwrap();
+        packet.push(byte);
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut data = vec![0; length];
+    backend.read_exact(&mut data).await.unwrap();
+    packet.extend(data);
+    let size = packet.len();
+    MinecraftStream::new(Cursor::new(packet), size).read_packet::<HandshakeC2SPacket>().await.unwrap()
+}
+
+/// Waits until the other side closes `stream`
+pub async fn assert_closed(stream: &mut TcpStream) {
+    let mut buf = [0; 16];
+    let read = timeout(Duration::from_secs(1), stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
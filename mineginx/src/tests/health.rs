@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+
+use crate::{config::MineginxConfig, health::serve_health, state::State};
+
+async fn get(address: &str) -> String {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn unavailable_after_shutdown_begins() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let state = Arc::new(State::new(Arc::new(MineginxConfig::default())));
+    let health = tokio::spawn(serve_health(listener, state.clone()));
+
+    assert!(get(&address).await.starts_with("HTTP/1.1 200 OK\r\n"));
+    state.begin_shutdown();
+    assert!(get(&address).await.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    health.abort();
+}
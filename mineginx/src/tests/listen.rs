@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use minecraft::packets::{HandshakeC2SPacket, MinecraftPacket};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream, UnixStream}};
+
+use crate::{
+    config::{Listen, MinecraftServerDescription, MineginxConfig},
+    handle_address,
+    listener::{bind_address, bind_listeners, Listener},
+    state::State,
+    tests::{harness::handshake, upstream::free_address},
+    Proxy
+};
+
+#[tokio::test]
+async fn bind_free_address() {
+    assert!(bind_address("127.0.0.1:0").await.is_some());
+}
+
+#[tokio::test]
+async fn bind_address_in_use() {
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = first.local_addr().unwrap().to_string();
+    assert!(bind_address(&address).await.is_none());
+}
+
+#[tokio::test]
+async fn bind_and_connect_ipv6() {
+    let listener = tcp(bind_address("[::1]:0").await.unwrap());
+    let address = format!("[::1]:{}", listener.local_addr().unwrap().port());
+    let (client, accepted) = tokio::join!(TcpStream::connect(&address), listener.accept());
+    assert!(client.unwrap().peer_addr().unwrap().is_ipv6());
+    assert!(accepted.unwrap().1.is_ipv6());
+}
+
+#[tokio::test]
+async fn one_listener_per_address() {
+    let first = free_address().await;
+    let second = free_address().await;
+    let config = MineginxConfig {
+        servers: vec![
+            MinecraftServerDescription {
+                listen: Listen::Multiple(vec![first.clone(), second.clone()]),
+                server_names: vec!["localhost".to_string()],
+                proxy_pass: "127.0.0.1:7878".to_string(),
+                ..Default::default()
+            },
+            MinecraftServerDescription {
+                listen: Listen::Single(second.clone()),
+                server_names: vec!["example.com".to_string()],
+                proxy_pass: "127.0.0.1:7879".to_string(),
+                ..Default::default()
+            }
+        ],
+        ..Default::default()
+    };
+    let listeners = bind_listeners(&config).await;
+    assert_eq!(listeners.len(), 2);
+    assert_eq!(listeners[0].0, first);
+    assert_eq!(listeners[1].0, second);
+    let addresses: Vec<String> = listeners.into_iter().map(|(_, x)| tcp(x).local_addr().unwrap().to_string()).collect();
+    assert_eq!(addresses, [first, second]);
+}
+
+#[tokio::test]
+async fn skip_address_in_use() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let taken_address = taken.local_addr().unwrap().to_string();
+    let free = free_address().await;
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: Listen::Multiple(vec![taken_address, free.clone()]),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: "127.0.0.1:7878".to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let mut listeners = bind_listeners(&config).await;
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].0, free);
+    let listener = tcp(listeners.remove(0).1);
+    let (client, accepted) = tokio::join!(TcpStream::connect(&free), listener.accept());
+    assert!(client.is_ok());
+    assert!(accepted.is_ok());
+    let (_client, accepted) = tokio::join!(TcpStream::connect(taken.local_addr().unwrap()), taken.accept());
+    assert!(accepted.is_ok());
+}
+
+#[tokio::test]
+async fn handshake_over_unix_socket() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let path = std::env::temp_dir().join(format!("mineginx-{}.sock", uuid::Uuid::new_v4()));
+    let listen = format!("unix:{}", path.display());
+    let config = Arc::new(MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: listen.as_str().into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: upstream.local_addr().unwrap().to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    let listener = bind_address(&listen).await.unwrap();
+    let proxy = tokio::spawn(handle_address(listener, Arc::new(State::new(config)), listen));
+
+    let handshake = handshake("localhost", 2);
+    let mut client = UnixStream::connect(&path).await.unwrap();
+    client.write_all(&handshake).await.unwrap();
+
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+
+    proxy.abort();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn rebind_stale_unix_socket() {
+    let path = std::env::temp_dir().join(format!("mineginx-{}.sock", uuid::Uuid::new_v4()));
+    let listen = format!("unix:{}", path.display());
+    drop(bind_address(&listen).await.unwrap());
+    assert!(path.exists());
+    assert!(bind_address(&listen).await.is_some());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn port_zero_gets_free_port() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: upstream.local_addr().unwrap().to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let proxy = Proxy::new(config).bind().await.unwrap();
+    let addresses = proxy.local_addrs();
+    assert_eq!(addresses.len(), 1);
+    assert!(addresses[0].ip().is_loopback());
+    assert_ne!(addresses[0].port(), 0);
+    tokio::spawn(proxy.run(std::future::pending()));
+
+    let handshake = MinecraftPacket::make_raw(0, &HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "localhost".to_string(),
+        server_port: addresses[0].port(),
+        next_state: 2
+    }).unwrap();
+    let mut client = TcpStream::connect(addresses[0]).await.unwrap();
+    client.write_all(&handshake).await.unwrap();
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn unix_listener_has_no_local_addr() {
+    let path = std::env::temp_dir().join(format!("mineginx-{}.sock", uuid::Uuid::new_v4()));
+    let listener = bind_address(&format!("unix:{}", path.display())).await.unwrap();
+    assert_eq!(listener.local_addr(), None);
+    assert!(bind_address("127.0.0.1:0").await.unwrap().local_addr().is_some());
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn tcp(listener: Listener) -> TcpListener {
+    match listener {
+        Listener::Tcp(x) => x,
+        _ => panic!("expected a tcp listener")
+    }
+}
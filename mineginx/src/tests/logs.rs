@@ -0,0 +1,33 @@
+use std::sync::{Mutex, Once};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+static RECORDS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        RECORDS.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Keeps every log record in memory, tests share the logger, so they should look for their own records
+pub fn capture_logs() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+/// Captured records which contain `pattern`
+pub fn captured(pattern: &str) -> Vec<String> {
+    RECORDS.lock().unwrap().iter().filter(|x| x.contains(pattern)).cloned().collect()
+}
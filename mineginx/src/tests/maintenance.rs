@@ -0,0 +1,89 @@
+use std::borrow::BorrowMut;
+
+use minecraft::{
+    packets::{LoginDisconnectS2CPacket, PingRequestC2SPacket, PongResponseS2CPacket, StatusRequestC2SPacket, StatusResponseS2CPacket},
+    serialization::MinecraftStream
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::{Maintenance, MinecraftServerDescription, MineginxConfig},
+    tests::harness::{assert_closed, handshake, Harness}
+};
+
+/// `proxy_pass` points to a closed port, so any reply comes from the proxy itself
+async fn start_maintenance() -> Harness {
+    start_maintenance_with(Maintenance {
+        kick_message: "back soon".to_string(),
+        motd: "under maintenance".to_string(),
+        show_version: None
+    }).await
+}
+
+async fn start_maintenance_with(maintenance: Maintenance) -> Harness {
+    Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass: "127.0.0.1:1".to_string(),
+        maintenance: Some(maintenance),
+        ..Default::default()
+    }).await
+}
+
+#[tokio::test]
+async fn login_is_kicked() {
+    let harness = start_maintenance().await;
+    let mut client = harness.connect(&handshake("localhost", 2)).await;
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 0);
+    let disconnect = minecraft.read_data::<LoginDisconnectS2CPacket>(signature).await.unwrap();
+    assert_eq!(disconnect.reason, r#"{"text":"back soon"}"#);
+}
+
+#[tokio::test]
+async fn status_shows_motd() {
+    let harness = start_maintenance().await;
+    let mut client = harness.connect(&handshake("localhost", 1)).await;
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    minecraft.write_packet_with_id(0, &StatusRequestC2SPacket {}).await.unwrap();
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 0);
+    let status = minecraft.read_data::<StatusResponseS2CPacket>(signature).await.unwrap();
+    let json: serde_json::Value = serde_json::from_str(&status.json_response).unwrap();
+    assert_eq!(json["description"]["text"], "under maintenance");
+    assert_eq!(json["version"]["protocol"], 765);
+    assert!(json.get("mineginx").is_none());
+
+    minecraft.write_packet_with_id(1, &PingRequestC2SPacket { payload: 42 }).await.unwrap();
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 1);
+    let pong = minecraft.read_data::<PongResponseS2CPacket>(signature).await.unwrap();
+    assert_eq!(pong.payload, 42);
+}
+
+#[tokio::test]
+async fn second_handshake_is_not_a_status_request() {
+    let harness = start_maintenance().await;
+    let mut client = harness.connect(&handshake("localhost", 1)).await;
+    client.write_all(&handshake("localhost", 1)).await.unwrap();
+    // closed without a status response
+    assert_closed(&mut client).await;
+}
+
+#[tokio::test]
+async fn status_shows_version() {
+    let harness = start_maintenance_with(Maintenance {
+        motd: "under maintenance".to_string(),
+        show_version: Some(true),
+        ..Default::default()
+    }).await;
+    let mut client = harness.connect(&handshake("localhost", 1)).await;
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    minecraft.write_packet_with_id(0, &StatusRequestC2SPacket {}).await.unwrap();
+    let signature = minecraft.read_signature().await.unwrap();
+    let status = minecraft.read_data::<StatusResponseS2CPacket>(signature).await.unwrap();
+    let json: serde_json::Value = serde_json::from_str(&status.json_response).unwrap();
+    assert_eq!(json["mineginx"]["version"], env!("MINEGINX_VERSION"));
+    assert_eq!(json["mineginx"]["hash"], env!("MINEGINX_HASH"));
+    assert_eq!(json["description"]["text"], "under maintenance");
+}
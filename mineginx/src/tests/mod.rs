@@ -0,0 +1,37 @@
+
+mod access_log;
+mod app;
+mod bans;
+mod cli;
+mod config;
+mod connection_log;
+mod control;
+mod domain;
+mod dump;
+mod end_to_end;
+mod geoip;
+mod harness;
+mod health;
+mod listen;
+mod logs;
+mod maintenance;
+mod observer;
+#[cfg(unix)]
+mod on_demand;
+mod pool;
+mod probe;
+mod protocol;
+mod proxy;
+mod proxy_protocol;
+mod resolver;
+mod router;
+mod routing;
+mod scanner;
+mod shutdown;
+mod srv;
+#[cfg(unix)]
+mod stats;
+mod status_cache;
+mod stream;
+mod throttle;
+mod upstream;
@@ -0,0 +1,106 @@
+use std::{net::SocketAddr, sync::{Arc, Mutex}, time::Duration};
+
+use minecraft::packets::HandshakeC2SPacket;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    observer::ConnectionObserver,
+    router::UpstreamTarget,
+    tests::harness::{handshake, start_proxy, RunningProxy},
+    Proxy
+};
+
+/// Writes down each call in order
+#[derive(Default)]
+struct RecordingObserver {
+    calls: Mutex<Vec<String>>
+}
+
+impl RecordingObserver {
+    fn record(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl ConnectionObserver for RecordingObserver {
+    fn on_handshake(&self, handshake: &HandshakeC2SPacket, peer: Option<SocketAddr>) {
+        self.record(format!("handshake {} from {}", handshake.domain, peer.unwrap().ip()));
+    }
+
+    fn on_upstream_selected(&self, _handshake: &HandshakeC2SPacket, upstream: &UpstreamTarget) {
+        self.record(format!("selected {}", upstream.server_names[0]));
+    }
+
+    fn on_connected(&self, handshake: &HandshakeC2SPacket, upstream: &UpstreamTarget) {
+        self.record(format!("connected {} to {}", handshake.domain, upstream.server_names[0]));
+    }
+
+    fn on_closed(&self, upstream: &UpstreamTarget, bytes_in: u64, bytes_out: u64) {
+        self.record(format!("closed {} in={bytes_in} out={bytes_out}", upstream.server_names[0]));
+    }
+}
+
+async fn start(upstream: &TcpListener, observer: Arc<RecordingObserver>) -> RunningProxy {
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["mc.example.com".to_string()],
+            proxy_pass: upstream.local_addr().unwrap().to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    start_proxy(Proxy::new(config).with_observer(observer)).await
+}
+
+async fn wait_for_calls(observer: &RecordingObserver, count: usize) {
+    timeout(Duration::from_secs(1), async {
+        while observer.calls().len() < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+}
+
+#[tokio::test]
+async fn hooks_follow_session() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let observer = Arc::new(RecordingObserver::default());
+    let proxy = start(&upstream, observer.clone()).await;
+    let handshake = handshake("mc.example.com", 2);
+
+    let mut client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    backend.write_all(b"hello").await.unwrap();
+    let mut hello = [0; 5];
+    client.read_exact(&mut hello).await.unwrap();
+    wait_for_calls(&observer, 3).await;
+    assert_eq!(observer.calls(), [
+        "handshake mc.example.com from 127.0.0.1",
+        "selected mc.example.com",
+        "connected mc.example.com to mc.example.com"
+    ]);
+
+    drop(client);
+    wait_for_calls(&observer, 4).await;
+    assert_eq!(observer.calls()[3], format!("closed mc.example.com in={} out=5", handshake.len()));
+}
+
+#[tokio::test]
+async fn unknown_domain_is_not_connected() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let observer = Arc::new(RecordingObserver::default());
+    let proxy = start(&upstream, observer.clone()).await;
+
+    let mut client = proxy.connect(&handshake("unknown.example.com", 2)).await;
+    // the proxy closes the connection of a client without an upstream
+    let mut rest = vec![];
+    client.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(observer.calls(), ["handshake unknown.example.com from 127.0.0.1"]);
+}
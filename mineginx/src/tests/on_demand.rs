@@ -0,0 +1,233 @@
+use std::{borrow::BorrowMut, fs, time::{Duration, Instant}};
+
+use minecraft::{packets::{LoginDisconnectS2CPacket, LoginPluginRequestS2CPacket}, serialization::{MinecraftStream, ReadingError}};
+use tokio::{io::{duplex, AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::{sleep, timeout}};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig, OnDemand},
+    on_demand::{hold_client, hold_timeout, Launcher, HOLD_CHANNEL},
+    tests::{harness::{handshake, Harness}, upstream::free_address}
+};
+
+fn on_demand_server(proxy_pass: String, on_demand: OnDemand) -> MinecraftServerDescription {
+    MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass,
+        on_demand: Some(on_demand),
+        ..Default::default()
+    }
+}
+
+/// Handshake and Login Start, as a client joining the server sends them
+fn join() -> Vec<u8> {
+    let data = [&[5][..], b"Steve", &[0; 16]].concat();
+    [handshake("localhost", 2), [&[data.len() as u8 + 1, 0][..], &data].concat()].concat()
+}
+
+fn plugin_response(message_id: u8) -> Vec<u8> {
+    // message id and `successful` set to false, as the client doesn't know the channel
+    vec![3, 2, message_id, 0]
+}
+
+/// Reads the next Login Plugin Request and answers it like the vanilla client
+async fn answer_plugin_request<S>(client: &mut S) -> i32 where S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin {
+    let mut minecraft = MinecraftStream::new(&mut *client, 64);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 4);
+    let request = minecraft.read_data::<LoginPluginRequestS2CPacket>(signature).await.unwrap();
+    assert_eq!(request.channel, HOLD_CHANNEL);
+    assert!(minecraft.take_buffer().is_empty());
+    client.write_all(&plugin_response(request.message_id as u8)).await.unwrap();
+    request.message_id
+}
+
+#[tokio::test]
+async fn player_waits_for_started_upstream() {
+    let marker = std::env::temp_dir().join(format!("mineginx-on-demand-{}", uuid::Uuid::new_v4()));
+    let proxy_pass = free_address().await;
+    let proxy = Harness::start_with(MineginxConfig::default(), on_demand_server(proxy_pass.clone(), OnDemand {
+        command: Some(format!("echo started > '{}'", marker.display())),
+        start_timeout_ms: Some(5000),
+        ..Default::default()
+    })).await;
+    let handshake = join();
+
+    let mut client = proxy.connect(&handshake).await;
+    assert_eq!(answer_plugin_request(&mut client).await, 0);
+    // the backend comes up a bit after the command is run
+    let started = Instant::now();
+    while !marker.exists() {
+        assert!(started.elapsed() < Duration::from_secs(2));
+        sleep(Duration::from_millis(10)).await;
+    }
+    sleep(Duration::from_millis(300)).await;
+    let upstream = TcpListener::bind(&proxy_pass).await.unwrap();
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+    fs::remove_file(marker).unwrap();
+}
+
+#[tokio::test]
+async fn held_player_reaches_upstream_started_elsewhere() {
+    let proxy_pass = free_address().await;
+    let proxy = Harness::start_with(MineginxConfig::default(), on_demand_server(proxy_pass.clone(), OnDemand {
+        start_timeout_ms: Some(5000),
+        poll_interval_ms: Some(50),
+        ..Default::default()
+    })).await;
+    let handshake = join();
+
+    let mut client = proxy.connect(&handshake).await;
+    answer_plugin_request(&mut client).await;
+    // nothing is run, the backend is brought up by someone else a bit later
+    sleep(Duration::from_millis(400)).await;
+    let upstream = TcpListener::bind(&proxy_pass).await.unwrap();
+    let started = Instant::now();
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+    backend.write_all(b"welcome").await.unwrap();
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"welcome");
+}
+
+#[tokio::test]
+async fn player_is_kicked_when_start_times_out() {
+    let proxy = Harness::start_with(MineginxConfig::default(), on_demand_server(free_address().await, OnDemand {
+        command: Some("true".to_string()),
+        start_timeout_ms: Some(300),
+        starting_message: Some("Starting, come back soon".to_string()),
+        ..Default::default()
+    })).await;
+
+    let mut client = proxy.connect(&join()).await;
+    let started = Instant::now();
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    let mut signature = minecraft.read_signature().await.unwrap();
+    // the kick may follow a Login Plugin Request which is sent while the client waits
+    if signature.packet_id == 4 {
+        minecraft.read_raw_data(signature).await.unwrap();
+        signature = minecraft.read_signature().await.unwrap();
+    }
+    let disconnect = minecraft.read_data::<LoginDisconnectS2CPacket>(signature).await.unwrap();
+    assert_eq!(disconnect.reason, r#"{"text":"Starting, come back soon"}"#);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn leaving_player_stops_polling() {
+    let proxy_pass = free_address().await;
+    let proxy = Harness::start_with(MineginxConfig::default(), on_demand_server(proxy_pass.clone(), OnDemand {
+        start_timeout_ms: Some(5000),
+        poll_interval_ms: Some(50),
+        ..Default::default()
+    })).await;
+
+    let mut client = proxy.connect(&join()).await;
+    answer_plugin_request(&mut client).await;
+    drop(client);
+    sleep(Duration::from_millis(100)).await;
+    let upstream = TcpListener::bind(&proxy_pass).await.unwrap();
+    assert!(timeout(Duration::from_millis(300), upstream.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn held_client_gets_keep_alives() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_pass = upstream.local_addr().unwrap();
+    let (mut proxy_side, mut client) = duplex(1024);
+    let client = tokio::spawn(async move {
+        for expected in 0..3 {
+            assert_eq!(answer_plugin_request(&mut client).await, expected);
+        }
+        client
+    });
+    let ready = async {
+        sleep(Duration::from_millis(250)).await;
+        TcpStream::connect(proxy_pass).await
+    };
+    let mut minecraft = MinecraftStream::new(&mut proxy_side, 64);
+    let held = hold_client(&mut minecraft, 765, Duration::from_millis(100), ready).await;
+    assert!(held.unwrap().is_ok());
+    // the answers are read to the end, nothing of them is left to forward
+    assert!(minecraft.take_buffer().is_empty());
+    client.await.unwrap();
+}
+
+#[tokio::test]
+async fn held_client_which_leaves_is_an_error() {
+    let (mut proxy_side, mut client) = duplex(1024);
+    let mut minecraft = MinecraftStream::new(&mut proxy_side, 64);
+    let leave = async {
+        answer_plugin_request(&mut client).await;
+        drop(client);
+    };
+    let (held, _) = tokio::join!(hold_client(&mut minecraft, 765, Duration::from_secs(10), std::future::pending()), leave);
+    assert_eq!(held.err(), Some(ReadingError::Closed));
+}
+
+#[tokio::test]
+async fn held_client_may_only_answer_requests() {
+    let (mut proxy_side, mut client) = duplex(1024);
+    let mut minecraft = MinecraftStream::new(&mut proxy_side, 64);
+    // an answer to a request which wasn't sent
+    client.write_all(&plugin_response(7)).await.unwrap();
+    let held = hold_client(&mut minecraft, 765, Duration::from_secs(10), std::future::pending()).await;
+    assert_eq!(held.err(), Some(ReadingError::Invalid));
+}
+
+#[tokio::test]
+async fn old_client_gets_no_requests() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_pass = upstream.local_addr().unwrap();
+    let (mut proxy_side, mut client) = duplex(1024);
+    let mut minecraft = MinecraftStream::new(&mut proxy_side, 64);
+    let ready = async {
+        sleep(Duration::from_millis(200)).await;
+        TcpStream::connect(proxy_pass).await
+    };
+    assert!(hold_client(&mut minecraft, 340, Duration::from_millis(50), ready).await.unwrap().is_ok());
+    drop(minecraft);
+    drop(proxy_side);
+    let mut received = vec![];
+    client.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty());
+}
+
+#[test]
+fn old_clients_wait_within_their_timeout() {
+    let on_demand = OnDemand {
+        start_timeout_ms: Some(120_000),
+        ..Default::default()
+    };
+    assert_eq!(hold_timeout(&on_demand, 765), Duration::from_secs(120));
+    assert_eq!(hold_timeout(&on_demand, 340), Duration::from_secs(25));
+    assert_eq!(hold_timeout(&OnDemand::default(), 340), Duration::from_secs(25));
+}
+
+#[test]
+fn command_runs_once_per_start_timeout() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let launcher = Launcher::default();
+    let on_demand = OnDemand {
+        command: Some("true".to_string()),
+        start_timeout_ms: Some(60_000),
+        ..Default::default()
+    };
+    assert!(launcher.launch("lobby:25565", &on_demand));
+    assert!(!launcher.launch("lobby:25565", &on_demand));
+    assert!(launcher.launch("survival:25565", &on_demand));
+}
+
+#[test]
+fn nothing_is_launched_without_command() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    assert!(!Launcher::default().launch("lobby:25565", &OnDemand::default()));
+}
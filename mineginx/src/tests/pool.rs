@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use tokio::{io::AsyncReadExt, net::TcpListener, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig, PoolBackend},
+    pool::{Affinity, Pools},
+    respond::player_key,
+    tests::{harness::{handshake, Harness}, upstream::free_address}
+};
+
+fn pool(backends: &[(&str, u32)]) -> Vec<PoolBackend> {
+    backends.iter().map(|(addr, weight)| PoolBackend { addr: addr.to_string(), weight: Some(*weight) }).collect()
+}
+
+fn select_many(pools: &Pools, pool: &[PoolBackend], count: usize) -> Vec<String> {
+    (0..count).map(|_| pools.select(pool).unwrap().addr.clone()).collect()
+}
+
+#[test]
+fn smooth_order() {
+    let pool = pool(&[("a", 5), ("b", 1), ("c", 1)]);
+    assert_eq!(select_many(&Pools::default(), &pool, 7), ["a", "a", "b", "a", "c", "a", "a"]);
+}
+
+#[test]
+fn distribution_follows_weights() {
+    let pools = Pools::default();
+    let pool = pool(&[("a", 3), ("b", 1), ("c", 6)]);
+    let selected = select_many(&pools, &pool, 10_000);
+    let count = |addr: &str| selected.iter().filter(|x| *x == addr).count();
+    assert_eq!((count("a"), count("b"), count("c")), (3000, 1000, 6000));
+}
+
+#[test]
+fn omitted_weight_is_one() {
+    let pool = vec![
+        PoolBackend { addr: "a".to_string(), weight: None },
+        PoolBackend { addr: "b".to_string(), weight: Some(1) }
+    ];
+    assert_eq!(select_many(&Pools::default(), &pool, 4), ["a", "b", "a", "b"]);
+}
+
+#[test]
+fn down_backend_is_skipped() {
+    let pools = Pools::default();
+    let pool = pool(&[("a", 1), ("b", 1), ("c", 1)]);
+    pools.mark_down("b");
+    assert_eq!(select_many(&pools, &pool, 4), ["a", "c", "a", "c"]);
+    // with the whole pool down the clients still go somewhere
+    pools.mark_down("a");
+    pools.mark_down("c");
+    assert_eq!(select_many(&pools, &pool, 3).len(), 3);
+    assert_eq!(pools.select(&[]), None);
+}
+
+#[test]
+fn sticky_player_keeps_backend() {
+    let pools = Pools::default();
+    let pool = pool(&[("a", 1), ("b", 1), ("c", 1)]);
+    let first = pools.select_sticky(&pool, "player-1").unwrap().addr.clone();
+    for _ in 0..10 {
+        assert_eq!(pools.select_sticky(&pool, "player-1").unwrap().addr, first);
+        pools.select(&pool);
+    }
+    // the others are still spread
+    let others: Vec<String> = (0..3).map(|x| pools.select_sticky(&pool, &format!("other-{x}")).unwrap().addr.clone()).collect();
+    assert!(others.iter().any(|x| *x != others[0]));
+    // a down backend is replaced and the new one is remembered
+    pools.mark_down(&first);
+    let second = pools.select_sticky(&pool, "player-1").unwrap().addr.clone();
+    assert_ne!(second, first);
+    assert_eq!(pools.select_sticky(&pool, "player-1").unwrap().addr, second);
+}
+
+#[test]
+fn affinity_forgets_least_recently_seen() {
+    let affinity = Affinity::new(2);
+    affinity.insert("a".to_string(), "10.0.0.1:25565".to_string());
+    affinity.insert("b".to_string(), "10.0.0.2:25565".to_string());
+    assert_eq!(affinity.get("a"), Some("10.0.0.1:25565".to_string()));
+    affinity.insert("c".to_string(), "10.0.0.3:25565".to_string());
+    assert_eq!(affinity.get("b"), None);
+    assert_eq!(affinity.get("a"), Some("10.0.0.1:25565".to_string()));
+    assert_eq!(affinity.get("c"), Some("10.0.0.3:25565".to_string()));
+    // a player seen again isn't counted twice
+    affinity.insert("c".to_string(), "10.0.0.4:25565".to_string());
+    assert_eq!(affinity.get("a"), Some("10.0.0.1:25565".to_string()));
+    assert_eq!(affinity.get("c"), Some("10.0.0.4:25565".to_string()));
+}
+
+const UUID: [u8; 16] = [0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x4d, 0x82, 0x8b, 0x6c, 0x8e, 0x3b, 0x9b, 0x7c, 0x0b, 0x7a];
+
+#[test]
+fn player_keys() {
+    let name = [&[5][..], b"Steve"].concat();
+    assert_eq!(player_key(&[name.as_slice(), &UUID].concat(), 765).unwrap(), "069a79f4-44e9-4d82-8b6c-8e3b9b7c0b7a");
+    assert_eq!(player_key(&[name.as_slice(), &[1], &UUID].concat(), 763).unwrap(), "069a79f4-44e9-4d82-8b6c-8e3b9b7c0b7a");
+    assert_eq!(player_key(&[name.as_slice(), &[0]].concat(), 763).unwrap(), "steve");
+    assert_eq!(player_key(&name, 47).unwrap(), "steve");
+    assert_eq!(player_key(&name[..3], 47), None);
+}
+
+fn login_start(name: &str) -> Vec<u8> {
+    let data = [&[name.len() as u8][..], name.as_bytes(), &UUID].concat();
+    [&[data.len() as u8 + 1, 0][..], &data].concat()
+}
+
+#[tokio::test]
+async fn same_player_goes_to_same_backend() {
+    let backends = [TcpListener::bind("127.0.0.1:0").await.unwrap(), TcpListener::bind("127.0.0.1:0").await.unwrap()];
+    let addresses: Vec<String> = backends.iter().map(|x| x.local_addr().unwrap().to_string()).collect();
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass_pool: Some(pool(&[(&addresses[0], 1), (&addresses[1], 1)])),
+        sticky_sessions: Some(true),
+        ..Default::default()
+    }).await;
+
+    let expected = [handshake("localhost", 2), login_start("Steve")].concat();
+    let mut chosen = vec![];
+    for _ in 0..3 {
+        let _client = harness.connect(&expected).await;
+        let (mut backend, index) = tokio::select! {
+            x = backends[0].accept() => (x.unwrap().0, 0),
+            x = backends[1].accept() => (x.unwrap().0, 1)
+        };
+        let mut received = vec![0; expected.len()];
+        backend.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+        chosen.push(index);
+    }
+    assert!(chosen.iter().all(|x| *x == chosen[0]));
+}
+
+#[tokio::test]
+async fn failed_backend_gets_no_more_clients() {
+    let live = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead = free_address().await;
+    let config = MineginxConfig {
+        upstream_connect_timeout_ms: Some(1000),
+        ..Default::default()
+    };
+    let harness = Harness::start_with(config, MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass_pool: Some(pool(&[(&dead, 1), (&live.local_addr().unwrap().to_string(), 1)])),
+        ..Default::default()
+    }).await;
+
+    // the first client goes to the dead backend and is disconnected
+    let mut client = harness.connect(&handshake("localhost", 2)).await;
+    let mut rest = vec![];
+    timeout(Duration::from_secs(2), client.read_to_end(&mut rest)).await.unwrap().unwrap();
+    // without the failure every other client would go to the dead backend
+    for _ in 0..2 {
+        let _client = harness.connect(&handshake("localhost", 2)).await;
+        timeout(Duration::from_secs(2), live.accept()).await.unwrap().unwrap();
+    }
+}
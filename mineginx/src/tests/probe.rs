@@ -0,0 +1,101 @@
+use std::{borrow::BorrowMut, sync::Arc};
+
+use minecraft::{
+    packets::{HandshakeC2SPacket, StatusRequestC2SPacket, StatusResponseS2CPacket},
+    serialization::MinecraftStream
+};
+use tokio::{net::TcpListener, sync::mpsc};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    probe::{probe_upstreams, PROBE_PROTOCOL_VERSION},
+    tests::upstream::free_address
+};
+
+/// Answers status requests and passes on the handshakes it got
+async fn start_upstream() -> (String, mpsc::UnboundedReceiver<HandshakeC2SPacket>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (handshakes, received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let handshakes = handshakes.clone();
+            tokio::spawn(async move {
+                let mut upstream = MinecraftStream::new(socket.borrow_mut(), 4096);
+                let handshake = upstream.read_packet::<HandshakeC2SPacket>().await.unwrap();
+                upstream.read_packet::<StatusRequestC2SPacket>().await.unwrap();
+                handshakes.send(handshake).unwrap();
+                let json_response = r#"{"description":{"text":"up"}}"#.to_string();
+                upstream.write_packet_with_id(0, &StatusResponseS2CPacket { json_response }).await.unwrap();
+            });
+        }
+    });
+    (address, received)
+}
+
+fn server(server_name: &str, proxy_pass: &str) -> MinecraftServerDescription {
+    MinecraftServerDescription {
+        listen: "0.0.0.0:25565".into(),
+        server_names: vec![server_name.to_string()],
+        proxy_pass: proxy_pass.to_string(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn reachable_and_unreachable_upstreams() {
+    let (upstream, mut handshakes) = start_upstream().await;
+    let closed = free_address().await;
+    let config = MineginxConfig {
+        upstream_connect_timeout_ms: Some(1000),
+        servers: vec![
+            server("mc.example.com", &upstream),
+            server("down.example.com", &closed),
+            server("other.example.com", &upstream)
+        ],
+        ..Default::default()
+    };
+    let probes = probe_upstreams(Arc::new(config)).await;
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0].proxy_pass, upstream);
+    assert!(probes[0].result.is_ok());
+    assert_eq!(probes[1].proxy_pass, closed);
+    assert!(probes[1].result.is_err());
+
+    // an upstream shared by servers is asked once, with the domain of the first one
+    let handshake = handshakes.recv().await.unwrap();
+    assert_eq!(handshake.domain, "mc.example.com");
+    assert_eq!(handshake.protocol_version, PROBE_PROTOCOL_VERSION);
+    assert_eq!(handshake.next_state, 1);
+    assert!(handshakes.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn handshake_is_rewritten_like_for_clients() {
+    let (upstream, mut handshakes) = start_upstream().await;
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            upstream_hostname: Some("survival.internal".to_string()),
+            rewrite_port: Some(25577),
+            ..server("mc.example.com", &upstream)
+        }],
+        ..Default::default()
+    };
+    assert!(probe_upstreams(Arc::new(config)).await[0].result.is_ok());
+    let handshake = handshakes.recv().await.unwrap();
+    assert_eq!(handshake.domain, "survival.internal");
+    assert_eq!(handshake.server_port, 25577);
+}
+
+#[tokio::test]
+async fn silent_upstream_is_unreachable() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = MineginxConfig {
+        upstream_connect_timeout_ms: Some(100),
+        servers: vec![server("mc.example.com", &listener.local_addr().unwrap().to_string())],
+        ..Default::default()
+    };
+    let probes = probe_upstreams(Arc::new(config)).await;
+    assert_eq!(probes[0].result, Err("no status response in 100ms".to_string()));
+}
@@ -0,0 +1,23 @@
+use crate::protocol::{version_name, ProtocolVersion};
+
+#[test]
+fn known_versions() {
+    assert_eq!(version_name(47), Some("1.8-1.8.9"));
+    assert_eq!(version_name(340), Some("1.12.2"));
+    assert_eq!(version_name(765), Some("1.20.3-1.20.4"));
+    assert_eq!(version_name(764), Some("1.20.2"));
+    assert_eq!(version_name(767), Some("1.21-1.21.1"));
+}
+
+#[test]
+fn unknown_version() {
+    // snapshots and versions between releases
+    assert_eq!(version_name(1), None);
+    assert_eq!(version_name(-1), None);
+}
+
+#[test]
+fn display_for_logs() {
+    assert_eq!(ProtocolVersion(765).to_string(), "765 (1.20.3-1.20.4)");
+    assert_eq!(ProtocolVersion(9999).to_string(), "9999 (unknown)");
+}
@@ -0,0 +1,338 @@
+use std::{borrow::BorrowMut, time::{Duration, Instant}};
+
+use minecraft::{packets::{HandshakeC2SPacket, LoginDisconnectS2CPacket}, serialization::MinecraftStream};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::{sleep, timeout}};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    tests::{harness::{assert_closed, handshake, versioned_handshake, Harness}, upstream::free_address}
+};
+
+fn server(proxy_pass: String) -> MinecraftServerDescription {
+    MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass,
+        ..Default::default()
+    }
+}
+
+fn versioned_server(proxy_pass: String) -> MinecraftServerDescription {
+    MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass,
+        allowed_protocol_versions: Some(vec![763, 764, 765]),
+        unsupported_version_message: Some("Please use version 1.20.x".to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn handshake_and_data_reach_upstream() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), server(upstream.local_addr().unwrap().to_string())).await;
+    let handshake = handshake("localhost", 2);
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+
+    // the login packet arrives together with the handshake and must not be lost
+    let mut client = proxy.connect(&[handshake.as_slice(), &login].concat()).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len() + login.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, [handshake.as_slice(), &login].concat());
+
+    backend.write_all(b"pong").await.unwrap();
+    let mut reply = [0; 4];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"pong");
+
+    client.write_all(b"after").await.unwrap();
+    let mut after = [0; 5];
+    backend.read_exact(&mut after).await.unwrap();
+    assert_eq!(&after, b"after");
+}
+
+#[tokio::test]
+async fn data_beyond_handshake_buffer_is_forwarded_in_order() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), server(upstream.local_addr().unwrap().to_string())).await;
+    let handshake = handshake("localhost", 2);
+    // more than the handshake reader buffers, so a part is taken from its buffer and the rest from the socket
+    let rest: Vec<u8> = (0..20_000).map(|x| x as u8).collect();
+
+    let _client = proxy.connect(&[handshake.as_slice(), &rest].concat()).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len() + rest.len()];
+    timeout(Duration::from_secs(5), backend.read_exact(&mut received)).await.unwrap().unwrap();
+    assert_eq!(received, [handshake.as_slice(), &rest].concat());
+}
+
+#[tokio::test]
+async fn forge_handshake_is_forwarded_intact() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["mc.example.com".to_string()],
+        proxy_pass: upstream.local_addr().unwrap().to_string(),
+        ..Default::default()
+    }).await;
+    let handshake = handshake("mc.example.com\0FML3\0", 2);
+
+    let _client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn upstream_hostname_replaces_domain() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["mc.example.com".to_string()],
+        proxy_pass: upstream.local_addr().unwrap().to_string(),
+        upstream_hostname: Some("vhost.shared-host.net".to_string()),
+        ..Default::default()
+    }).await;
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+
+    let _client = proxy.connect(&[handshake("mc.example.com\0FML3\0", 2).as_slice(), &login].concat()).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let expected = [handshake("vhost.shared-host.net\0FML3\0", 2).as_slice(), &login].concat();
+    let mut received = vec![0; expected.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn upstream_hostname_replaces_plain_domain() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["play.example.com".to_string()],
+        proxy_pass: upstream.local_addr().unwrap().to_string(),
+        upstream_hostname: Some("survival.internal".to_string()),
+        ..Default::default()
+    }).await;
+
+    let _client = proxy.connect(&handshake("play.example.com", 2)).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut minecraft = MinecraftStream::new(backend.borrow_mut(), 1024);
+    let received = minecraft.read_packet::<HandshakeC2SPacket>().await.unwrap();
+    assert_eq!(received.domain, "survival.internal");
+    assert_eq!(received.server_port, 25565);
+}
+
+#[tokio::test]
+async fn rewrite_port_replaces_only_port() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["mc.example.com".to_string()],
+        proxy_pass: upstream.local_addr().unwrap().to_string(),
+        rewrite_port: Some(25577),
+        ..Default::default()
+    }).await;
+
+    let _client = proxy.connect(&handshake("mc.example.com\0FML3\0", 2)).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut minecraft = MinecraftStream::new(backend.borrow_mut(), 1024);
+    let received = minecraft.read_packet::<HandshakeC2SPacket>().await.unwrap();
+    assert_eq!(received.server_port, 25577);
+    assert_eq!(received.domain, "mc.example.com\0FML3\0");
+    assert_eq!(received.protocol_version, 765);
+    assert_eq!(received.next_state, 2);
+}
+
+#[tokio::test]
+async fn mixed_case_forge_handshake_is_forwarded_intact() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["play.example.com".to_string()],
+        proxy_pass: upstream.local_addr().unwrap().to_string(),
+        ..Default::default()
+    }).await;
+    let handshake = handshake("Play.Example.Com\0FML3\0", 2);
+
+    let _client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn unmatched_domain_is_closed() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), server(upstream.local_addr().unwrap().to_string())).await;
+
+    let mut client = proxy.connect(&handshake("example.com", 2)).await;
+    assert_closed(&mut client).await;
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn upstream_down_is_closed() {
+    let proxy = Harness::start_with(MineginxConfig::default(), server(free_address().await)).await;
+
+    let mut client = proxy.connect(&handshake("localhost", 2)).await;
+    assert_closed(&mut client).await;
+}
+
+#[tokio::test]
+async fn allowed_protocol_version_reaches_upstream() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), versioned_server(upstream.local_addr().unwrap().to_string())).await;
+    let handshake = versioned_handshake("localhost", 764, 2);
+
+    let _client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn unsupported_protocol_version_is_kicked() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), versioned_server(upstream.local_addr().unwrap().to_string())).await;
+
+    let mut client = proxy.connect(&versioned_handshake("localhost", 47, 2)).await;
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 0);
+    let disconnect = minecraft.read_data::<LoginDisconnectS2CPacket>(signature).await.unwrap();
+    assert_eq!(disconnect.reason, r#"{"text":"Please use version 1.20.x"}"#);
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+}
+
+fn blocking_server(proxy_pass: String) -> MinecraftServerDescription {
+    MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass,
+        blocked_protocol_versions: Some(vec![764]),
+        unsupported_version_message: Some("Please update Minecraft".to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn blocked_protocol_version_is_kicked() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), blocking_server(upstream.local_addr().unwrap().to_string())).await;
+
+    let mut client = proxy.connect(&versioned_handshake("localhost", 764, 2)).await;
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 0);
+    let disconnect = minecraft.read_data::<LoginDisconnectS2CPacket>(signature).await.unwrap();
+    assert_eq!(disconnect.reason, r#"{"text":"Please update Minecraft"}"#);
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+
+    // a server list ping gets no answer
+    let mut client = proxy.connect(&versioned_handshake("localhost", 764, 1)).await;
+    assert_closed(&mut client).await;
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn not_blocked_protocol_version_reaches_upstream() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), blocking_server(upstream.local_addr().unwrap().to_string())).await;
+    let handshake = versioned_handshake("localhost", 765, 2);
+
+    let _client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn transfer_reaches_upstream() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), versioned_server(upstream.local_addr().unwrap().to_string())).await;
+    let handshake = versioned_handshake("localhost", 766, 3);
+
+    let mut client = proxy.connect(&handshake).await;
+    // transferred players are checked like the ones logging in
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    let signature = minecraft.read_signature().await.unwrap();
+    let disconnect = minecraft.read_data::<LoginDisconnectS2CPacket>(signature).await.unwrap();
+    assert_eq!(disconnect.reason, r#"{"text":"Please use version 1.20.x"}"#);
+
+    let handshake = versioned_handshake("localhost", 765, 3);
+    let _client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn denied_transfer_is_kicked() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        allow_transfer: Some(false),
+        ..versioned_server(upstream.local_addr().unwrap().to_string())
+    }).await;
+
+    let mut client = proxy.connect(&versioned_handshake("localhost", 765, 3)).await;
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 0);
+    let disconnect = minecraft.read_data::<LoginDisconnectS2CPacket>(signature).await.unwrap();
+    assert_eq!(disconnect.reason, r#"{"text":"Transfers to this server are not allowed"}"#);
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn unknown_next_state_is_closed() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), server(upstream.local_addr().unwrap().to_string())).await;
+
+    let mut client = proxy.connect(&versioned_handshake("localhost", 765, 4)).await;
+    assert_closed(&mut client).await;
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn status_ping_ignores_protocol_version() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), versioned_server(upstream.local_addr().unwrap().to_string())).await;
+    let handshake = versioned_handshake("localhost", -1, 1);
+
+    let _client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
+
+#[tokio::test]
+async fn dribbling_handshake_is_closed_at_deadline() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass: upstream.local_addr().unwrap().to_string(),
+        handshake_timeout_ms: Some(300),
+        ..Default::default()
+    }).await;
+    let handshake = handshake("localhost", 2);
+
+    // every byte comes well within the deadline, but the whole handshake doesn't
+    let (mut reader, mut writer) = TcpStream::connect(&proxy.address).await.unwrap().into_split();
+    let started = Instant::now();
+    let dribbling = tokio::spawn(async move {
+        for byte in &handshake[..handshake.len() - 1] {
+            if writer.write_all(&[*byte]).await.is_err() {
+                return;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        sleep(Duration::from_secs(5)).await;
+    });
+    let mut buf = [0; 16];
+    let read = timeout(Duration::from_secs(2), reader.read(&mut buf)).await.unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert!(started.elapsed() < Duration::from_millis(600));
+    dribbling.abort();
+    assert!(timeout(Duration::from_millis(100), upstream.accept()).await.is_err());
+}
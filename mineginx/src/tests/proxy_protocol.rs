@@ -0,0 +1,255 @@
+use std::{io::{Cursor, ErrorKind}, net::SocketAddr, sync::{Arc, Mutex}};
+
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+use crate::{
+    config::{parse_config, ConfigFormat, MinecraftServerDescription, MineginxConfig, ProxyProtocolVersion},
+    proxy_protocol::{encode_header, parse_v1, parse_v2, read_header},
+    tests::{harness::{assert_closed, handshake, Harness}, router::{start_routed, MockRouter}}
+};
+
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.push(0x20 | command);
+    header.push(family);
+    header.extend((addresses.len() as u16).to_be_bytes());
+    header.extend(addresses);
+    header
+}
+
+fn v2_ipv4() -> Vec<u8> {
+    v2(1, 0x11, &[1, 2, 3, 4, 10, 0, 0, 1, 0xC7, 0x38, 0x63, 0xDD])
+}
+
+fn socket_address(x: &str) -> Option<SocketAddr> {
+    Some(x.parse().unwrap())
+}
+
+#[test]
+fn v1_addresses() {
+    assert_eq!(parse_v1(b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565\r\n").unwrap(), socket_address("1.2.3.4:51000"));
+    assert_eq!(parse_v1(b"PROXY TCP6 2001:db8::1 ::1 51000 25565\r\n").unwrap(), socket_address("[2001:db8::1]:51000"));
+    assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+    assert_eq!(parse_v1(b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n").unwrap(), None);
+}
+
+#[test]
+fn malformed_v1() {
+    for header in [
+        &b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565"[..],
+        b"PROXY TCP4 1.2.3.4 10.0.0.1 51000\r\n",
+        b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565 extra\r\n",
+        b"PROXY UDP4 1.2.3.4 10.0.0.1 51000 25565\r\n",
+        b"PROXY TCP4 2001:db8::1 10.0.0.1 51000 25565\r\n",
+        b"PROXY TCP6 2001:db8::1 10.0.0.1 51000 25565\r\n",
+        b"PROXY TCP4 1.2.3.4 10.0.0.1 70000 25565\r\n",
+        b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 port\r\n",
+        b"PROXY TCP4 \xFF.2.3.4 10.0.0.1 51000 25565\r\n",
+        b"PROXY  TCP4 1.2.3.4 10.0.0.1 51000 25565\r\n"
+    ] {
+        let err = parse_v1(header).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", String::from_utf8_lossy(header));
+    }
+}
+
+#[test]
+fn v2_addresses() {
+    let header = v2_ipv4();
+    assert_eq!(parse_v2(&header[..16], &header[16..]).unwrap(), socket_address("1.2.3.4:51000"));
+
+    let mut addresses = vec![0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    addresses.extend([0; 15]);
+    addresses.push(1);
+    addresses.extend([0xC7, 0x38, 0x63, 0xDD]);
+    let header = v2(1, 0x21, &addresses);
+    assert_eq!(parse_v2(&header[..16], &header[16..]).unwrap(), socket_address("[2001:db8::1]:51000"));
+}
+
+#[test]
+fn v2_tlvs_are_skipped() {
+    let mut addresses = v2_ipv4()[16..].to_vec();
+    // PP2_TYPE_AUTHORITY with "mc"
+    addresses.extend([0x02, 0x00, 0x02, b'm', b'c']);
+    let header = v2(1, 0x11, &addresses);
+    assert_eq!(parse_v2(&header[..16], &header[16..]).unwrap(), socket_address("1.2.3.4:51000"));
+}
+
+#[test]
+fn v2_without_client_address() {
+    // LOCAL is sent by health checks of the load balancer
+    let header = v2(0, 0x00, &[]);
+    assert_eq!(parse_v2(&header[..16], &header[16..]).unwrap(), None);
+    let header = v2(1, 0x00, &[]);
+    assert_eq!(parse_v2(&header[..16], &header[16..]).unwrap(), None);
+    let header = v2(1, 0x31, &[0; 216]);
+    assert_eq!(parse_v2(&header[..16], &header[16..]).unwrap(), None);
+}
+
+#[test]
+fn malformed_v2() {
+    let truncated = v2(1, 0x11, &[1, 2, 3, 4, 10, 0, 0, 1]);
+    let truncated_ipv6 = v2(1, 0x21, &[0; 12]);
+    let mut version = v2_ipv4();
+    version[12] = 0x11;
+    let command = v2(2, 0x11, &v2_ipv4()[16..]);
+    let family = v2(1, 0x41, &v2_ipv4()[16..]);
+    let mut signature = v2_ipv4();
+    signature[0] = 0;
+    for header in [truncated, truncated_ipv6, version, command, family, signature] {
+        let err = parse_v2(&header[..16], &header[16..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[tokio::test]
+async fn header_is_read_exactly() {
+    for header in [b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565\r\n".to_vec(), v2_ipv4()] {
+        let mut stream = Cursor::new([header, handshake("localhost", 2)].concat());
+        assert_eq!(read_header(&mut stream).await.unwrap(), socket_address("1.2.3.4:51000"));
+        let mut rest = vec![];
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, handshake("localhost", 2));
+    }
+
+    let mut stream = Cursor::new([&b"PROXY UNKNOWN\r\n"[..], &handshake("localhost", 2)].concat());
+    assert_eq!(read_header(&mut stream).await.unwrap(), None);
+    let mut rest = vec![];
+    stream.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, handshake("localhost", 2));
+}
+
+#[tokio::test]
+async fn missing_or_endless_header() {
+    let err = read_header(&mut Cursor::new(handshake("localhost", 2))).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let endless = [&b"PROXY TCP4 "[..], &[b'1'; 200]].concat();
+    let err = read_header(&mut Cursor::new(endless)).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let err = read_header(&mut Cursor::new(&v2_ipv4()[..20])).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn client_address_is_taken_from_header() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let router = Arc::new(MockRouter {
+        proxy_pass: Some(upstream.local_addr().unwrap().to_string()),
+        calls: Mutex::new(vec![])
+    });
+    let proxy = start_routed(router.clone(), Some(true)).await;
+
+    let _client = proxy.connect(&[v2_ipv4(), handshake("localhost", 2)].concat()).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    // the header is for mineginx, the upstream gets the minecraft connection as is
+    let mut received = vec![0; handshake("localhost", 2).len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake("localhost", 2));
+
+    let calls = router.calls.lock().unwrap();
+    assert_eq!(*calls, [("localhost".to_string(), "127.0.0.1:0".to_string(), socket_address("1.2.3.4:51000"))]);
+}
+
+#[tokio::test]
+async fn login_after_header_and_handshake_is_forwarded() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let router = Arc::new(MockRouter {
+        proxy_pass: Some(upstream.local_addr().unwrap().to_string()),
+        calls: Mutex::new(vec![])
+    });
+    let proxy = start_routed(router, Some(true)).await;
+    let login = [5, 0, 3, b'b', b'o', b'b'];
+
+    let _client = proxy.connect(&[&b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565\r\n"[..], &handshake("localhost", 2), &login].concat()).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake("localhost", 2).len() + login.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, [handshake("localhost", 2).as_slice(), &login].concat());
+}
+
+#[tokio::test]
+async fn client_without_header_is_closed() {
+    let router = Arc::new(MockRouter { proxy_pass: None, calls: Mutex::new(vec![]) });
+    let proxy = start_routed(router.clone(), Some(true)).await;
+
+    let mut client = proxy.connect(&handshake("localhost", 2)).await;
+    assert_closed(&mut client).await;
+    assert!(router.calls.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn encoded_headers_are_read_back() {
+    let cases = [
+        ("1.2.3.4:51000", "10.0.0.1:25565", "1.2.3.4:51000"),
+        ("[2001:db8::1]:51000", "[::1]:25565", "[2001:db8::1]:51000"),
+        // one header has one family, the ipv4 address is mapped
+        ("1.2.3.4:51000", "[::1]:25565", "[::ffff:1.2.3.4]:51000")
+    ];
+    for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+        for (source, destination, expected) in cases {
+            let header = encode_header(version, socket_address(source), socket_address(destination));
+            let mut stream = Cursor::new([header, handshake("localhost", 2)].concat());
+            assert_eq!(read_header(&mut stream).await.unwrap(), socket_address(expected), "{version:?} {source}");
+            let mut rest = vec![];
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert_eq!(rest, handshake("localhost", 2));
+        }
+        let header = encode_header(version, None, socket_address("10.0.0.1:25565"));
+        assert_eq!(read_header(&mut Cursor::new(header)).await.unwrap(), None);
+    }
+    let header = encode_header(ProxyProtocolVersion::V1, socket_address("1.2.3.4:51000"), socket_address("10.0.0.1:25565"));
+    assert_eq!(header, b"PROXY TCP4 1.2.3.4 10.0.0.1 51000 25565\r\n");
+}
+
+#[test]
+fn old_name_of_accept_proxy_protocol() {
+    let yaml = b"servers:\n- listen: 0.0.0.0:25565\n  server_names: [localhost]\n  proxy_pass: 127.0.0.1:7878\n  expect_proxy_protocol: true\n  send_proxy_protocol: v2\n";
+    let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+    assert_eq!(config.servers[0].accept_proxy_protocol, Some(true));
+    assert_eq!(config.servers[0].send_proxy_protocol, Some(ProxyProtocolVersion::V2));
+}
+
+/// Receives a header from the front load balancer and sends one to the upstream
+async fn start_relaying_proxy(version: ProxyProtocolVersion) -> Harness {
+    Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        accept_proxy_protocol: Some(true),
+        send_proxy_protocol: Some(version),
+        ..Default::default()
+    }).await
+}
+
+#[tokio::test]
+async fn client_address_is_passed_to_upstream() {
+    for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+        let harness = start_relaying_proxy(version).await;
+        let login = [5, 0, 3, b'b', b'o', b'b'];
+
+        let _client = harness.connect(&[v2_ipv4(), handshake("localhost", 2), login.to_vec()].concat()).await;
+        let mut backend = harness.accept().await;
+        assert_eq!(read_header(&mut backend).await.unwrap(), socket_address("1.2.3.4:51000"), "{version:?}");
+        let mut received = vec![0; handshake("localhost", 2).len() + login.len()];
+        backend.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, [handshake("localhost", 2).as_slice(), &login].concat());
+    }
+}
+
+#[tokio::test]
+async fn header_is_sent_without_accepting_one() {
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        send_proxy_protocol: Some(ProxyProtocolVersion::V1),
+        ..Default::default()
+    }).await;
+
+    let client = harness.connect(&handshake("localhost", 2)).await;
+    let mut backend = harness.accept().await;
+    let client_address = client.local_addr().unwrap();
+    assert_eq!(read_header(&mut backend).await.unwrap(), Some(client_address));
+    let mut received = vec![0; handshake("localhost", 2).len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake("localhost", 2));
+}
@@ -0,0 +1,78 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    resolver::Resolver,
+    tests::harness::{handshake, Harness}
+};
+
+#[tokio::test]
+async fn refresh_resolves_address() {
+    let resolver = Resolver::default();
+    resolver.refresh("127.0.0.1:7878").await.unwrap();
+    assert_eq!(resolver.cached("127.0.0.1:7878"), Some(vec!["127.0.0.1:7878".parse::<SocketAddr>().unwrap()]));
+    assert_eq!(resolver.cached("127.0.0.2:7878"), None);
+}
+
+#[tokio::test]
+async fn failed_refresh_keeps_addresses() {
+    let resolver = Resolver::default();
+    let address: SocketAddr = "127.0.0.1:7878".parse().unwrap();
+    resolver.store("backend.invalid:7878", vec![address]);
+    assert!(resolver.refresh("backend.invalid:7878").await.is_err());
+    assert_eq!(resolver.cached("backend.invalid:7878"), Some(vec![address]));
+}
+
+#[tokio::test]
+async fn background_resolution_stops_with_resolver() {
+    let resolver = Arc::new(Resolver::default());
+    resolver.start("127.0.0.1:7878".to_string(), Duration::from_millis(10));
+    let weak = Arc::downgrade(&resolver);
+    drop(resolver);
+    assert!(weak.upgrade().is_none());
+}
+
+#[tokio::test]
+async fn follow_reloaded_config() {
+    let server = |proxy_pass: &str, resolve_interval_ms: Option<u64>| MinecraftServerDescription {
+        listen: "0.0.0.0:25565".into(),
+        server_names: vec![proxy_pass.to_string()],
+        proxy_pass: proxy_pass.to_string(),
+        resolve_interval_ms,
+        ..Default::default()
+    };
+    let resolver = Arc::new(Resolver::default());
+    resolver.follow(&MineginxConfig {
+        servers: vec![server("127.0.0.1:7878", Some(60_000)), server("127.0.0.2:7878", Some(60_000)), server("127.0.0.3:7878", None)],
+        ..Default::default()
+    }).await;
+    assert_eq!(resolver.refreshing(), ["127.0.0.1:7878", "127.0.0.2:7878"]);
+    assert!(resolver.cached("127.0.0.2:7878").is_some());
+    assert!(resolver.cached("127.0.0.3:7878").is_none());
+    resolver.follow(&MineginxConfig {
+        servers: vec![server("127.0.0.2:7878", Some(30_000))],
+        ..Default::default()
+    }).await;
+    assert_eq!(resolver.refreshing(), ["127.0.0.2:7878"]);
+}
+
+#[tokio::test]
+async fn client_connects_to_cached_address() {
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        proxy_pass: "backend.invalid:25565".to_string(),
+        resolve_interval_ms: Some(60_000),
+        ..Default::default()
+    }).await;
+    // the name can't be resolved, so only the cache leads to the upstream
+    harness.state.resolver.store("backend.invalid:25565", vec![harness.upstream.local_addr().unwrap()]);
+
+    let handshake = handshake("localhost", 2);
+    let _client = harness.connect(&handshake).await;
+    let mut backend = harness.accept().await;
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+}
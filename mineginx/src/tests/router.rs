@@ -0,0 +1,91 @@
+use std::{net::SocketAddr, sync::{Arc, Mutex}};
+
+use minecraft::packets::HandshakeC2SPacket;
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    router::{ConfigRouter, RouteFuture, Router, UpstreamTarget},
+    tests::harness::{assert_closed, handshake, start_proxy, RunningProxy},
+    Proxy
+};
+
+/// Sends every client to `proxy_pass` and remembers who asked
+pub(super) struct MockRouter {
+    pub(super) proxy_pass: Option<String>,
+    pub(super) calls: Mutex<Vec<(String, String, Option<SocketAddr>)>>
+}
+
+impl Router for MockRouter {
+    fn resolve<'a>(&'a self, handshake: &'a HandshakeC2SPacket, listen: &'a str, peer: Option<SocketAddr>) -> RouteFuture<'a> {
+        Box::pin(async move {
+            self.calls.lock().unwrap().push((handshake.domain.clone(), listen.to_string(), peer));
+            self.proxy_pass.clone().map(|proxy_pass| UpstreamTarget { proxy_pass, ..Default::default() })
+        })
+    }
+}
+
+/// A proxy where only `router` chooses the upstreams, `listen` is `127.0.0.1:0`
+pub(super) async fn start_routed(router: Arc<MockRouter>, accept_proxy_protocol: Option<bool>) -> RunningProxy {
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            accept_proxy_protocol,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    start_proxy(Proxy::new(config).with_router(router)).await
+}
+
+#[tokio::test]
+async fn custom_router_chooses_upstream() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let router = Arc::new(MockRouter {
+        proxy_pass: Some(upstream.local_addr().unwrap().to_string()),
+        calls: Mutex::new(vec![])
+    });
+    let proxy = start_routed(router.clone(), None).await;
+    let handshake = handshake("Tenant42.Example.com", 2);
+
+    let client = proxy.connect(&handshake).await;
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+
+    let calls = router.calls.lock().unwrap();
+    assert_eq!(*calls, [("Tenant42.Example.com".to_string(), "127.0.0.1:0".to_string(), Some(client.local_addr().unwrap()))]);
+}
+
+#[tokio::test]
+async fn no_route_closes_client() {
+    let router = Arc::new(MockRouter { proxy_pass: None, calls: Mutex::new(vec![]) });
+    let proxy = start_routed(router.clone(), None).await;
+
+    let mut client = proxy.connect(&handshake("localhost", 2)).await;
+    assert_closed(&mut client).await;
+    assert_eq!(router.calls.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn config_router_normalizes_domain() {
+    let router = ConfigRouter::new(Arc::new(MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "0.0.0.0:25565".into(),
+            server_names: vec!["mc.example.com".to_string()],
+            proxy_pass: "127.0.0.1:7878".to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }));
+    let handshake = HandshakeC2SPacket {
+        protocol_version: 765,
+        domain: "MC.Example.com\0FML3\0".to_string(),
+        server_port: 25565,
+        next_state: 2
+    };
+    let target = router.resolve(&handshake, "0.0.0.0:25565", None).await;
+    assert_eq!(target.map(|x| x.proxy_pass), Some("127.0.0.1:7878".to_string()));
+    assert!(router.resolve(&handshake, "0.0.0.0:25566", None).await.is_none());
+}
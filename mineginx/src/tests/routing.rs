@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use crate::{config::{DomainMatching, MinecraftServerDescription, MineginxConfig, ServerNamePattern}, router::find_upstream};
+
+#[test]
+fn exact_server_name() {
+    let config = make_config(&[&["mc.example.com"], &["example.com"]]);
+    assert_eq!(upstream("example.com", &config), Some("server1".to_string()));
+}
+
+#[test]
+fn exact_server_name_wins_over_wildcard() {
+    let config = make_config(&[&["*.example.com"], &["mc.example.com"]]);
+    assert_eq!(upstream("mc.example.com", &config), Some("server1".to_string()));
+    assert_eq!(upstream("eu.example.com", &config), Some("server0".to_string()));
+}
+
+#[test]
+fn longest_wildcard_wins() {
+    let config = make_config(&[&["*.example.com"], &["*.mc.example.com"]]);
+    assert_eq!(upstream("eu.mc.example.com", &config), Some("server1".to_string()));
+    assert_eq!(upstream("eu.example.com", &config), Some("server0".to_string()));
+}
+
+#[test]
+fn mixed_case_domain() {
+    let config = make_config(&[&["mc.example.com"], &["*.example.com"]]);
+    assert_eq!(upstream("MC.Example.COM", &config), Some("server0".to_string()));
+    assert_eq!(upstream("EU.Example.COM", &config), Some("server1".to_string()));
+}
+
+#[test]
+fn mixed_case_server_name() {
+    let config = make_config(&[&["MC.Example.com"], &["*.EXAMPLE.com"]]);
+    assert_eq!(upstream("mc.example.com", &config), Some("server0".to_string()));
+    assert_eq!(upstream("eu.example.com", &config), Some("server1".to_string()));
+}
+
+#[test]
+fn case_sensitive_matching() {
+    let mut config = MineginxConfig::clone(&make_config(&[&["mc.example.com"], &["*.Example.com"]]));
+    config.domain_matching = Some(DomainMatching { case_insensitive: Some(false), ..Default::default() });
+    let config = Arc::new(config);
+    assert_eq!(upstream("MC.example.com", &config), None);
+    assert_eq!(upstream("mc.example.com", &config), Some("server0".to_string()));
+    assert_eq!(upstream("eu.example.com", &config), None);
+    assert_eq!(upstream("eu.Example.com", &config), Some("server1".to_string()));
+}
+
+#[test]
+fn only_servers_on_accepting_listen() {
+    let mut config = MineginxConfig::clone(&make_config(&[&["*.example.com"], &["mc.example.com"]]));
+    config.servers[1].listen = "0.0.0.0:25566".into();
+    let config = Arc::new(config);
+    assert_eq!(upstream("mc.example.com", &config), Some("server0".to_string()));
+    assert_eq!(find_upstream("mc.example.com", config.clone(), "0.0.0.0:25566").map(|x| x.proxy_pass), Some("server1".to_string()));
+    assert_eq!(find_upstream("eu.example.com", config, "0.0.0.0:25566"), None);
+}
+
+#[test]
+fn no_upstream() {
+    let config = make_config(&[&["*.example.com"]]);
+    assert_eq!(upstream("example.com", &config), None);
+}
+
+#[test]
+fn pattern_matches_whole_domain() {
+    let config = with_patterns(&[&["mc[0-9]+\\.example\\.com"]]);
+    assert_eq!(upstream("mc1.example.com", &config), Some("server0".to_string()));
+    assert_eq!(upstream("mc42.example.com", &config), Some("server0".to_string()));
+    assert_eq!(upstream("mc.example.com", &config), None);
+    assert_eq!(upstream("eu.mc1.example.com", &config), None);
+    assert_eq!(upstream("mc1.example.com.evil", &config), None);
+}
+
+#[test]
+fn exact_and_wildcard_names_win_over_patterns() {
+    let mut config = MineginxConfig::clone(&make_config(&[&["*.example.com"], &["mc1.example.com"], &[]]));
+    config.servers[2].server_name_patterns = Some(vec![ServerNamePattern::new("mc[0-9]\\.example\\.com|.*\\.example\\.org").unwrap()]);
+    let config = Arc::new(config);
+    assert_eq!(upstream("mc1.example.com", &config), Some("server1".to_string()));
+    assert_eq!(upstream("mc2.example.com", &config), Some("server0".to_string()));
+    assert_eq!(upstream("mc2.example.org", &config), Some("server2".to_string()));
+}
+
+#[test]
+fn first_matching_pattern_wins() {
+    let config = with_patterns(&[&["eu\\..*", "us\\..*"], &[".*\\.example\\.com"]]);
+    assert_eq!(upstream("us.example.com", &config), Some("server0".to_string()));
+    assert_eq!(upstream("asia.example.com", &config), Some("server1".to_string()));
+}
+
+#[test]
+fn pattern_case_follows_domain_matching() {
+    let config = with_patterns(&[&["MC[0-9]\\.example\\.com"]]);
+    assert_eq!(upstream("mc1.example.com", &config), Some("server0".to_string()));
+    let mut config = MineginxConfig::clone(&config);
+    config.domain_matching = Some(DomainMatching { case_insensitive: Some(false), ..Default::default() });
+    let config = Arc::new(config);
+    assert_eq!(upstream("mc1.example.com", &config), None);
+    assert_eq!(upstream("MC1.example.com", &config), Some("server0".to_string()));
+}
+
+fn upstream(domain: &str, config: &Arc<MineginxConfig>) -> Option<String> {
+    find_upstream(domain, config.clone(), "0.0.0.0:25565").map(|x| x.proxy_pass)
+}
+
+fn make_config(servers: &[&[&str]]) -> Arc<MineginxConfig> {
+    Arc::new(MineginxConfig {
+        servers: servers.iter().enumerate().map(|(index, server_names)| MinecraftServerDescription {
+            listen: "0.0.0.0:25565".into(),
+            server_names: server_names.iter().map(|x| x.to_string()).collect(),
+            proxy_pass: format!("server{index}"),
+            ..Default::default()
+        }).collect(),
+        ..Default::default()
+    })
+}
+
+/// Servers without `server_names`, only with the given `server_name_patterns`
+fn with_patterns(servers: &[&[&str]]) -> Arc<MineginxConfig> {
+    let mut config = MineginxConfig::clone(&make_config(&vec![&[][..]; servers.len()]));
+    for (server, patterns) in config.servers.iter_mut().zip(servers) {
+        server.server_name_patterns = Some(patterns.iter().map(|x| ServerNamePattern::new(x).unwrap()).collect());
+    }
+    Arc::new(config)
+}
@@ -0,0 +1,147 @@
+use std::{io::Cursor, sync::atomic::Ordering, time::Duration};
+
+use minecraft::{packets::HandshakeC2SPacket, serialization::{MinecraftStream, ReadingError}};
+use tokio::{io::AsyncReadExt, net::TcpStream, time::{sleep, timeout}};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    read_handshake_packet,
+    state::State,
+    tests::{harness::{assert_closed, handshake, Harness}, logs::{capture_logs, captured}}
+};
+
+fn honeypot_config(honeypot_domains: &[&str]) -> MineginxConfig {
+    MineginxConfig {
+        honeypot_domains: Some(honeypot_domains.iter().map(|x| x.to_string()).collect()),
+        ..Default::default()
+    }
+}
+
+async fn wait_scanners(state: &State, count: u64) {
+    for _ in 0..100 {
+        if state.stats.scanners.load(Ordering::Relaxed) == count {
+            return;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("scanners: {}, expected {count}", state.stats.scanners.load(Ordering::Relaxed));
+}
+
+#[test]
+fn honeypot_matches() {
+    let config = honeypot_config(&["admin.example.com", "*.internal.example.com"]);
+    assert!(config.is_honeypot("admin.example.com"));
+    assert!(config.is_honeypot("ADMIN.example.com"));
+    assert!(config.is_honeypot("db.internal.example.com"));
+    assert!(!config.is_honeypot("mc.example.com"));
+    assert!(!MineginxConfig::default().is_honeypot("admin.example.com"));
+}
+
+#[tokio::test]
+async fn honeypot_domain_is_closed_and_logged() {
+    capture_logs();
+    let harness = Harness::start_with(honeypot_config(&["admin.honeypot.example.com"]), MinecraftServerDescription {
+        server_names: vec!["*.honeypot.example.com".to_string()],
+        ..Default::default()
+    }).await;
+
+    let mut client = harness.connect(&handshake("admin.honeypot.example.com", 2)).await;
+    assert_closed(&mut client).await;
+    assert!(timeout(Duration::from_millis(100), harness.upstream.accept()).await.is_err());
+    wait_scanners(&harness.state, 1).await;
+    assert_eq!(captured("admin.honeypot.example.com"), ["WARN scanner 127.0.0.1 asked for honeypot domain \"admin.honeypot.example.com\""]);
+}
+
+#[tokio::test]
+async fn status_handshake_then_disconnect_is_scanner() {
+    capture_logs();
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["status-scan.example.com".to_string()],
+        ..Default::default()
+    }).await;
+    let handshake = handshake("status-scan.example.com", 1);
+
+    let client = harness.connect(&handshake).await;
+    let mut backend = harness.accept().await;
+    let mut received = vec![0; handshake.len()];
+    backend.read_exact(&mut received).await.unwrap();
+    drop(client);
+    wait_scanners(&harness.state, 1).await;
+    assert_eq!(captured("scanner 127.0.0.1 left"), ["INFO scanner 127.0.0.1 left right after status handshake for domain status-scan.example.com"]);
+}
+
+#[tokio::test]
+async fn immediate_close_is_quiet() {
+    capture_logs();
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription {
+        server_names: vec!["localhost".to_string()],
+        ..Default::default()
+    }).await;
+
+    drop(TcpStream::connect(&harness.address).await.unwrap());
+    // a broken handshake is not a probe
+    drop(harness.connect(&[0x80]).await);
+    for _ in 0..100 {
+        if harness.state.stats.empty_connections.load(Ordering::Relaxed) == 1 {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(harness.state.stats.empty_connections.load(Ordering::Relaxed), 1);
+    assert!(!captured("DEBUG 127.0.0.1 closed the connection without a handshake").is_empty());
+}
+
+#[tokio::test]
+async fn broken_handshake_is_logged_with_reason() {
+    capture_logs();
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription::default()).await;
+    // packet id 1 is not a handshake
+    let _client = harness.connect(&[0x01, 0x01]).await;
+    timeout(Duration::from_secs(1), async {
+        while harness.state.stats.handshake_failures.load(Ordering::Relaxed) == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+    assert!(captured("handshake failed for 127.0.0.1").contains(&"ERROR handshake failed for 127.0.0.1: malformed packet".to_string()));
+}
+
+#[tokio::test]
+async fn truncated_handshake_is_not_an_error() {
+    capture_logs();
+    let harness = Harness::start_with(MineginxConfig::default(), MinecraftServerDescription::default()).await;
+    // the length of the handshake and its packet id, the rest never comes
+    drop(harness.connect(&[0x09, 0x00]).await);
+    timeout(Duration::from_secs(1), async {
+        while harness.state.stats.handshake_failures.load(Ordering::Relaxed) == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+    assert!(captured("in the middle of the handshake").contains(&"INFO 127.0.0.1 closed the connection in the middle of the handshake".to_string()));
+}
+
+#[tokio::test]
+async fn handshake_reading_errors() {
+    async fn read(data: &[u8]) -> Result<HandshakeC2SPacket, ReadingError> {
+        let mut source = Cursor::new(data.to_vec());
+        read_handshake_packet(&mut MinecraftStream::new(&mut source, 64)).await.map(|(handshake, _)| handshake)
+    }
+    assert!(read(&handshake("localhost", 2)).await.is_ok());
+    assert_eq!(read(&[]).await.err(), Some(ReadingError::ClosedEmpty));
+    assert_eq!(read(&handshake("localhost", 2)[..5]).await.err(), Some(ReadingError::Closed));
+    // packet id 1 is not a handshake
+    assert_eq!(read(&[0x01, 0x01]).await.err(), Some(ReadingError::Invalid));
+    // the domain is longer than the packet
+    assert_eq!(read(&[0x04, 0x00, 0x10, 0x7F, 0x00]).await.err(), Some(ReadingError::Invalid));
+    // the packet id is missing
+    assert_eq!(read(&[0x00, 0x00]).await.err(), Some(ReadingError::Invalid));
+
+
+    // a byte after the fields is part of the packet, the next packet is not
+    let mut packet = [handshake("localhost", 2).as_slice(), &[0x00]].concat();
+    packet[0] += 1;
+    let mut source = Cursor::new([packet.as_slice(), &[0x01, 0x00]].concat());
+    let (received, raw) = read_handshake_packet(&mut MinecraftStream::new(&mut source, 64)).await.unwrap();
+    assert_eq!(received.domain, "localhost");
+    assert_eq!(raw, packet);
+}
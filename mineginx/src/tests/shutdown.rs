@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    tests::harness::{assert_closed, handshake, start_proxy, RunningProxy},
+    Proxy, Shutdown
+};
+
+/// Runs a proxy to `upstream` until `stop`
+async fn start(upstream: &TcpListener, shutdown_grace_ms: Option<u64>) -> RunningProxy {
+    start_proxy(Proxy::new(MineginxConfig {
+        shutdown_grace_ms,
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: upstream.local_addr().unwrap().to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    })).await
+}
+
+/// Connects a client through the proxy, returns it with the upstream side of the session
+async fn open_session(address: &str, upstream: &TcpListener) -> (TcpStream, TcpStream) {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    client.write_all(&handshake("localhost", 2)).await.unwrap();
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake("localhost", 2).len()];
+    backend.read_exact(&mut received).await.unwrap();
+    (client, backend)
+}
+
+#[tokio::test]
+async fn stuck_session_is_aborted_after_grace() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut proxy = start(&upstream, Some(200)).await;
+    let (mut client, _backend) = open_session(&proxy.address, &upstream).await;
+
+    let started = Instant::now();
+    proxy.stop();
+    assert_eq!(timeout(Duration::from_secs(2), proxy.stopped()).await.unwrap(), Shutdown::Forced);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    // the forwarding is stopped, so the client is disconnected
+    assert_closed(&mut client).await;
+}
+
+#[tokio::test]
+async fn finished_sessions_shut_down_gracefully() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut proxy = start(&upstream, Some(5_000)).await;
+    let (client, backend) = open_session(&proxy.address, &upstream).await;
+
+    proxy.stop();
+    drop(client);
+    drop(backend);
+    let started = Instant::now();
+    assert_eq!(timeout(Duration::from_secs(2), proxy.stopped()).await.unwrap(), Shutdown::Graceful);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn sessions_are_kept_without_grace() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut proxy = start(&upstream, None).await;
+    let (mut client, mut backend) = open_session(&proxy.address, &upstream).await;
+
+    proxy.stop();
+    assert_eq!(timeout(Duration::from_secs(1), proxy.stopped()).await.unwrap(), Shutdown::Graceful);
+    backend.write_all(b"still here").await.unwrap();
+    let mut reply = [0; 10];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"still here");
+}
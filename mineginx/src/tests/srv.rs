@@ -0,0 +1,260 @@
+use std::{collections::{HashSet, VecDeque}, io, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::Duration};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, UdpSocket}, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    srv::{is_domain_name, select_record, DnsSrvLookup, SrvAnswer, SrvCache, SrvFuture, SrvLookup, SrvRecord},
+    tests::harness::{handshake, read_handshake, start_proxy},
+    Proxy
+};
+
+/// Gives the prepared answers in order and counts the lookups
+#[derive(Default)]
+struct MockLookup {
+    answers: Mutex<VecDeque<io::Result<SrvAnswer>>>,
+    lookups: AtomicUsize
+}
+
+impl MockLookup {
+    fn new(answers: Vec<io::Result<SrvAnswer>>) -> Arc<MockLookup> {
+        Arc::new(MockLookup {
+            answers: Mutex::new(answers.into()),
+            lookups: AtomicUsize::new(0)
+        })
+    }
+}
+
+impl SrvLookup for MockLookup {
+    fn lookup<'a>(&'a self, _name: &'a str) -> SrvFuture<'a> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let answer = self.answers.lock().unwrap().pop_front().expect("no more answers");
+        Box::pin(async move { answer })
+    }
+}
+
+fn record(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+    SrvRecord { priority, weight, port, target: target.to_string() }
+}
+
+fn answer(records: Vec<SrvRecord>, ttl: u64) -> io::Result<SrvAnswer> {
+    Ok(SrvAnswer { records, ttl: Duration::from_secs(ttl) })
+}
+
+fn name(name: &str) -> Vec<u8> {
+    let mut encoded = vec![];
+    for label in name.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// The question of `query`, its name, type and class
+fn question(query: &[u8]) -> &[u8] {
+    let mut end = 12;
+    while query[end] != 0 {
+        end += 1 + query[end] as usize;
+    }
+    &query[12..end + 5]
+}
+
+/// A response to `query` with `question`, the names of the records point to it
+fn response_to(query: &[u8], question: &[u8], records: &[(SrvRecord, u32)], truncated: bool) -> Vec<u8> {
+    let mut message = query[..2].to_vec();
+    message.extend([if truncated { 0x83 } else { 0x81 }, 0x80, 0, 1]);
+    message.extend((records.len() as u16).to_be_bytes());
+    message.extend([0, 0, 0, 0]);
+    message.extend(question);
+    for (record, ttl) in records {
+        let target = name(&record.target);
+        message.extend([0xC0, 12, 0, 33, 0, 1]);
+        message.extend(ttl.to_be_bytes());
+        message.extend(((6 + target.len()) as u16).to_be_bytes());
+        message.extend(record.priority.to_be_bytes());
+        message.extend(record.weight.to_be_bytes());
+        message.extend(record.port.to_be_bytes());
+        message.extend(target);
+    }
+    message
+}
+
+fn response(query: &[u8], records: &[(SrvRecord, u32)]) -> Vec<u8> {
+    response_to(query, question(query), records, false)
+}
+
+#[tokio::test]
+async fn lookup_asks_nameserver() {
+    let nameserver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let lookup = DnsSrvLookup::with_nameserver(nameserver.local_addr().unwrap());
+    let answered = async {
+        let mut query = [0; 512];
+        let (size, client) = nameserver.recv_from(&mut query).await.unwrap();
+        assert_eq!(question(&query[..size]), [name("_minecraft._tcp.example.com"), vec![0, 33, 0, 1]].concat());
+        nameserver.send_to(&response(&query[..size], &[(record(0, 0, 25570, "mc.example.com"), 120)]), client).await.unwrap();
+    };
+    let (answer, _) = tokio::join!(lookup.lookup("_minecraft._tcp.example.com."), answered);
+    let answer = answer.unwrap();
+    assert_eq!(answer.records, [record(0, 0, 25570, "mc.example.com.")]);
+    assert!(answer.ttl <= Duration::from_secs(120) && answer.ttl > Duration::from_secs(100));
+}
+
+#[tokio::test]
+async fn answer_to_another_question_is_ignored() {
+    let nameserver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let lookup = DnsSrvLookup::with_nameserver(nameserver.local_addr().unwrap());
+    let answered = async {
+        let mut query = [0; 512];
+        let (size, client) = nameserver.recv_from(&mut query).await.unwrap();
+        let query = &query[..size];
+        // the right id, but the question of someone else
+        let forged = [name("_minecraft._tcp.evil.com"), vec![0, 33, 0, 1]].concat();
+        nameserver.send_to(&response_to(query, &forged, &[(record(0, 0, 1, "evil.com"), 120)], false), client).await.unwrap();
+        nameserver.send_to(&response(query, &[(record(0, 0, 25570, "mc.example.com"), 120)]), client).await.unwrap();
+    };
+    let (answer, _) = tokio::join!(lookup.lookup("_minecraft._tcp.example.com."), answered);
+    assert_eq!(answer.unwrap().records, [record(0, 0, 25570, "mc.example.com.")]);
+}
+
+#[tokio::test]
+async fn truncated_answer_is_asked_over_tcp() {
+    let nameserver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = nameserver.local_addr().unwrap();
+    let tcp = TcpListener::bind(address).await.unwrap();
+    let lookup = DnsSrvLookup::with_nameserver(address);
+    let answered = async {
+        let mut query = [0; 512];
+        let (size, client) = nameserver.recv_from(&mut query).await.unwrap();
+        let query = &query[..size];
+        nameserver.send_to(&response_to(query, question(query), &[], true), client).await.unwrap();
+
+        let (mut stream, _) = tcp.accept().await.unwrap();
+        let length = stream.read_u16().await.unwrap();
+        let mut query = vec![0; length as usize];
+        stream.read_exact(&mut query).await.unwrap();
+        let message = response(&query, &[
+            (record(0, 0, 25570, "mc1.example.com"), 120),
+            (record(0, 0, 25571, "mc2.example.com"), 120)
+        ]);
+        stream.write_u16(message.len() as u16).await.unwrap();
+        stream.write_all(&message).await.unwrap();
+    };
+    let (answer, _) = tokio::join!(lookup.lookup("_minecraft._tcp.example.com."), answered);
+    assert_eq!(answer.unwrap().records.len(), 2);
+}
+
+#[tokio::test]
+async fn missing_domain_is_not_found() {
+    let nameserver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let lookup = DnsSrvLookup::with_nameserver(nameserver.local_addr().unwrap());
+    let answered = async {
+        let mut query = [0; 512];
+        let (size, client) = nameserver.recv_from(&mut query).await.unwrap();
+        let mut message = response(&query[..size], &[]);
+        message[3] = 0x83;
+        nameserver.send_to(&message, client).await.unwrap();
+    };
+    let (answer, _) = tokio::join!(lookup.lookup("_minecraft._tcp.example.com."), answered);
+    assert_eq!(answer.unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn domain_names() {
+    assert!(is_domain_name("_minecraft._tcp.example.com"));
+    assert!(is_domain_name("_minecraft._tcp.example.com."));
+    assert!(!is_domain_name("_minecraft..example.com"));
+    assert!(!is_domain_name(&"a".repeat(64)));
+    assert!(!is_domain_name(""));
+    assert!(!is_domain_name("."));
+}
+
+#[test]
+fn lowest_priority_is_selected() {
+    let records = [record(10, 100, 1, "backup.example.com"), record(0, 0, 2, "main.example.com")];
+    assert_eq!(select_record(&records, 0.99).unwrap().port, 2);
+    assert_eq!(select_record(&[], 0.5), None);
+}
+
+#[test]
+fn weights_share_the_records() {
+    let records = [record(0, 1, 1, "light.example.com"), record(0, 3, 2, "heavy.example.com"), record(0, 0, 3, "spare.example.com")];
+    assert_eq!(select_record(&records, 0.0).unwrap().port, 1);
+    assert_eq!(select_record(&records, 0.24).unwrap().port, 1);
+    assert_eq!(select_record(&records, 0.25).unwrap().port, 2);
+    assert_eq!(select_record(&records, 0.99).unwrap().port, 2);
+    // without weights each record is as likely
+    let records = [record(0, 0, 1, "a.example.com"), record(0, 0, 2, "b.example.com")];
+    assert_eq!(select_record(&records, 0.49).unwrap().port, 1);
+    assert_eq!(select_record(&records, 0.5).unwrap().port, 2);
+}
+
+#[tokio::test]
+async fn records_of_same_priority_share_players() {
+    let lookup = MockLookup::new(vec![
+        answer(vec![record(10, 100, 1, "backup.example.com."), record(0, 1, 2, "light.example.com."), record(0, 1, 3, "heavy.example.com.")], 60)
+    ]);
+    let cache = SrvCache::new(lookup.clone());
+    let mut targets = HashSet::new();
+    for _ in 0..200 {
+        targets.insert(cache.resolve("_minecraft._tcp.example.com").await.unwrap());
+    }
+    assert_eq!(targets, HashSet::from(["light.example.com:2".to_string(), "heavy.example.com:3".to_string()]));
+    // the records are kept for their TTL
+    assert_eq!(lookup.lookups.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn expired_target_is_looked_up_again() {
+    let lookup = MockLookup::new(vec![
+        answer(vec![record(0, 0, 25565, "old.example.com")], 0),
+        answer(vec![record(0, 0, 25565, "new.example.com")], 0),
+        Err(io::Error::new(io::ErrorKind::TimedOut, "no answer"))
+    ]);
+    let cache = SrvCache::new(lookup.clone());
+    assert_eq!(cache.resolve("_minecraft._tcp.example.com").await.unwrap(), "old.example.com:25565");
+    assert_eq!(cache.resolve("_minecraft._tcp.example.com").await.unwrap(), "new.example.com:25565");
+    // the last target is better than none
+    assert_eq!(cache.resolve("_minecraft._tcp.example.com").await.unwrap(), "new.example.com:25565");
+    assert_eq!(lookup.lookups.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test]
+async fn failed_lookup_without_target() {
+    let lookup = MockLookup::new(vec![
+        Err(io::Error::new(io::ErrorKind::NotFound, "no such domain")),
+        answer(vec![record(0, 0, 0, ".")], 60)
+    ]);
+    let cache = SrvCache::new(lookup);
+    assert_eq!(cache.resolve("_minecraft._tcp.example.com").await.unwrap_err().kind(), io::ErrorKind::NotFound);
+    // the service is explicitly not available
+    assert_eq!(cache.resolve("_minecraft._tcp.example.com").await.unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn client_reaches_srv_target() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = upstream.local_addr().unwrap().port();
+    let lookup = MockLookup::new(vec![answer(vec![record(0, 0, port, "127.0.0.1.")], 60)]);
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass_srv: Some("_minecraft._tcp.backend.example.com".to_string()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let proxy = start_proxy(Proxy::new(config).with_srv_lookup(lookup.clone())).await;
+
+    for _ in 0..2 {
+        let mut client = proxy.connect(&handshake("localhost", 2)).await;
+        let (mut backend, _) = timeout(Duration::from_secs(1), upstream.accept()).await.unwrap().unwrap();
+        assert_eq!(read_handshake(&mut backend).await.domain, "localhost");
+        backend.write_all(b"pong").await.unwrap();
+        let mut reply = [0; 4];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"pong");
+    }
+    assert_eq!(lookup.lookups.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,85 @@
+use std::{process::Command, sync::atomic::Ordering, time::Duration};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    state::UpstreamStats,
+    tests::{harness::{handshake, start_proxy, Harness}, logs::{capture_logs, captured}},
+    Proxy
+};
+
+async fn wait_for<F>(condition: F) where F: Fn() -> bool {
+    timeout(Duration::from_secs(1), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+}
+
+#[tokio::test]
+async fn failures_misses_and_bytes_are_counted() {
+    let harness = Harness::start(&["localhost"]).await;
+    let proxy_pass = harness.upstream.local_addr().unwrap().to_string();
+    let stats = &harness.state.stats;
+
+    // packet id 1 is not a handshake
+    let _client = harness.connect(&[0x01, 0x01]).await;
+    let _client = harness.connect(&handshake("unknown.example.com", 2)).await;
+    wait_for(|| stats.handshake_failures.load(Ordering::Relaxed) == 1 && stats.misses.load(Ordering::Relaxed) == 1).await;
+
+    let mut client = harness.connect(&handshake("localhost", 2)).await;
+    let mut backend = harness.accept().await;
+    let mut received = vec![0; handshake("localhost", 2).len()];
+    backend.read_exact(&mut received).await.unwrap();
+    backend.write_all(b"hello").await.unwrap();
+    let mut hello = [0; 5];
+    client.read_exact(&mut hello).await.unwrap();
+    assert_eq!(stats.upstream(&proxy_pass), UpstreamStats { status_fetches: 0, logins: 1, online: 1 });
+
+    drop(client);
+    wait_for(|| stats.upstream(&proxy_pass).online == 0).await;
+    wait_for(|| stats.bytes_out.load(Ordering::Relaxed) == 5).await;
+    assert_eq!(stats.bytes_in.load(Ordering::Relaxed), received.len() as u64);
+}
+
+#[tokio::test]
+async fn named_servers_are_counted_apart() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_pass = upstream.local_addr().unwrap().to_string();
+    let server = |name: &str, domain: &str| MinecraftServerDescription {
+        listen: "127.0.0.1:0".into(),
+        server_names: vec![domain.to_string()],
+        proxy_pass: proxy_pass.clone(),
+        name: Some(name.to_string()),
+        ..Default::default()
+    };
+    let config = MineginxConfig {
+        servers: vec![server("eu-lobby", "eu.localhost"), server("us-lobby", "us.localhost")],
+        ..Default::default()
+    };
+    let proxy = start_proxy(Proxy::new(config)).await;
+    let state = &proxy.state;
+
+    let _client = proxy.connect(&handshake("eu.localhost", 2)).await;
+    let _backend = upstream.accept().await.unwrap();
+    let eu = format!("eu-lobby ({proxy_pass})");
+    wait_for(|| state.stats.upstream(&eu).online == 1).await;
+    assert_eq!(state.stats.upstream(&format!("us-lobby ({proxy_pass})")), UpstreamStats::default());
+    assert!(state.summary().ends_with(&format!(" online[{eu}]=1 status_fetches[{eu}]=0 logins[{eu}]=1")), "{}", state.summary());
+}
+
+#[tokio::test]
+async fn user_defined_signal_logs_stats() {
+    capture_logs();
+    let harness = Harness::start(&["localhost"]).await;
+    let proxy_pass = harness.upstream.local_addr().unwrap().to_string();
+    let _client = harness.connect(&handshake("localhost", 2)).await;
+    let _backend = harness.accept().await;
+    wait_for(|| harness.state.stats.upstream(&proxy_pass).online == 1).await;
+
+    let status = Command::new("kill").args(["-USR1", &std::process::id().to_string()]).status().unwrap();
+    assert!(status.success());
+    let expected = format!(" online[{proxy_pass}]=1 status_fetches[{proxy_pass}]=0 logins[{proxy_pass}]=1");
+    wait_for(|| captured(&expected).iter().any(|x| x.starts_with("INFO stats: sessions="))).await;
+}
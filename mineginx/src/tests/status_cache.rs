@@ -0,0 +1,132 @@
+use std::{borrow::BorrowMut, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+use minecraft::{
+    packets::{HandshakeC2SPacket, PingRequestC2SPacket, PongResponseS2CPacket, StatusRequestC2SPacket, StatusResponseS2CPacket},
+    serialization::MinecraftStream
+};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    status_cache::{StatusCache, StatusKey, MAX_STATUS_CACHE_ENTRIES},
+    tests::harness::{start_proxy, RunningProxy},
+    Proxy
+};
+
+/// Answers every status request with the number of the connection, like `fetch 1`
+async fn start_upstream() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let counter = fetches.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let fetch = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::spawn(async move {
+                let mut upstream = MinecraftStream::new(socket.borrow_mut(), 4096);
+                let handshake = upstream.read_packet::<HandshakeC2SPacket>().await.unwrap();
+                assert_eq!(handshake.next_state, 1);
+                upstream.read_packet::<StatusRequestC2SPacket>().await.unwrap();
+                let json_response = format!(r#"{{"description":{{"text":"fetch {fetch} for {}"}}}}"#, handshake.protocol_version);
+                upstream.write_packet_with_id(0, &StatusResponseS2CPacket { json_response }).await.unwrap();
+            });
+        }
+    });
+    (address, fetches)
+}
+
+async fn start_cached(status_cache_ttl_ms: Option<u64>) -> (RunningProxy, Arc<AtomicUsize>) {
+    let (upstream, fetches) = start_upstream().await;
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "127.0.0.1:0".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: upstream,
+            status_cache_ttl_ms,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    (start_proxy(Proxy::new(config)).await, fetches)
+}
+
+/// The whole server list ping, returns the description the client sees
+async fn ping(address: &str, protocol_version: i32) -> String {
+    let mut client = TcpStream::connect(address).await.unwrap();
+    let mut minecraft = MinecraftStream::new(client.borrow_mut(), 4096);
+    minecraft.write_packet_with_id(0, &HandshakeC2SPacket {
+        protocol_version,
+        domain: "localhost".to_string(),
+        server_port: 25565,
+        next_state: 1
+    }).await.unwrap();
+    minecraft.write_packet_with_id(0, &StatusRequestC2SPacket {}).await.unwrap();
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 0);
+    let status = minecraft.read_data::<StatusResponseS2CPacket>(signature).await.unwrap();
+
+    minecraft.write_packet_with_id(1, &PingRequestC2SPacket { payload: 42 }).await.unwrap();
+    let signature = minecraft.read_signature().await.unwrap();
+    assert_eq!(signature.packet_id, 1);
+    let pong = minecraft.read_data::<PongResponseS2CPacket>(signature).await.unwrap();
+    assert_eq!(pong.payload, 42);
+
+    let json: serde_json::Value = serde_json::from_str(&status.json_response).unwrap();
+    json["description"]["text"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn miss_fetches_and_hit_is_answered_locally() {
+    let (proxy, fetches) = start_cached(Some(60_000)).await;
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 1 for 765");
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 1 for 765");
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 1 for 765");
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn other_protocol_version_is_a_miss() {
+    let (proxy, fetches) = start_cached(Some(60_000)).await;
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 1 for 765");
+    assert_eq!(ping(&proxy.address, 766).await, "fetch 2 for 766");
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 1 for 765");
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn expired_status_is_fetched_again() {
+    let (proxy, fetches) = start_cached(Some(100)).await;
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 1 for 765");
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 2 for 765");
+    assert_eq!(ping(&proxy.address, 765).await, "fetch 2 for 765");
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn cache_entries_expire() {
+    let cache = StatusCache::default();
+    let key = |domain: &str| StatusKey { proxy_pass: "127.0.0.1:7878".to_string(), domain: domain.to_string(), protocol_version: 765 };
+    assert_eq!(cache.get(&key("a.example.com")), None);
+    cache.insert(key("a.example.com"), "a".to_string(), Duration::from_secs(60));
+    cache.insert(key("b.example.com"), "b".to_string(), Duration::ZERO);
+    assert_eq!(cache.get(&key("a.example.com")), Some("a".to_string()));
+    assert_eq!(cache.get(&key("b.example.com")), None);
+}
+
+#[test]
+fn oldest_entry_makes_room() {
+    let cache = StatusCache::default();
+    let key = |domain: String| StatusKey { proxy_pass: "127.0.0.1:7878".to_string(), domain, protocol_version: 765 };
+    for i in 0..MAX_STATUS_CACHE_ENTRIES {
+        cache.insert(key(format!("{i}.example.com")), i.to_string(), Duration::from_secs(60 + i as u64));
+    }
+    cache.insert(key("0.example.com".to_string()), "again".to_string(), Duration::from_secs(3600));
+    assert_eq!(cache.len(), MAX_STATUS_CACHE_ENTRIES);
+    cache.insert(key("scanner.example.com".to_string()), "new".to_string(), Duration::from_secs(60));
+    assert_eq!(cache.len(), MAX_STATUS_CACHE_ENTRIES);
+    assert_eq!(cache.get(&key("1.example.com".to_string())), None);
+    assert_eq!(cache.get(&key("0.example.com".to_string())), Some("again".to_string()));
+    assert_eq!(cache.get(&key("scanner.example.com".to_string())), Some("new".to_string()));
+}
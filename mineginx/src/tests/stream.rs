@@ -0,0 +1,309 @@
+use std::{io, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll}, time::Duration};
+
+use tokio::{io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf}, net::TcpListener, sync::oneshot, time::timeout};
+
+use crate::{
+    config::{MinecraftServerDescription, MineginxConfig},
+    serve_stream,
+    state::State,
+    stream::{forward_stream, AdaptiveBuffer, Closed, Forwarded, SharedStream, MAX_FORWARD_BUFFER_SIZE},
+    tests::harness::handshake,
+    throttle::TokenBucket
+};
+
+#[tokio::test]
+async fn bytes_flow_to_writer() {
+    let (mut client, reader) = duplex(64);
+    let (writer, mut upstream) = duplex(64);
+    let (close, _closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    let data: Vec<u8> = (0..200).collect();
+    let mut received = vec![0; data.len()];
+    let (written, read) = tokio::join!(client.write_all(&data), upstream.read_exact(&mut received));
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(received, data);
+}
+
+#[tokio::test]
+async fn reader_eof_signals_close() {
+    let (client, reader) = duplex(64);
+    let (writer, mut upstream) = duplex(64);
+    let (close, closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    drop(client);
+    assert!(timeout(Duration::from_secs(1), closed).await.unwrap().is_ok());
+    timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    let mut rest = Vec::new();
+    assert_eq!(upstream.read_to_end(&mut rest).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn stops_when_other_side_closes() {
+    let (_client, reader) = duplex(64);
+    let (writer, _upstream) = duplex(64);
+    let (close, _closed) = oneshot::channel();
+    let (close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    // the reader is idle, so only the signal can stop the task
+    close_other.send(()).unwrap();
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    assert_eq!(forwarded.closed, Closed::ByOther);
+}
+
+#[tokio::test]
+async fn eof_of_one_direction_closes_idle_other() {
+    let (client, client_proxy) = duplex(64);
+    let (upstream_proxy, _upstream) = duplex(64);
+    let (client_reader, client_writer) = tokio::io::split(client_proxy);
+    let (upstream_reader, upstream_writer) = tokio::io::split(upstream_proxy);
+    let (client_close, client_closed) = oneshot::channel();
+    let (upstream_close, upstream_closed) = oneshot::channel();
+    let sent = forward_stream(client_close, upstream_closed, client_reader, upstream_writer, 16, None);
+    let received = forward_stream(upstream_close, client_closed, upstream_reader, client_writer, 16, None);
+
+    // the upstream never sends anything, so only the client's eof can stop that direction
+    drop(client);
+    let (sent, received) = timeout(Duration::from_secs(1), async { tokio::join!(sent, received) }).await.unwrap();
+    assert_eq!(sent.unwrap().closed, Closed::Eof);
+    assert_eq!(received.unwrap().closed, Closed::ByOther);
+}
+
+#[tokio::test]
+async fn stops_while_writer_is_full() {
+    let (mut client, reader) = duplex(64);
+    let (writer, _upstream) = duplex(8);
+    let (close, _closed) = oneshot::channel();
+    let (close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    // nobody reads the upstream, so the task waits in the write
+    client.write_all(&[0; 32]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    close_other.send(()).unwrap();
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    assert_eq!(forwarded.closed, Closed::ByOther);
+}
+
+#[tokio::test]
+async fn stops_while_throttled() {
+    let (mut client, reader) = duplex(64);
+    let (writer, _upstream) = duplex(64);
+    let (close, _closed) = oneshot::channel();
+    let (close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 64, Some(Arc::new(Mutex::new(TokenBucket::new(1)))));
+
+    client.write_all(&[0; 32]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    close_other.send(()).unwrap();
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    assert_eq!(forwarded.closed, Closed::ByOther);
+}
+
+/// Fails every read like a connection reset by the peer
+struct ResetReader;
+
+impl AsyncRead for ResetReader {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+}
+
+#[tokio::test]
+async fn eof_and_error_are_distinguished() {
+    let (client, reader) = duplex(64);
+    let (writer, _upstream) = duplex(64);
+    let (close, _closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+    drop(client);
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    assert_eq!(forwarded, Forwarded { bytes: 0, closed: Closed::Eof });
+    assert_eq!(forwarded.closed.to_string(), "eof");
+
+    let (writer, _upstream) = duplex(64);
+    let (close, closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, ResetReader, writer, 16, None);
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    assert_eq!(forwarded.closed, Closed::ReadError(io::ErrorKind::ConnectionReset));
+    assert_eq!(forwarded.closed.to_string(), "read error: connection reset");
+    assert!(closed.await.is_ok());
+}
+
+#[tokio::test]
+async fn slow_writer_gets_everything() {
+    let (mut client, reader) = duplex(64);
+    let (writer, mut upstream) = duplex(4);
+    let (close, closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    let data: Vec<u8> = (0..=255).cycle().take(1024).collect();
+    let sending = data.clone();
+    tokio::spawn(async move {
+        client.write_all(&sending).await.unwrap();
+    });
+    // the upstream reads less than the buffer size and pauses, so the forwarder has to wait for it
+    let mut received: Vec<u8> = vec![];
+    let mut chunk = [0; 64];
+    while received.len() < data.len() {
+        let size = upstream.read(&mut chunk).await.unwrap();
+        received.extend(&chunk[..size]);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert_eq!(received, data);
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    assert_eq!(forwarded, Forwarded { bytes: 1024, closed: Closed::Eof });
+    assert!(closed.await.is_ok());
+}
+
+/// Takes up to `limit` bytes per write, then fails every write
+struct BreakingWriter {
+    written: Arc<Mutex<Vec<u8>>>,
+    limit: usize
+}
+
+impl AsyncWrite for BreakingWriter {
+    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.limit == 0 {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let size = buf.len().min(3).min(self.limit);
+        self.limit -= size;
+        self.written.lock().unwrap().extend(&buf[..size]);
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn write_error_in_the_middle_signals_close() {
+    let (mut client, reader) = duplex(64);
+    let written = Arc::new(Mutex::new(vec![]));
+    let writer = BreakingWriter { written: written.clone(), limit: 10 };
+    let (close, closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    let data: Vec<u8> = (0..16).collect();
+    client.write_all(&data).await.unwrap();
+    assert!(timeout(Duration::from_secs(1), closed).await.unwrap().is_ok());
+    let forwarded = timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+    // the bytes of the partial writes before the error are counted
+    assert_eq!(forwarded, Forwarded { bytes: 10, closed: Closed::WriteError(io::ErrorKind::BrokenPipe) });
+    assert_eq!(*written.lock().unwrap(), data[..10]);
+}
+
+/// An in-memory client stands for a TLS stream, anything readable and writable is proxied the same way
+#[tokio::test]
+async fn session_over_generic_stream() {
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = MineginxConfig {
+        servers: vec![MinecraftServerDescription {
+            listen: "tls:25565".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: upstream.local_addr().unwrap().to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let state = Arc::new(State::new(Arc::new(config)));
+    let (mut client, proxied) = duplex(1024);
+    serve_stream(SharedStream(proxied), &state, "tls:25565", Some("1.2.3.4:51000".parse().unwrap()));
+
+    let handshake = handshake("localhost", 2);
+    client.write_all(&[handshake.as_slice(), b"login"].concat()).await.unwrap();
+    let (mut backend, _) = upstream.accept().await.unwrap();
+    let mut received = vec![0; handshake.len() + 5];
+    backend.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, [handshake.as_slice(), b"login"].concat());
+
+    backend.write_all(b"welcome").await.unwrap();
+    let mut welcome = [0; 7];
+    client.read_exact(&mut welcome).await.unwrap();
+    assert_eq!(&welcome, b"welcome");
+
+    // the client's eof closes the upstream too
+    drop(client);
+    let mut rest = vec![];
+    assert_eq!(timeout(Duration::from_secs(1), backend.read_to_end(&mut rest)).await.unwrap().unwrap(), 0);
+}
+
+#[test]
+fn adaptive_buffer_grows_to_cap_with_full_reads() {
+    let mut buf = AdaptiveBuffer::new(2048, 16 * 1024);
+    let mut sizes = vec![];
+    for _ in 0..20 {
+        let size = buf.capacity();
+        buf.record(size);
+        sizes.push(buf.capacity());
+    }
+    assert_eq!(buf.capacity(), 16 * 1024);
+    // doubles once per 4 full reads and never goes past the cap
+    assert_eq!(sizes[..12], [2048, 2048, 2048, 4096, 4096, 4096, 4096, 8192, 8192, 8192, 8192, 16384]);
+    assert!(sizes.iter().all(|&x| x <= 16 * 1024));
+}
+
+#[test]
+fn adaptive_buffer_shrinks_to_base_with_small_reads() {
+    let mut buf = AdaptiveBuffer::new(2048, 16 * 1024);
+    while buf.capacity() < 16 * 1024 {
+        let size = buf.capacity();
+        buf.record(size);
+    }
+    for _ in 0..63 {
+        buf.record(100);
+    }
+    assert_eq!(buf.capacity(), 16 * 1024);
+    for _ in 0..1000 {
+        buf.record(100);
+    }
+    assert_eq!(buf.capacity(), 2048);
+}
+
+#[test]
+fn adaptive_buffer_keeps_size_for_mixed_reads() {
+    let mut buf = AdaptiveBuffer::new(2048, 16 * 1024);
+    for _ in 0..100 {
+        buf.record(2048);
+        buf.record(1500);
+    }
+    assert_eq!(buf.capacity(), 2048);
+    // the base is kept when it is already above the cap
+    let mut buf = AdaptiveBuffer::new(4096, 1024);
+    for _ in 0..10 {
+        buf.record(4096);
+    }
+    assert_eq!(buf.capacity(), 4096);
+}
+
+#[tokio::test]
+async fn bulk_transfer_through_growing_buffer_is_intact() {
+    let (mut client, reader) = duplex(MAX_FORWARD_BUFFER_SIZE);
+    let (writer, mut upstream) = duplex(MAX_FORWARD_BUFFER_SIZE);
+    let (close, _closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let task = forward_stream(close, close_by_other, reader, writer, 16, None);
+
+    let data: Vec<u8> = (0..256 * 1024).map(|x| x as u8).collect();
+    let mut received = vec![0; data.len()];
+    let (written, read) = tokio::join!(client.write_all(&data), upstream.read_exact(&mut received));
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(received, data);
+    drop(client);
+    assert_eq!(timeout(Duration::from_secs(1), task).await.unwrap().unwrap().bytes, data.len() as u64);
+}
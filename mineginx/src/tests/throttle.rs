@@ -0,0 +1,60 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use tokio::{io::{duplex, AsyncReadExt, AsyncWriteExt}, sync::oneshot};
+
+use crate::{stream::forward_stream, throttle::{Bandwidth, TokenBucket}};
+
+#[tokio::test]
+async fn throughput_stays_near_limit() {
+    let mut bucket = TokenBucket::new(100_000);
+    let started = Instant::now();
+    for _ in 0..25 {
+        bucket.consume(2048).await;
+    }
+    // 51200 bytes, the first 10000 are the initial burst
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(380), "{elapsed:?}");
+    assert!(elapsed < Duration::from_millis(700), "{elapsed:?}");
+}
+
+#[tokio::test]
+async fn burst_without_waiting() {
+    let mut bucket = TokenBucket::new(1_000_000);
+    let started = Instant::now();
+    bucket.consume(100_000).await;
+    assert!(started.elapsed() < Duration::from_millis(50));
+}
+
+/// Forwards 25600 bytes through the bucket of the server to the upstream
+async fn upload(bandwidth: &Bandwidth) {
+    let (mut client, reader) = duplex(64 * 1024);
+    let (writer, mut upstream) = duplex(64 * 1024);
+    let (close, _closed) = oneshot::channel();
+    let (_close_other, close_by_other) = oneshot::channel();
+    let (sent, _) = bandwidth.buckets("lobby (127.0.0.1:7878)", 100_000);
+    let _task = forward_stream(close, close_by_other, reader, writer, 2048, Some(sent));
+    client.write_all(&[0; 25_600]).await.unwrap();
+    let mut received = vec![0; 25_600];
+    upstream.read_exact(&mut received).await.unwrap();
+}
+
+#[tokio::test]
+async fn connections_of_server_share_limit() {
+    let bandwidth = Bandwidth::default();
+    let started = Instant::now();
+    tokio::join!(upload(&bandwidth), upload(&bandwidth));
+    // 51200 bytes together, the first 10000 are the initial burst, alone each would take about 160ms
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(380), "{elapsed:?}");
+    assert!(elapsed < Duration::from_millis(700), "{elapsed:?}");
+}
+
+#[test]
+fn buckets_follow_reload() {
+    let bandwidth = Bandwidth::default();
+    let (sent, received) = bandwidth.buckets("127.0.0.1:7878", 100_000);
+    assert!(!Arc::ptr_eq(&sent, &received));
+    assert!(Arc::ptr_eq(&sent, &bandwidth.buckets("127.0.0.1:7878", 100_000).0));
+    assert!(!Arc::ptr_eq(&sent, &bandwidth.buckets("127.0.0.2:7878", 100_000).0));
+    assert!(!Arc::ptr_eq(&sent, &bandwidth.buckets("127.0.0.1:7878", 200_000).0));
+}
@@ -0,0 +1,268 @@
+use std::{io, net::SocketAddr, time::{Duration, Instant}};
+
+use socket2::SockRef;
+use tokio::{io::{duplex, AsyncReadExt}, net::{TcpListener, TcpSocket, TcpStream}, time::sleep};
+
+use crate::{
+    config::{AddressFamily, MinecraftServerDescription, MineginxConfig},
+    upstream::{connect_parallel, connect_upstream, prefer_family, write_upstream, ConnectOptions}
+};
+
+#[tokio::test]
+async fn connect_to_listening_upstream() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    assert!(connect_upstream(&address, &options(1000, 0, 0)).await.is_ok());
+}
+
+#[tokio::test]
+async fn connect_to_blackholed_upstream() {
+    let (_listener, _queued, address) = blackholed_upstream().await;
+    let started = Instant::now();
+    let result = connect_upstream(&address, &options(200, 0, 0)).await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn connect_without_retries() {
+    let address = free_address().await;
+    let result = connect_upstream(&address, &options(1000, 0, 0)).await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+}
+
+#[tokio::test]
+async fn retry_until_upstream_is_up() {
+    let address = free_address().await;
+    let upstream_address = address.clone();
+    let upstream = tokio::spawn(async move {
+        sleep(Duration::from_millis(100)).await;
+        let listener = TcpListener::bind(upstream_address).await.unwrap();
+        listener.accept().await.unwrap();
+    });
+    assert!(connect_upstream(&address, &options(5000, 5, 50)).await.is_ok());
+    upstream.await.unwrap();
+}
+
+#[tokio::test]
+async fn server_retries_until_upstream_is_up() {
+    let address = free_address().await;
+    let config = MineginxConfig {
+        upstream_connect_retries: Some(0),
+        servers: vec![MinecraftServerDescription {
+            listen: "0.0.0.0:25565".into(),
+            server_names: vec!["localhost".to_string()],
+            proxy_pass: address.clone(),
+            connect_retries: Some(5),
+            connect_retry_delay_ms: Some(50),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let upstream_address = address.clone();
+    let upstream = tokio::spawn(async move {
+        sleep(Duration::from_millis(80)).await;
+        let listener = TcpListener::bind(upstream_address).await.unwrap();
+        listener.accept().await.unwrap();
+    });
+    assert!(connect_upstream(&address, &config.connect_options(&config.servers[0])).await.is_ok());
+    upstream.await.unwrap();
+}
+
+#[tokio::test]
+async fn resolved_addresses_are_used() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let options = ConnectOptions {
+        addresses: Some(vec![listener.local_addr().unwrap()]),
+        ..options(1000, 0, 0)
+    };
+    assert!(connect_upstream("backend.invalid:25565", &options).await.is_ok());
+}
+
+#[test]
+fn preferred_family_goes_first() {
+    let v4: SocketAddr = "127.0.0.1:25565".parse().unwrap();
+    let v6: SocketAddr = "[::1]:25565".parse().unwrap();
+    assert_eq!(prefer_family(vec![v4, v6], AddressFamily::Ipv6), [v6, v4]);
+    assert_eq!(prefer_family(vec![v6, v4], AddressFamily::Ipv4), [v4, v6]);
+    assert_eq!(prefer_family(vec![v6, v4], AddressFamily::Auto), [v6, v4]);
+}
+
+#[tokio::test]
+async fn connect_preferred_family() {
+    let v4 = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = v4.local_addr().unwrap().port();
+    let v6 = TcpListener::bind(("::1", port)).await.unwrap();
+    // both families of one dual-stack name
+    let addresses = vec![v4.local_addr().unwrap(), v6.local_addr().unwrap()];
+    for (family, ipv6) in [(AddressFamily::Ipv6, true), (AddressFamily::Ipv4, false)] {
+        let options = ConnectOptions {
+            address_family: family,
+            addresses: Some(addresses.clone()),
+            ..options(1000, 0, 0)
+        };
+        let stream = connect_upstream("dual.invalid:25565", &options).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap().is_ipv6(), ipv6);
+    }
+}
+
+#[tokio::test]
+async fn fallback_to_other_family() {
+    let v4 = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = v4.local_addr().unwrap().port();
+    let options = ConnectOptions {
+        address_family: AddressFamily::Ipv6,
+        addresses: Some(vec![v4.local_addr().unwrap(), SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port))]),
+        ..options(1000, 0, 0)
+    };
+    let stream = connect_upstream("dual.invalid:25565", &options).await.unwrap();
+    assert!(stream.peer_addr().unwrap().is_ipv4());
+}
+
+#[tokio::test]
+async fn second_address_wins_over_hanging_one() {
+    let (_blackholed, _queued, hanging) = blackholed_upstream().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addresses = [hanging.parse().unwrap(), listener.local_addr().unwrap()];
+    let started = Instant::now();
+    let stream = connect_parallel(&addresses, Duration::from_millis(50)).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn refused_address_starts_next_at_once() {
+    let refused: SocketAddr = free_address().await.parse().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let started = Instant::now();
+    let stream = connect_parallel(&[refused, listener.local_addr().unwrap()], Duration::from_secs(10)).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn all_addresses_fail() {
+    let first: SocketAddr = free_address().await.parse().unwrap();
+    let second: SocketAddr = free_address().await.parse().unwrap();
+    let result = connect_parallel(&[first, second], Duration::from_millis(50)).await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+    assert_eq!(connect_parallel(&[], Duration::from_millis(50)).await.unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn upstream_skips_hanging_address() {
+    let (_blackholed, _queued, hanging) = blackholed_upstream().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let options = ConnectOptions {
+        addresses: Some(vec![hanging.parse().unwrap(), listener.local_addr().unwrap()]),
+        ..options(5000, 0, 0)
+    };
+    let started = Instant::now();
+    assert!(connect_upstream("backend.invalid:25565", &options).await.is_ok());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn nodelay_follows_config() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = MinecraftServerDescription {
+        proxy_pass: listener.local_addr().unwrap().to_string(),
+        ..Default::default()
+    };
+    for tcp_nodelay in [None, Some(false), Some(true)] {
+        let config = MineginxConfig { tcp_nodelay, ..Default::default() };
+        let stream = connect_upstream(&server.proxy_pass, &config.connect_options(&server)).await.unwrap();
+        assert_eq!(stream.nodelay().unwrap(), tcp_nodelay.unwrap_or(true));
+    }
+}
+
+#[tokio::test]
+async fn server_nodelay_overrides_global() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = MineginxConfig { tcp_nodelay: Some(true), ..Default::default() };
+    for upstream_tcp_nodelay in [Some(false), Some(true)] {
+        let server = MinecraftServerDescription {
+            proxy_pass: listener.local_addr().unwrap().to_string(),
+            upstream_tcp_nodelay,
+            ..Default::default()
+        };
+        let stream = connect_upstream(&server.proxy_pass, &config.connect_options(&server)).await.unwrap();
+        assert_eq!(stream.nodelay().unwrap(), upstream_tcp_nodelay.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn buffer_sizes_are_applied() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = MinecraftServerDescription {
+        proxy_pass: listener.local_addr().unwrap().to_string(),
+        upstream_send_buffer_size: Some(32768),
+        upstream_recv_buffer_size: Some(49152),
+        ..Default::default()
+    };
+    let stream = connect_upstream(&server.proxy_pass, &MineginxConfig::default().connect_options(&server)).await.unwrap();
+    // linux doubles the requested sizes for its bookkeeping
+    let socket = SockRef::from(&stream);
+    assert!((32768..=65536).contains(&socket.send_buffer_size().unwrap()));
+    assert!((49152..=98304).contains(&socket.recv_buffer_size().unwrap()));
+}
+
+#[tokio::test]
+async fn write_to_non_reading_upstream() {
+    let (mut upstream, _backend) = duplex(64);
+    let started = Instant::now();
+    let result = write_upstream(&mut upstream, &[0; 1024], Duration::from_millis(100)).await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn write_to_reading_upstream() {
+    let (mut upstream, mut backend) = duplex(64);
+    let reading = tokio::spawn(async move {
+        let mut received = vec![0; 1024];
+        backend.read_exact(&mut received).await.unwrap();
+    });
+    write_upstream(&mut upstream, &[0; 1024], Duration::from_secs(1)).await.unwrap();
+    reading.await.unwrap();
+}
+
+#[tokio::test]
+async fn retries_are_bounded_by_timeout() {
+    let address = free_address().await;
+    let started = Instant::now();
+    let result = connect_upstream(&address, &options(300, 100, 50)).await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+fn options(timeout_ms: u64, retries: u32, retry_backoff_ms: u64) -> ConnectOptions {
+    ConnectOptions {
+        timeout: Duration::from_millis(timeout_ms),
+        retries,
+        retry_backoff: Duration::from_millis(retry_backoff_ms),
+        address_family: AddressFamily::Auto,
+        addresses: None,
+        nodelay: true,
+        send_buffer_size: None,
+        recv_buffer_size: None
+    }
+}
+
+/// An address nobody listens to right now
+pub async fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// A listener with a full accept queue, new connections hang in SYN_SENT like with a blackholed host  
+/// Keep the returned sockets alive during the test
+pub async fn blackholed_upstream() -> (TcpListener, TcpStream, String) {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let queued = TcpStream::connect(&address).await.unwrap();
+    (listener, queued, address)
+}
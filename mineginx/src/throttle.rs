@@ -0,0 +1,71 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use tokio::time::sleep;
+
+/// Token bucket limiting the bytes per second  
+/// Holds at most a tenth of a second of traffic, so bursts stay small
+pub struct TokenBucket {
+    bytes_per_second: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant
+}
+
+/// A bucket taken by every connection of a server, so together they stay within its limit
+pub type SharedBucket = Arc<Mutex<TokenBucket>>;
+
+impl TokenBucket {
+    pub fn new(bytes_per_second: u64) -> TokenBucket {
+        let bytes_per_second = bytes_per_second as f64;
+        let capacity = bytes_per_second / 10.0;
+        TokenBucket {
+            bytes_per_second,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now()
+        }
+    }
+
+    /// Takes `bytes` from the bucket, sleeping until they are refilled if the bucket goes into debt
+    pub async fn consume(&mut self, bytes: usize) {
+        sleep(self.take(bytes)).await;
+    }
+
+    /// Takes `bytes` from the bucket and returns how long to wait until the debt is refilled  
+    /// The debt of the others is waited for too, so a shared bucket is not drained faster by more connections
+    pub fn take(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.bytes_per_second;
+        self.tokens = (self.tokens + refilled).min(self.capacity) - bytes as f64;
+        self.updated = now;
+        Duration::from_secs_f64((-self.tokens).max(0.0) / self.bytes_per_second)
+    }
+}
+
+/// Takes `bytes` from a bucket which other connections use too, the lock isn't held while sleeping
+pub async fn consume_shared(bucket: &SharedBucket, bytes: usize) {
+    let wait = bucket.lock().unwrap().take(bytes);
+    if !wait.is_zero() {
+        sleep(wait).await;
+    }
+}
+
+/// The buckets of `max_bandwidth_bytes_per_sec`, one for each direction of each server by its `label`
+#[derive(Default)]
+pub struct Bandwidth {
+    servers: Mutex<HashMap<String, (u64, SharedBucket, SharedBucket)>>
+}
+
+impl Bandwidth {
+    /// The buckets of the traffic to the upstream and from it  
+    /// New ones are made once a reload changes `bytes_per_second` of the server
+    pub fn buckets(&self, label: &str, bytes_per_second: u64) -> (SharedBucket, SharedBucket) {
+        let mut servers = self.servers.lock().unwrap();
+        let new = || (bytes_per_second, Arc::new(Mutex::new(TokenBucket::new(bytes_per_second))), Arc::new(Mutex::new(TokenBucket::new(bytes_per_second))));
+        let server = servers.entry(label.to_string()).or_insert_with(new);
+        if server.0 != bytes_per_second {
+            *server = new();
+        }
+        (server.1.clone(), server.2.clone())
+    }
+}
@@ -0,0 +1,128 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use log::debug;
+use socket2::SockRef;
+use tokio::{io::{AsyncWrite, AsyncWriteExt}, net::{lookup_host, TcpStream}, task::JoinSet, time::{sleep, timeout}};
+
+use crate::config::AddressFamily;
+
+pub struct ConnectOptions {
+    /// Bounds all attempts together, including the pauses between them
+    pub timeout: Duration,
+    pub retries: u32,
+    /// The pause after the first failed attempt, doubled after each next one
+    pub retry_backoff: Duration,
+    pub address_family: AddressFamily,
+    /// Already resolved addresses of the upstream, used instead of resolving it again
+    pub addresses: Option<Vec<SocketAddr>>,
+    /// `TCP_NODELAY` of the established connection
+    pub nodelay: bool,
+    /// `SO_SNDBUF` of the established connection, `None` keeps the system default
+    pub send_buffer_size: Option<u32>,
+    /// `SO_RCVBUF` of the established connection, `None` keeps the system default
+    pub recv_buffer_size: Option<u32>
+}
+
+/// How long an address may be connecting before the next one is tried in parallel
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Like `TcpStream::connect`, but retries failed attempts and gives up with `TimedOut` after `options.timeout`
+pub async fn connect_upstream(address: &str, options: &ConnectOptions) -> io::Result<TcpStream> {
+    let attempts = async {
+        let mut backoff = options.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            let connected = match resolve(address, options).await {
+                Ok(addresses) => connect_parallel(&addresses, ATTEMPT_DELAY).await,
+                Err(err) => Err(err)
+            };
+            match connected {
+                Ok(x) => {
+                    x.set_nodelay(options.nodelay)?;
+                    set_buffer_sizes(&x, options)?;
+                    return Ok(x);
+                },
+                Err(err) if attempt >= options.retries => return Err(err),
+                Err(err) => {
+                    attempt += 1;
+                    debug!("failed to connect upstream: {address}, retry {attempt}/{} in {}ms: {err}", options.retries, backoff.as_millis());
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+    };
+    match timeout(options.timeout, attempts).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, format!("connection is not established in {}ms", options.timeout.as_millis())))
+    }
+}
+
+fn set_buffer_sizes(stream: &TcpStream, options: &ConnectOptions) -> io::Result<()> {
+    let socket = SockRef::from(stream);
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size as usize)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size as usize)?;
+    }
+    Ok(())
+}
+
+async fn resolve(address: &str, options: &ConnectOptions) -> io::Result<Vec<SocketAddr>> {
+    let addresses = match &options.addresses {
+        Some(addresses) => addresses.clone(),
+        None => lookup_host(address).await?.collect()
+    };
+    Ok(prefer_family(addresses, options.address_family))
+}
+
+/// Connects to `addresses` in order, but doesn't wait for an address longer than `delay` before trying the next one  
+/// The first established connection wins and the others are dropped, like happy eyeballs in RFC 6555
+pub async fn connect_parallel(addresses: &[SocketAddr], delay: Duration) -> io::Result<TcpStream> {
+    let mut attempts = JoinSet::new();
+    let mut next = 0;
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "there are no addresses to connect");
+    loop {
+        if attempts.is_empty() {
+            match addresses.get(next) {
+                Some(&address) => {
+                    attempts.spawn(TcpStream::connect(address));
+                    next += 1;
+                },
+                None => return Err(last_error)
+            }
+        }
+        let more = next < addresses.len();
+        tokio::select! {
+            finished = attempts.join_next() => match finished {
+                Some(Ok(Ok(stream))) => return Ok(stream),
+                Some(Ok(Err(err))) => last_error = err,
+                Some(Err(err)) => last_error = io::Error::other(err),
+                None => {}
+            },
+            _ = sleep(delay), if more => {
+                attempts.spawn(TcpStream::connect(addresses[next]));
+                next += 1;
+            }
+        }
+    }
+}
+
+/// Moves addresses of the preferred family to the front, keeping the resolver order otherwise
+pub fn prefer_family(mut addresses: Vec<SocketAddr>, family: AddressFamily) -> Vec<SocketAddr> {
+    match family {
+        AddressFamily::Auto => {},
+        AddressFamily::Ipv4 => addresses.sort_by_key(|x| !x.is_ipv4()),
+        AddressFamily::Ipv6 => addresses.sort_by_key(|x| !x.is_ipv6())
+    }
+    addresses
+}
+
+/// `write_all` which gives up with `TimedOut` if the upstream doesn't take `data` in time
+pub async fn write_upstream<W>(upstream: &mut W, data: &[u8], write_timeout: Duration) -> io::Result<()> where W: AsyncWrite + Unpin {
+    match timeout(write_timeout, upstream.write_all(data)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, format!("data is not written in {}ms", write_timeout.as_millis())))
+    }
+}